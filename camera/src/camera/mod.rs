@@ -111,6 +111,10 @@ pub struct Camera {
     #[get = "pub"]
     #[set = "pub"]
     target: Vector3<f32>,
+
+    /// When flat the camera looks straight down the z axis, and both buttons pan
+    #[get = "pub"]
+    flat: bool,
 }
 
 impl Camera {
@@ -156,6 +160,7 @@ impl Camera {
             near: 0.01,
             far: 1000.0,
             scroll_modifier: 1.0 / 200.0,
+            flat: false,
         }
     }
 
@@ -232,6 +237,19 @@ impl Camera {
         self.start_transition(target, rotation, distance, duration);
     }
 
+    /// Switch to or from viewing the xy plane head on, for plots that lie in it
+    pub fn set_flat(&mut self, flat: bool) {
+        self.flat = flat;
+        if flat {
+            let target = self.target;
+            let distance = self.distance;
+            let duration = self.default_transition_duration;
+            self.start_transition(target, Quaternion::one(), distance, duration);
+        } else {
+            self.transition_to_default();
+        }
+    }
+
     pub fn set_current_as_default(&mut self) {
         self.default_rotation = self.rotation;
         self.default_distance = self.distance;
@@ -401,6 +419,11 @@ impl Camera {
     /// Handle mouse clicks,
     pub fn handle_mouse_input(&mut self, button: MouseButton, state: ButtonState) {
         match (button, state) {
+            (_, ButtonState::Pressed) if self.flat => {
+                self.state = CamState::Pan;
+                self.original_pan_point = self.mouse_to_pan_point(self.prev_mouse_coords);
+                self.original_target = self.target;
+            }
             (MouseButton::Left, ButtonState::Pressed) => {
                 self.state = CamState::Tumble;
                 self.tumble_duration = 0.0;
@@ -532,4 +555,22 @@ mod tests {
         screen_point = camera.mouse_to_screen(vec2(256.0, 512.0)).into();
         close(&screen_point, &[0.0, 0.0], f32::EPSILON);
     }
+
+    #[test]
+    fn test_flat() {
+        let mut camera = make_cam_with_window(1024.0, 1024.0);
+        camera.set_flat(true);
+        camera.update(1000.0, 1024.0, 1024.0);
+        let position: [f32; 3] = camera.get_position().into();
+        close(&position, &[0.0, 0.0, 50.0], 0.0001);
+
+        // Dragging with the left button pans rather than tumbles
+        camera.handle_mouse_move(512.0, 512.0);
+        camera.handle_mouse_input(MouseButton::Left, ButtonState::Pressed);
+        camera.handle_mouse_move(768.0, 512.0);
+        camera.handle_mouse_input(MouseButton::Left, ButtonState::Released);
+        assert_eq!(camera.rotation, Quaternion::one());
+        assert!(camera.target.x < 0.0);
+        close(&[camera.target.y, camera.target.z], &[0.0, 0.0], 0.0001);
+    }
 }
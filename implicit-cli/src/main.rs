@@ -1,9 +1,15 @@
 use bincode::serialize_into;
 use geoprim::Plot;
-use implicit_mesh::function::Function;
+use implicit_mesh::bytecode::CompiledFunction;
+use implicit_mesh::display::constraint_to_string;
+use implicit_mesh::function::{Parameters, Relation};
+use implicit_mesh::function_ir::Node;
 use implicit_mesh::interval::Interval;
 use implicit_mesh::mesh_tree::*;
-use std::collections::HashMap;
+use implicit_mesh::parametric::{self, Parametric};
+use implicit_mesh::parser;
+use implicit_mesh::quad_tree::{BoundingRectangle, QuadTree};
+use std::collections::{BTreeSet, HashMap};
 use std::fs::File;
 use std::io;
 use std::io::BufWriter;
@@ -11,12 +17,22 @@ use std::path::PathBuf;
 use std::process::Command;
 use structopt::StructOpt;
 
+/// Mesh trees are split in f64, so they can go as deep as morton keys allow
+const MESH_TREE_MAX_LEVEL: u32 = 21;
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "implicit-cli")]
 struct Args {
-    /// The epression to generate geometry for
-    #[structopt(short = "e", long = "expression")]
-    expression: String,
+    /// The equations to generate geometry for, i.e. "x^2 + y^2 = z" or "x^2 + y^2 = 1, z = 0".
+    /// An expression without a relation, like "x^2 + y^2 - z", is taken to be "= 0".
+    /// Definitions can come first, i.e. "r = x^2 + y^2; f(t) = t^2 - 1; f(r) + z^2 = 0",
+    /// and LaTeX such as "\frac{x^{2}}{4} + y^{2} \le 1" works too.
+    /// With --coordinates, spherical and cylindrical coordinates r, theta, phi and rho can be used
+    /// in place of x, y and z, i.e. "r = 10 + 3 * sin(5 * theta)".
+    /// Parametric surfaces and curves are given as coordinates of u and v along with their ranges,
+    /// i.e. "x = cos(u), y = sin(u), z = v, 0 <= u, u <= 6.2832, -1 <= v, v <= 1".
+    #[structopt(short = "e", long = "equation", alias = "expression")]
+    equation: String,
 
     /// The epsilon value that serves as the basecase for our octtree recursion
     #[structopt(short = "s", long = "epsilon")]
@@ -28,21 +44,198 @@ struct Args {
 
     /// The bounding box side size
     #[structopt(short = "b", long = "bounding-box", default_value = "40.0")]
-    box_size: f32,
+    box_size: f64,
 
     /// If passed, don't plot the oct tree
     #[structopt(long = "no-oct-tree")]
     no_oct_tree: bool,
+
+    /// Values for named parameters in the equation, i.e. "-p a=2.5 -p radius=10".
+    /// Parameters that are not given default to 1.0
+    #[structopt(short = "p", long = "parameter")]
+    parameters: Vec<String>,
+
+    /// If passed, stop refining cells where the surface of a single equation is provably
+    /// a single sheet. Those cells are only drawn as a wire grid sampled on the sheet,
+    /// without triangles
+    #[structopt(long = "monotone-pruning")]
+    monotone_pruning: bool,
+
+    /// If passed, also enclose the equations with affine arithmetic, which keeps
+    /// fewer cells when variables appear more than once but is slower per cell
+    #[structopt(long = "affine")]
+    affine: bool,
+
+    /// The number of steps to sample u and v at for parametric surfaces and curves
+    #[structopt(long = "steps", default_value = "64")]
+    steps: usize,
+
+    /// If passed, plot the curve where a single equation holds in the plane z = 0,
+    /// i.e. "x^2 + y^2 = 1" is a circle rather than a cylinder
+    #[structopt(long = "flat")]
+    flat: bool,
+
+    /// If passed, r, theta, phi and rho in the equation are spherical and cylindrical
+    /// coordinates rather than parameters
+    #[structopt(long = "coordinates")]
+    coordinates: bool,
+}
+
+fn parse_parameters(args: &[String]) -> Parameters {
+    let mut parameters = Parameters::new();
+    for arg in args {
+        let mut split = arg.splitn(2, '=');
+        let name = split.next().unwrap().trim();
+        let value = split
+            .next()
+            .and_then(|v| v.trim().parse().ok())
+            .expect("Parameters must be given as name=value");
+        parameters.insert(name.to_string(), value);
+    }
+    parameters
+}
+
+/// The given parameters, with the ones the equation uses but weren't given set to 1.0.
+/// Given parameters that the equation doesn't use are likely typos, so they're reported.
+fn fill_parameters(args: &Args, used: &BTreeSet<String>) -> Parameters {
+    let mut parameters = parse_parameters(&args.parameters);
+    for name in parameters.keys() {
+        if !used.contains(name) {
+            println!("Parameter {} is not used by the equation", name);
+        }
+    }
+    for name in used {
+        parameters.entry(name.clone()).or_insert_with_key(|name| {
+            println!("No value given for parameter {}, using 1.0", name);
+            1.0
+        });
+    }
+    parameters
+}
+
+fn plot_parametric(args: &Args, constraints: &[(Box<Node>, Relation)]) {
+    let surface = match Parametric::from_constraints(constraints) {
+        Ok(surface) => surface,
+        Err(err) => {
+            eprintln!("Unable to plot parametric equation\n{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let parameters = fill_parameters(args, &surface.parameters());
+
+    println!("Tessellating...");
+    let geometry = surface.tessellate(&parameters, args.steps);
+    let mut plot = Plot::new();
+    geometry.add_to_plot(surface.is_curve(), &mut plot);
+    let file = File::create(&args.output).unwrap();
+    let mut w = BufWriter::new(file);
+    serialize_into(&mut w, &plot).expect("Unable to serialize plot");
+}
+
+/// Report equations that hold everywhere or nowhere, there's nothing to refine for those
+fn report_degeneracy(degeneracy: Option<Degeneracy>) -> bool {
+    match degeneracy {
+        Some(Degeneracy::Everywhere) => {
+            println!("Every point in the bounding box satisfies the equations");
+            true
+        }
+        Some(Degeneracy::Nowhere) => {
+            println!("No point in the bounding box satisfies the equations");
+            true
+        }
+        None => false,
+    }
+}
+
+fn write_plot(args: &Args, plot: &Plot) {
+    let file = File::create(&args.output).unwrap();
+    let mut w = BufWriter::new(file);
+    serialize_into(&mut w, plot).expect("Unable to serialize plot");
+}
+
+fn plot_flat(args: &Args, constraints: Vec<(Box<Node>, Relation)>, parameters: Parameters) {
+    let f = match constraints.as_slice() {
+        [(f, Relation::Equal)] => f,
+        _ => {
+            eprintln!("Flat plots take a single equation");
+            std::process::exit(1);
+        }
+    };
+
+    println!("Making quad tree...");
+    let half_size = (args.box_size / 2.0) as f32;
+    let size_interval = Interval::new(-half_size, half_size);
+    let rectangle = BoundingRectangle {
+        x: size_interval,
+        y: size_interval,
+    };
+    let mut qtree = QuadTree::new(
+        Box::new(CompiledFunction::compile(&f.simplify())),
+        rectangle,
+    );
+    qtree.set_parameters(parameters);
+    let degenerate = report_degeneracy(qtree.degeneracy());
+
+    loop {
+        println!("Plotting quad tree...");
+        qtree.generate_segments();
+        let mut plot = Plot::new();
+        qtree.add_to_plot(!args.no_oct_tree, false, true, &mut plot);
+        write_plot(args, &plot);
+
+        if degenerate || qtree.level >= 16 {
+            break;
+        }
+
+        let mut line = String::new();
+        io::stdin()
+            .read_line(&mut line)
+            .expect("Failed to read line");
+        println!("Next level...");
+        qtree.next_level();
+    }
 }
 
 fn main() {
     let args = Args::from_args();
 
     println!("Parsing...");
-    let input: Vec<char> = args.expression.chars().collect();
-    let f = implicit_mesh::parser::parse_expression(&input, 0).expect("Unable to parse expression");
+    let input: Vec<char> = args.equation.chars().collect();
+    let parsed = match (parser::is_latex(&input), args.coordinates) {
+        (false, false) => parser::parse_program(&input, 0),
+        (true, false) => parser::parse_latex_program(&input, 0),
+        (false, true) => parser::parse_program_with_coordinates(&input, 0),
+        (true, true) => parser::parse_latex_program_with_coordinates(&input, 0),
+    };
+    let constraints = match parsed {
+        Ok(program) => program.expand(),
+        Err(errors) => {
+            eprintln!("Unable to parse equation\n{}", errors.render(&input));
+            std::process::exit(1);
+        }
+    };
+    for (f, relation) in &constraints {
+        println!("Interpreted as {}", constraint_to_string(f, *relation));
+    }
     //let f = Box::new(implicit::function::ConstFunction{ c: 0.0});
 
+    if parametric::is_parametric(&constraints) {
+        plot_parametric(&args, &constraints);
+        return;
+    }
+
+    let used: BTreeSet<String> = constraints
+        .iter()
+        .flat_map(|(f, _)| f.parameters())
+        .collect();
+    let parameters = fill_parameters(&args, &used);
+
+    if args.flat {
+        plot_flat(&args, constraints, parameters);
+        return;
+    }
+
     println!("Making mesh tree...");
     let size_interval = Interval::new(-args.box_size / 2.0, args.box_size / 2.0);
     let bounding_box = BoundingBox {
@@ -51,20 +244,49 @@ fn main() {
         z: size_interval.clone(),
     };
 
-    let mut mtree = MeshTree::new(f, bounding_box);
+    let add_interior = constraints
+        .iter()
+        .any(|(_, relation)| *relation != Relation::Equal);
+    let constraints = constraints
+        .into_iter()
+        .map(|(f, relation)| {
+            (
+                Box::new(CompiledFunction::<f64>::compile(&f.simplify())),
+                relation,
+            )
+        })
+        .collect();
+    let mut mtree = MeshTree::new_system(constraints, bounding_box);
+    mtree.set_parameters(parameters);
+    if args.monotone_pruning {
+        mtree.set_monotone_pruning(true);
+    }
+    if args.affine {
+        mtree.set_affine_arithmetic(true);
+    }
+    let is_curve = mtree.is_curve();
+    if report_degeneracy(mtree.degeneracy()) {
+        let mut plot = Plot::new();
+        mtree.add_to_plot(false, false, false, false, true, &mut plot);
+        write_plot(&args, &plot);
+        return;
+    }
     {
         println!("Plotting mtree...");
         mtree.generate_vertex_map();
+        if is_curve {
+            mtree.generate_curve_set();
+        }
         let mut plot = Plot::new();
-        mtree.add_to_plot(false, false, false, true, &mut plot);
+        mtree.add_to_plot(false, false, is_curve, !is_curve, add_interior, &mut plot);
         let file = File::create(&args.output).unwrap();
         let mut w = BufWriter::new(file);
         serialize_into(&mut w, &plot).expect("Unable to serialize plot");
     }
 
-    while mtree.level < 16 {
+    while mtree.level < MESH_TREE_MAX_LEVEL {
         let mut line = String::new();
-        let input = io::stdin()
+        io::stdin()
             .read_line(&mut line)
             .expect("Failed to read line");
 
@@ -76,14 +298,24 @@ fn main() {
             _ => {
                 println!("Next level...");
                 mtree.next_level();
+                if report_degeneracy(mtree.degeneracy()) {
+                    let mut plot = Plot::new();
+                    mtree.add_to_plot(false, false, false, false, true, &mut plot);
+                    write_plot(&args, &plot);
+                    break;
+                }
                 mtree.generate_vertex_map();
-                mtree.generate_triangle_set();
+                if is_curve {
+                    mtree.generate_curve_set();
+                } else {
+                    mtree.generate_quad_set();
+                }
             }
         }
 
         println!("Plotting mtree...");
         let mut plot = Plot::new();
-        mtree.add_to_plot(false, true, false, true, &mut plot);
+        mtree.add_to_plot(false, true, is_curve, !is_curve, add_interior, &mut plot);
 
         let file = File::create(&args.output).unwrap();
         let mut w = BufWriter::new(file);
@@ -0,0 +1,86 @@
+//! How many cells `MeshTree::next_level` keeps with interval arithmetic alone,
+//! and with affine arithmetic narrowing the intervals down, along with the time
+//! each takes. Run with `cargo bench -p implicit-mesh --bench affine`.
+
+use implicit_mesh::bytecode::CompiledFunction;
+use implicit_mesh::interval::Interval;
+use implicit_mesh::mesh_tree::{BoundingBox, MeshTree};
+use implicit_mesh::parser::parse_expression;
+use std::time::Instant;
+
+const LEVELS: u32 = 6;
+
+const SURFACES: [(&str, &str, f32); 6] = [
+    ("sphere", "(x - 1)^2 + (y - 2)^2 + (z - 3)^2 - 64", 20.0),
+    (
+        "torus",
+        "((x - 1)^2 + (y - 1)^2 + (z - 1)^2 + 21)^2 - 100 * ((x - 1)^2 + (y - 1)^2)",
+        20.0,
+    ),
+    (
+        "tanglecube",
+        "x^4 - 5 * x^2 + y^4 - 5 * y^2 + z^4 - 5 * z^2 + 11.8",
+        8.0,
+    ),
+    (
+        "heart",
+        "(x^2 + 2.25 * y^2 + (z - 0.1)^2 - 1)^3 - x^2 * (z - 0.1)^3 - 0.1125 * y^2 * (z - 0.1)^3",
+        3.0,
+    ),
+    ("hyperboloid", "x * y + y * z + z * x - 1", 20.0),
+    (
+        "gyroid",
+        "sin(x) * cos(y) + sin(y) * cos(z) + sin(z) * cos(x)",
+        20.0,
+    ),
+];
+
+/// Refine to each level, returning the cells kept at each and the time taken in milliseconds
+fn refine(expression: &str, size: f32, affine: bool) -> (Vec<usize>, f64) {
+    let input: Vec<char> = expression.chars().collect();
+    let f = parse_expression(&input, 0).unwrap();
+    let side = Interval::new(-size / 2.0, size / 2.0);
+    let bounding_box = BoundingBox {
+        x: side,
+        y: side,
+        z: side,
+    };
+
+    let start = Instant::now();
+    let mut mtree = MeshTree::new(
+        Box::new(CompiledFunction::compile(&f.simplify())),
+        bounding_box,
+    );
+    mtree.set_affine_arithmetic(affine);
+    let mut counts = Vec::new();
+    for _ in 0..LEVELS {
+        mtree.next_level();
+        counts.push(mtree.get_solution_cell_count());
+    }
+    (counts, start.elapsed().as_secs_f64() * 1000.0)
+}
+
+fn main() {
+    println!(
+        "{:<12} {:>5} {:>10} {:>10} {:>8}",
+        "surface", "level", "intervals", "affine", "saved"
+    );
+    for &(name, expression, size) in SURFACES.iter() {
+        let (intervals, intervals_ms) = refine(expression, size, false);
+        let (affine, affine_ms) = refine(expression, size, true);
+        for (level, (i, a)) in intervals.iter().zip(&affine).enumerate() {
+            println!(
+                "{:<12} {:>5} {:>10} {:>10} {:>7.1}%",
+                name,
+                level + 1,
+                i,
+                a,
+                100.0 * (*i as f64 - *a as f64) / *i as f64
+            );
+        }
+        println!(
+            "{:<12} took {:.1}ms with intervals, {:.1}ms with affine",
+            name, intervals_ms, affine_ms
+        );
+    }
+}
@@ -0,0 +1,122 @@
+//! How many cells `MeshTree::next_level` keeps for some standard surfaces, compared
+//! with interval evaluation that treats every factor of a power as independent.
+//! Run with `cargo bench -p implicit-mesh`.
+
+use implicit_mesh::function::{Function, Parameters};
+use implicit_mesh::function_ir::Node;
+use implicit_mesh::interval::Interval;
+use implicit_mesh::mesh_tree::{BoundingBox, MeshTree};
+use implicit_mesh::parser::parse_expression;
+use std::collections::HashMap;
+
+const LEVELS: u32 = 6;
+
+// Centred away from the cell boundaries, so bases of powers straddle zero
+const SURFACES: [(&str, &str, f32); 6] = [
+    ("sphere", "(x - 1)^2 + (y - 2)^2 + (z - 3)^2 - 64", 20.0),
+    ("paraboloid", "(x - 1.5)^2 + (y + 2.5)^2 - z", 20.0),
+    (
+        "torus",
+        "((x - 1)^2 + (y - 1)^2 + (z - 1)^2 + 21)^2 - 100 * ((x - 1)^2 + (y - 1)^2)",
+        20.0,
+    ),
+    (
+        "tanglecube",
+        "x^4 - 5 * x^2 + y^4 - 5 * y^2 + z^4 - 5 * z^2 + 11.8",
+        8.0,
+    ),
+    (
+        "heart",
+        "(x^2 + 2.25 * y^2 + (z - 0.1)^2 - 1)^3 - x^2 * (z - 0.1)^3 - 0.1125 * y^2 * (z - 0.1)^3",
+        3.0,
+    ),
+    (
+        "saddle",
+        "(x - 0.7) * (x - 0.7) - (y + 1.3) * (y + 1.3) - z",
+        20.0,
+    ),
+];
+
+/// Evaluates intervals the way `Interval` did before integer powers were special
+/// cased, multiplying out `x^n` and `x * x` as if each factor were unrelated
+struct Independent(Node);
+
+fn independent_hull(node: &Node, bindings: &HashMap<char, Interval>) -> Interval {
+    let e = |n: &Node| independent_hull(n, bindings);
+    match *node {
+        Node::Mul(ref n1, ref n2) => e(n1).mul_hull(&e(n2)),
+        Node::Exp(ref n1, ref n2) => match **n2 {
+            Node::Constant(n) if n.fract() == 0.0 && n >= 1.0 => {
+                let base = e(n1);
+                (1..n as u32).fold(base, |product, _| product.mul_hull(&base))
+            }
+            _ => e(n1).exp_hull(&e(n2)),
+        },
+        Node::Add(ref n1, ref n2) => e(n1).add_hull(&e(n2)),
+        Node::Sub(ref n1, ref n2) => e(n1).sub_hull(&e(n2)),
+        Node::Div(ref n1, ref n2) => e(n1).div_hull(&e(n2)),
+        Node::Variable(c) => bindings[&c],
+        Node::Constant(c) => Interval::new(c, c),
+        _ => Interval::hull(&node.evaluate_intervals(bindings)),
+    }
+}
+
+impl Function for Independent {
+    fn evaluate(&self, x: f32, y: f32, z: f32) -> f32 {
+        Function::evaluate(&self.0, x, y, z)
+    }
+
+    fn evaluate_interval(&self, bindings: &HashMap<char, Interval>) -> Vec<Interval> {
+        let hull = independent_hull(&self.0, bindings);
+        if hull.is_empty() {
+            vec![]
+        } else {
+            vec![hull]
+        }
+    }
+
+    fn bind_parameters(&self, parameters: &Parameters) -> Independent {
+        Independent(self.0.bind(parameters))
+    }
+}
+
+/// Refine to each level, returning the cells kept at each
+fn refine<F: Function>(f: F, size: f32) -> Vec<usize> {
+    let side = Interval::new(-size / 2.0, size / 2.0);
+    let bounding_box = BoundingBox {
+        x: side,
+        y: side,
+        z: side,
+    };
+    let mut mtree = MeshTree::new(Box::new(f), bounding_box);
+    let mut counts = Vec::new();
+    for _ in 0..LEVELS {
+        mtree.next_level();
+        counts.push(mtree.get_solution_cell_count());
+    }
+    counts
+}
+
+fn main() {
+    println!(
+        "{:<12} {:>5} {:>12} {:>12} {:>8}",
+        "surface", "level", "independent", "powers", "saved"
+    );
+    for &(name, expression, size) in SURFACES.iter() {
+        let input: Vec<char> = expression.chars().collect();
+        let node = *parse_expression(&input, 0).unwrap();
+
+        let before = refine(Independent(node.clone()), size);
+        let after = refine(node, size);
+        for (level, (b, a)) in before.iter().zip(&after).enumerate() {
+            println!(
+                "{:<12} {:>5} {:>12} {:>12} {:>7.1}%",
+                name,
+                level + 1,
+                b,
+                a,
+                100.0 * (*b as f64 - *a as f64) / *b as f64
+            );
+        }
+    }
+}
@@ -0,0 +1,531 @@
+use crate::bounding_box::BoundingBox;
+use crate::function_ir::Node;
+use crate::interval::Interval;
+use crate::scalar::Scalar;
+
+/// A value in affine form, `center + sum(coefficients[i] * e[i]) + [-error, error]`,
+/// where the noise symbols `e[i]` range over [-1, 1] and stand for the position
+/// along x, y and z within a cell. Unlike intervals, forms remember how they
+/// depend on each axis, so `x - x` cancels and `x * y - y * x` stays small.
+///
+/// Nonlinear operations are replaced by a linear approximation of their argument,
+/// and the approximation error is folded into `error`, which isn't correlated
+/// with anything. Rounding is accounted for by a generous relative epsilon on
+/// every term involved in an operation.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AffineForm<S = f32> {
+    pub center: S,
+    pub coefficients: [S; 3],
+    pub error: S,
+}
+
+impl<S: Scalar> AffineForm<S> {
+    pub fn constant(c: S) -> AffineForm<S> {
+        AffineForm {
+            center: c,
+            coefficients: [S::zero(); 3],
+            error: S::zero(),
+        }
+    }
+
+    /// A form that only knows its value lies in the interval
+    pub fn from_interval(interval: &Interval<S>) -> AffineForm<S> {
+        AffineForm::symbol(interval, None)
+    }
+
+    /// The form for an axis ranging over the interval, tied to that axis' noise symbol
+    pub fn axis(interval: &Interval<S>, axis: usize) -> AffineForm<S> {
+        AffineForm::symbol(interval, Some(axis))
+    }
+
+    fn symbol(interval: &Interval<S>, axis: Option<usize>) -> AffineForm<S> {
+        if interval.is_empty() {
+            return AffineForm::empty();
+        }
+        if !interval.min.is_finite() || !interval.max.is_finite() {
+            return AffineForm::unbounded();
+        }
+
+        let center = interval.middle();
+        let radius = (interval.max - interval.min) / S::from_f32(2.0);
+        let mut result = AffineForm::constant(center);
+        match axis {
+            Some(axis) => result.coefficients[axis] = radius,
+            None => result.error = radius,
+        }
+        result.with_rounding(interval.min.abs() + interval.max.abs())
+    }
+
+    /// Values outside the domain, i.e. `ln` of a negative number
+    pub fn empty() -> AffineForm<S> {
+        AffineForm::constant(S::nan())
+    }
+
+    pub fn unbounded() -> AffineForm<S> {
+        AffineForm {
+            center: S::zero(),
+            coefficients: [S::zero(); 3],
+            error: S::infinity(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.center.is_nan()
+    }
+
+    /// The total magnitude of the noise terms
+    pub fn radius(&self) -> S {
+        self.coefficients
+            .iter()
+            .fold(S::zero(), |sum, c| sum + c.abs())
+            + self.error
+    }
+
+    /// The magnitude of every term, used to bound rounding errors
+    fn magnitude(&self) -> S {
+        self.center.abs() + self.radius()
+    }
+
+    /// Widen the error to cover the rounding of an operation whose terms had the given magnitude
+    fn with_rounding(mut self, magnitude: S) -> AffineForm<S> {
+        let epsilon = S::epsilon();
+        self.error = (self.error + (epsilon + epsilon) * magnitude).next_up();
+        if self.error.is_nan() && !self.center.is_nan() {
+            self.error = S::infinity();
+        }
+        self
+    }
+
+    pub fn to_interval(&self) -> Interval<S> {
+        if self.is_empty() {
+            return Interval::empty();
+        }
+
+        let radius = self.radius() * (S::one() + S::from_f32(4.0) * S::epsilon());
+        if !radius.is_finite() || !self.center.is_finite() {
+            return Interval::unbounded();
+        }
+        Interval::new(
+            (self.center - radius).next_down(),
+            (self.center + radius).next_up(),
+        )
+    }
+
+    pub fn neg(&self) -> AffineForm<S> {
+        AffineForm {
+            center: -self.center,
+            coefficients: [
+                -self.coefficients[0],
+                -self.coefficients[1],
+                -self.coefficients[2],
+            ],
+            error: self.error,
+        }
+    }
+
+    pub fn add(&self, other: &AffineForm<S>) -> AffineForm<S> {
+        AffineForm {
+            center: self.center + other.center,
+            coefficients: [
+                self.coefficients[0] + other.coefficients[0],
+                self.coefficients[1] + other.coefficients[1],
+                self.coefficients[2] + other.coefficients[2],
+            ],
+            error: self.error + other.error,
+        }
+        .with_rounding(self.magnitude() + other.magnitude())
+    }
+
+    pub fn sub(&self, other: &AffineForm<S>) -> AffineForm<S> {
+        self.add(&other.neg())
+    }
+
+    /// `alpha * self + zeta ± delta`, the shape of every linear approximation
+    fn affine(&self, alpha: S, zeta: S, delta: S) -> AffineForm<S> {
+        AffineForm {
+            center: alpha * self.center + zeta,
+            coefficients: [
+                alpha * self.coefficients[0],
+                alpha * self.coefficients[1],
+                alpha * self.coefficients[2],
+            ],
+            error: alpha.abs() * self.error + delta,
+        }
+        .with_rounding(alpha.abs() * self.magnitude() + zeta.abs() + delta)
+    }
+
+    /// The product of two forms is a form plus a quadratic term in the noise
+    /// symbols, which is bounded by the product of their radii
+    pub fn mul(&self, other: &AffineForm<S>) -> AffineForm<S> {
+        let (a, b) = (self.center, other.center);
+        AffineForm {
+            center: a * b,
+            coefficients: [
+                a * other.coefficients[0] + b * self.coefficients[0],
+                a * other.coefficients[1] + b * self.coefficients[1],
+                a * other.coefficients[2] + b * self.coefficients[2],
+            ],
+            error: a.abs() * other.error + b.abs() * self.error + self.radius() * other.radius(),
+        }
+        .with_rounding(self.magnitude() * other.magnitude())
+    }
+
+    /// Like multiplying a form by itself, but the quadratic term can't be negative,
+    /// so it's enclosed by [0, r^2] rather than [-r^2, r^2]
+    pub fn square(&self) -> AffineForm<S> {
+        let c = self.center;
+        let two = S::from_f32(2.0);
+        let half_square = self.radius() * self.radius() / two;
+        AffineForm {
+            center: c * c + half_square,
+            coefficients: [
+                two * c * self.coefficients[0],
+                two * c * self.coefficients[1],
+                two * c * self.coefficients[2],
+            ],
+            error: two * c.abs() * self.error + half_square,
+        }
+        .with_rounding(self.magnitude() * self.magnitude())
+    }
+
+    pub fn powi(&self, n: i32) -> AffineForm<S> {
+        if n == 0 {
+            AffineForm::constant(S::one())
+        } else if n < 0 {
+            self.powi(-n).recip()
+        } else if n == 1 {
+            *self
+        } else if n % 2 == 0 {
+            self.powi(n / 2).square()
+        } else {
+            self.powi(n - 1).mul(self)
+        }
+    }
+
+    /// Approximate a function that is convex or concave over `range` by
+    /// `alpha * x + zeta`, where alpha is its slope at one end. Choosing the end
+    /// that makes `f(x) - alpha * x` monotone means that difference is bounded
+    /// by its values at the ends, `d1` and `d2`.
+    fn min_range(&self, range: &Interval<S>, alpha: S, f_min: S, f_max: S) -> AffineForm<S> {
+        let two = S::from_f32(2.0);
+        let d1 = f_min - alpha * range.min;
+        let d2 = f_max - alpha * range.max;
+        let zeta = (d1 + d2) / two;
+        let delta = (d1 - d2).abs() / two;
+        self.affine(alpha, zeta, delta).with_rounding(
+            f_min.abs() + f_max.abs() + alpha.abs() * (range.min.abs() + range.max.abs()),
+        )
+    }
+
+    /// Apply an interval operation, losing track of how the result depends on each axis
+    pub fn map_interval<F: Fn(&Interval<S>) -> Interval<S>>(&self, op: F) -> AffineForm<S> {
+        AffineForm::from_interval(&op(&self.to_interval()))
+    }
+
+    /// Whether the form is bounded, and so can be linearised
+    fn bounded_range(&self) -> Option<Interval<S>> {
+        let range = self.to_interval();
+        if !self.is_empty() && range.min.is_finite() && range.max.is_finite() {
+            Some(range)
+        } else {
+            None
+        }
+    }
+
+    /// 1 / x is convex for positive x, so the slope at the upper end works
+    pub fn recip(&self) -> AffineForm<S> {
+        match self.bounded_range() {
+            Some(range) if range.min > S::zero() => {
+                let alpha = -(range.max * range.max).recip();
+                self.min_range(&range, alpha, range.min.recip(), range.max.recip())
+            }
+            Some(range) if range.max < S::zero() => self.neg().recip().neg(),
+            _ => self.map_interval(|i| Interval::constant(S::one()).div_hull(i)),
+        }
+    }
+
+    pub fn div(&self, other: &AffineForm<S>) -> AffineForm<S> {
+        self.mul(&other.recip())
+    }
+
+    /// e^x is convex, so the slope at the lower end works
+    pub fn exponential(&self) -> AffineForm<S> {
+        match self.bounded_range() {
+            Some(range) if range.max < S::from_f32(80.0) => {
+                let (f_min, f_max) = (range.min.exp(), range.max.exp());
+                self.min_range(&range, f_min, f_min, f_max)
+            }
+            _ => self.map_interval(Interval::exponential_hull),
+        }
+    }
+
+    /// ln is concave, so the slope at the upper end works
+    pub fn ln(&self) -> AffineForm<S> {
+        match self.bounded_range() {
+            Some(range) if range.min > S::zero() => {
+                let alpha = range.max.recip();
+                self.min_range(&range, alpha, range.min.ln(), range.max.ln())
+            }
+            _ => self.map_interval(Interval::ln_hull),
+        }
+    }
+
+    /// sqrt is concave, so the slope at the upper end works. Negative values
+    /// are outside the domain, so only the non-negative part is approximated.
+    pub fn sqrt(&self) -> AffineForm<S> {
+        match self.bounded_range() {
+            Some(range) if range.max > S::zero() => {
+                let range = Interval::new(range.min.max(S::zero()), range.max);
+                let alpha = S::from_f32(0.5) / range.max.sqrt();
+                self.min_range(&range, alpha, range.min.sqrt(), range.max.sqrt())
+            }
+            _ => self.map_interval(Interval::sqrt_hull),
+        }
+    }
+
+    pub fn sin(&self) -> AffineForm<S> {
+        self.map_interval(Interval::sin_hull)
+    }
+
+    pub fn cos(&self) -> AffineForm<S> {
+        self.map_interval(Interval::cos_hull)
+    }
+
+    pub fn tan(&self) -> AffineForm<S> {
+        self.map_interval(Interval::tan_hull)
+    }
+
+    pub fn abs(&self) -> AffineForm<S> {
+        let range = self.to_interval();
+        if range.min >= S::zero() {
+            *self
+        } else if range.max <= S::zero() {
+            self.neg()
+        } else {
+            self.map_interval(Interval::abs_hull)
+        }
+    }
+
+    pub fn min(&self, other: &AffineForm<S>) -> AffineForm<S> {
+        let (a, b) = (self.to_interval(), other.to_interval());
+        if a.max <= b.min {
+            *self
+        } else if b.max <= a.min {
+            *other
+        } else {
+            AffineForm::from_interval(&a.min_hull(&b))
+        }
+    }
+
+    pub fn max(&self, other: &AffineForm<S>) -> AffineForm<S> {
+        let (a, b) = (self.to_interval(), other.to_interval());
+        if a.min >= b.max {
+            *self
+        } else if b.min >= a.max {
+            *other
+        } else {
+            AffineForm::from_interval(&a.max_hull(&b))
+        }
+    }
+
+    /// Constant integer powers are multiplied out, everything else goes through intervals
+    pub fn exp(&self, power: &AffineForm<S>) -> AffineForm<S> {
+        let n = power.center;
+        if power.radius() == S::zero() && n.fract() == S::zero() && n.abs() <= S::from_f32(64.0) {
+            self.powi(n.to_i32().unwrap())
+        } else {
+            AffineForm::from_interval(&self.to_interval().exp_hull(&power.to_interval()))
+        }
+    }
+
+    pub fn atan2(&self, x: &AffineForm<S>) -> AffineForm<S> {
+        AffineForm::from_interval(&self.to_interval().atan2_hull(&x.to_interval()))
+    }
+}
+
+impl Node {
+    /// Enclose the expression over the bounding box with affine arithmetic.
+    /// Unbound parameters are treated as unbounded.
+    pub fn evaluate_affine_form<S: Scalar>(&self, bb: &BoundingBox<S>) -> AffineForm<S> {
+        let axes = [
+            AffineForm::axis(&bb.x, 0),
+            AffineForm::axis(&bb.y, 1),
+            AffineForm::axis(&bb.z, 2),
+        ];
+        self.affine_form(&axes)
+    }
+
+    fn affine_form<S: Scalar>(&self, axes: &[AffineForm<S>; 3]) -> AffineForm<S> {
+        let e = |n: &Node| n.affine_form(axes);
+        match *self {
+            Node::Add(ref n1, ref n2) => e(n1).add(&e(n2)),
+            Node::Sub(ref n1, ref n2) => e(n1).sub(&e(n2)),
+            Node::Mul(ref n1, ref n2) if n1 == n2 => e(n1).square(),
+            Node::Mul(ref n1, ref n2) => e(n1).mul(&e(n2)),
+            Node::Div(ref n1, ref n2) => e(n1).div(&e(n2)),
+            Node::Exp(ref n1, ref n2) => e(n1).exp(&e(n2)),
+            Node::Sin(ref n) => e(n).sin(),
+            Node::Cos(ref n) => e(n).cos(),
+            Node::Tan(ref n) => e(n).tan(),
+            Node::Exponential(ref n) => e(n).exponential(),
+            Node::Ln(ref n) => e(n).ln(),
+            Node::Sqrt(ref n) => e(n).sqrt(),
+            Node::Abs(ref n) => e(n).abs(),
+            Node::Min(ref n1, ref n2) => e(n1).min(&e(n2)),
+            Node::Max(ref n1, ref n2) => e(n1).max(&e(n2)),
+            Node::Atan2(ref n1, ref n2) => e(n1).atan2(&e(n2)),
+            Node::Variable('x') => axes[0],
+            Node::Variable('y') => axes[1],
+            Node::Variable('z') => axes[2],
+            Node::Variable(_) | Node::Parameter(_) => AffineForm::unbounded(),
+            Node::Constant(c) => AffineForm::constant(S::from_f32(c)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::function::Function;
+    use crate::parser::parse_expression;
+    use itertools::iproduct;
+    use std::collections::HashMap;
+
+    fn parse(expression: &str) -> Node {
+        let input: Vec<char> = expression.chars().collect();
+        *parse_expression(&input, 0).unwrap()
+    }
+
+    fn cube(min: f32, max: f32) -> BoundingBox {
+        let side = Interval::new(min, max);
+        BoundingBox {
+            x: side,
+            y: side,
+            z: side,
+        }
+    }
+
+    #[test]
+    fn test_cancellation() {
+        let bb = cube(1.0, 3.0);
+        let r = parse("x - x").evaluate_affine_form(&bb).to_interval();
+        assert!(r.contains_zero());
+        assert!(r.max - r.min < 1e-5);
+
+        // Intervals can't tell the two products are the same, only their
+        // quadratic terms of at most 1 each are left over in affine form
+        let f = parse("x * y - y * x + z");
+        let r = f.evaluate_affine_form(&bb).to_interval();
+        assert!(r.min > -1.0 - 1e-4 && r.max < 5.0 + 1e-4);
+        let bindings = bb_bindings(&bb);
+        assert_eq!(
+            Interval::hull(&f.evaluate_intervals(&bindings)),
+            Interval::new(-7.0, 11.0)
+        );
+    }
+
+    fn bb_bindings(bb: &BoundingBox) -> HashMap<char, Interval> {
+        let mut bindings = HashMap::new();
+        bindings.insert('x', bb.x);
+        bindings.insert('y', bb.y);
+        bindings.insert('z', bb.z);
+        bindings
+    }
+
+    #[test]
+    fn test_special_values() {
+        let bb = cube(-1.0, 1.0);
+        assert!(parse("ln(x - 5)").evaluate_affine_form(&bb).is_empty());
+        assert!(parse("sqrt(x - 5)").evaluate_affine_form(&bb).is_empty());
+
+        let r = parse("1 / x").evaluate_affine_form(&bb).to_interval();
+        assert_eq!(r, Interval::new(-f32::INFINITY, f32::INFINITY));
+        let r = parse("a * x").evaluate_affine_form(&bb).to_interval();
+        assert_eq!(r, Interval::new(-f32::INFINITY, f32::INFINITY));
+
+        let r = parse("x^2").evaluate_affine_form(&bb).to_interval();
+        assert!(r.min <= 0.0 && r.max >= 1.0);
+    }
+
+    #[test]
+    fn test_enclosures_contain_samples() {
+        let expressions = [
+            "x * y - z / (x + 20)",
+            "(x + y)^2 - (x - y)^2 - 4 * x * y",
+            "x^3 - 2 * x * y + z^-2",
+            "exp(x / 4) - ln(y + 20) + sqrt(z + 5)",
+            "sin(x * y) + cos(z) * tan(x / 8)",
+            "abs(x - y) + min(x, z) - max(y, z)",
+            "atan2(y, x) + sqrt(x) * x^0.5",
+            "1 / (x - y) + 1 / (z + 20)",
+        ];
+        let boxes = [
+            cube(-4.0, 4.0),
+            cube(0.5, 0.75),
+            cube(-3.3, -3.1),
+            cube(2.0, 2.0),
+            BoundingBox {
+                x: Interval::new(-1.0, 2.5),
+                y: Interval::new(3.0, 3.125),
+                z: Interval::new(-0.1, 0.3),
+            },
+        ];
+        let steps = 6;
+
+        for expression in expressions.iter() {
+            let f = parse(expression);
+            for bb in boxes.iter() {
+                let r = f.evaluate_affine_form(bb).to_interval();
+                let point = |i: &Interval, step: usize| {
+                    if step == steps {
+                        i.max
+                    } else {
+                        i.min + (i.max - i.min) * step as f32 / steps as f32
+                    }
+                };
+                for (i, j, k) in iproduct!(0..=steps, 0..=steps, 0..=steps) {
+                    let (x, y, z) = (point(&bb.x, i), point(&bb.y, j), point(&bb.z, k));
+                    let value = Function::evaluate(&f, x, y, z);
+                    if value.is_nan() {
+                        continue;
+                    }
+                    assert!(
+                        r.min <= value && value <= r.max,
+                        "{} = {} at ({}, {}, {}) is outside {:?}",
+                        expression,
+                        value,
+                        x,
+                        y,
+                        z,
+                        r
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_tighter_than_intervals() {
+        let bb = BoundingBox {
+            x: Interval::new(1.0, 1.5),
+            y: Interval::new(-2.0, -1.5),
+            z: Interval::new(0.0, 0.5),
+        };
+        for expression in &[
+            "x * (y - x) + x^2 - y",
+            "(x^2 + y^2 + z^2 + 21)^2 - 100 * (x^2 + y^2)",
+            "x * y * z - x * y",
+        ] {
+            let f = parse(expression);
+            let affine = f.evaluate_affine_form(&bb).to_interval();
+            let interval = Interval::hull(&f.evaluate_intervals(&bb_bindings(&bb)));
+            assert!(
+                affine.max - affine.min < interval.max - interval.min,
+                "{}: {:?} is no tighter than {:?}",
+                expression,
+                affine,
+                interval
+            );
+        }
+    }
+}
@@ -0,0 +1,534 @@
+use crate::bounding_box::BoundingBox;
+use crate::function_ir::Node;
+use crate::interval::Interval;
+use crate::scalar::Scalar;
+use cgmath::{Vector3, Zero};
+
+/// A value along with its gradient with respect to x, y and z
+#[derive(Copy, Clone, Debug)]
+pub struct Dual<S = f32> {
+    pub value: S,
+    pub gradient: Vector3<S>,
+}
+
+impl<S: Scalar> Dual<S> {
+    pub fn constant(value: S) -> Dual<S> {
+        Dual {
+            value,
+            gradient: Vector3::zero(),
+        }
+    }
+
+    /// Apply a function with the given value and derivative at self, using the chain rule
+    fn chain(&self, value: S, derivative: S) -> Dual<S> {
+        Dual {
+            value,
+            gradient: self.gradient * derivative,
+        }
+    }
+
+    fn add(&self, other: &Dual<S>) -> Dual<S> {
+        Dual {
+            value: self.value + other.value,
+            gradient: self.gradient + other.gradient,
+        }
+    }
+
+    fn sub(&self, other: &Dual<S>) -> Dual<S> {
+        Dual {
+            value: self.value - other.value,
+            gradient: self.gradient - other.gradient,
+        }
+    }
+
+    fn mul(&self, other: &Dual<S>) -> Dual<S> {
+        Dual {
+            value: self.value * other.value,
+            gradient: self.gradient * other.value + other.gradient * self.value,
+        }
+    }
+
+    fn div(&self, other: &Dual<S>) -> Dual<S> {
+        let value = self.value / other.value;
+        Dual {
+            value,
+            gradient: (self.gradient - other.gradient * value) / other.value,
+        }
+    }
+
+    fn exp(&self, power: &Dual<S>) -> Dual<S> {
+        let value = self.value.powf(power.value);
+        if power.gradient.is_zero() {
+            // Constant powers are fine with negative bases
+            self.chain(value, power.value * self.value.powf(power.value - S::one()))
+        } else {
+            Dual {
+                value,
+                gradient: (power.gradient * self.value.ln()
+                    + self.gradient * (power.value / self.value))
+                    * value,
+            }
+        }
+    }
+
+    /// The angle of (x, self), whose gradient is (x u' - u x') / (x^2 + u^2)
+    fn atan2(&self, x: &Dual<S>) -> Dual<S> {
+        let squared_norm = x.value * x.value + self.value * self.value;
+        Dual {
+            value: self.value.atan2(x.value),
+            gradient: (self.gradient * x.value - x.gradient * self.value) / squared_norm,
+        }
+    }
+}
+
+/// An enclosure of a function's value and of each component of its gradient
+/// over a bounding box
+#[derive(Copy, Clone, Debug)]
+pub struct IntervalDual<S = f32> {
+    pub value: Interval<S>,
+    pub gradient: [Interval<S>; 3],
+}
+
+impl<S: Scalar> IntervalDual<S> {
+    pub fn constant(value: Interval<S>) -> IntervalDual<S> {
+        let zero = Interval::constant(S::zero());
+        IntervalDual {
+            value,
+            gradient: [zero, zero, zero],
+        }
+    }
+
+    fn is_constant(&self) -> bool {
+        self.gradient
+            .iter()
+            .all(|g| g.min == S::zero() && g.max == S::zero())
+    }
+
+    fn map_gradient<F>(&self, other: &IntervalDual<S>, op: F) -> [Interval<S>; 3]
+    where
+        F: Fn(&Interval<S>, &Interval<S>) -> Interval<S>,
+    {
+        [
+            op(&self.gradient[0], &other.gradient[0]),
+            op(&self.gradient[1], &other.gradient[1]),
+            op(&self.gradient[2], &other.gradient[2]),
+        ]
+    }
+
+    fn scale_gradient(&self, factor: &Interval<S>) -> [Interval<S>; 3] {
+        [
+            self.gradient[0].mul_hull(factor),
+            self.gradient[1].mul_hull(factor),
+            self.gradient[2].mul_hull(factor),
+        ]
+    }
+
+    fn chain(&self, value: Interval<S>, derivative: &Interval<S>) -> IntervalDual<S> {
+        IntervalDual {
+            value,
+            gradient: self.scale_gradient(derivative),
+        }
+    }
+
+    fn add(&self, other: &IntervalDual<S>) -> IntervalDual<S> {
+        IntervalDual {
+            value: self.value.add_hull(&other.value),
+            gradient: self.map_gradient(other, Interval::add_hull),
+        }
+    }
+
+    fn sub(&self, other: &IntervalDual<S>) -> IntervalDual<S> {
+        IntervalDual {
+            value: self.value.sub_hull(&other.value),
+            gradient: self.map_gradient(other, Interval::sub_hull),
+        }
+    }
+
+    fn mul(&self, other: &IntervalDual<S>) -> IntervalDual<S> {
+        let left = self.scale_gradient(&other.value);
+        let right = other.scale_gradient(&self.value);
+        IntervalDual {
+            value: self.value.mul_hull(&other.value),
+            gradient: [
+                left[0].add_hull(&right[0]),
+                left[1].add_hull(&right[1]),
+                left[2].add_hull(&right[2]),
+            ],
+        }
+    }
+
+    fn div(&self, other: &IntervalDual<S>) -> IntervalDual<S> {
+        // (u' - v' u / v) / v
+        let value = self.value.div_hull(&other.value);
+        let scaled = other.scale_gradient(&value);
+        IntervalDual {
+            value,
+            gradient: [
+                self.gradient[0].sub_hull(&scaled[0]).div_hull(&other.value),
+                self.gradient[1].sub_hull(&scaled[1]).div_hull(&other.value),
+                self.gradient[2].sub_hull(&scaled[2]).div_hull(&other.value),
+            ],
+        }
+    }
+
+    fn exp(&self, power: &IntervalDual<S>) -> IntervalDual<S> {
+        if power.is_constant() && power.value.min == power.value.max {
+            let n = power.value.min;
+            let derivative = self
+                .value
+                .exp_hull(&Interval::constant(n - S::one()))
+                .mul_hull(&power.value);
+            self.chain(self.value.exp_hull(&power.value), &derivative)
+        } else {
+            // u^v (v' ln(u) + v u' / u)
+            let value = self.value.exp_hull(&power.value);
+            let ln = self.value.ln_hull();
+            let ratio = power.value.div_hull(&self.value);
+            let left = power.scale_gradient(&ln);
+            let right = self.scale_gradient(&ratio);
+            IntervalDual {
+                value,
+                gradient: [
+                    left[0].add_hull(&right[0]).mul_hull(&value),
+                    left[1].add_hull(&right[1]).mul_hull(&value),
+                    left[2].add_hull(&right[2]).mul_hull(&value),
+                ],
+            }
+        }
+    }
+
+    fn atan2(&self, x: &IntervalDual<S>) -> IntervalDual<S> {
+        let squared_norm = x.value.powi_hull(2).add_hull(&self.value.powi_hull(2));
+        let left = self.scale_gradient(&x.value);
+        let right = x.scale_gradient(&self.value);
+        IntervalDual {
+            value: self.value.atan2_hull(&x.value),
+            gradient: [
+                left[0].sub_hull(&right[0]).div_hull(&squared_norm),
+                left[1].sub_hull(&right[1]).div_hull(&squared_norm),
+                left[2].sub_hull(&right[2]).div_hull(&squared_norm),
+            ],
+        }
+    }
+
+    /// min and max follow whichever side is smaller or larger. Where that's
+    /// ambiguous the gradient could be either side's.
+    fn select(
+        &self,
+        other: &IntervalDual<S>,
+        value: Interval<S>,
+        pick_self: bool,
+    ) -> IntervalDual<S> {
+        if self.value.max < other.value.min || other.value.max < self.value.min {
+            if pick_self {
+                *self
+            } else {
+                *other
+            }
+        } else {
+            IntervalDual {
+                value,
+                gradient: [
+                    Interval::hull(&[self.gradient[0], other.gradient[0]]),
+                    Interval::hull(&[self.gradient[1], other.gradient[1]]),
+                    Interval::hull(&[self.gradient[2], other.gradient[2]]),
+                ],
+            }
+        }
+    }
+}
+
+impl Node {
+    /// Evaluate the expression and its gradient in one pass.
+    /// Unbound parameters evaluate to NaN.
+    pub fn evaluate_dual<S: Scalar>(&self, x: S, y: S, z: S) -> Dual<S> {
+        match *self {
+            Node::Add(ref n1, ref n2) => n1.evaluate_dual(x, y, z).add(&n2.evaluate_dual(x, y, z)),
+            Node::Sub(ref n1, ref n2) => n1.evaluate_dual(x, y, z).sub(&n2.evaluate_dual(x, y, z)),
+            Node::Mul(ref n1, ref n2) => n1.evaluate_dual(x, y, z).mul(&n2.evaluate_dual(x, y, z)),
+            Node::Div(ref n1, ref n2) => n1.evaluate_dual(x, y, z).div(&n2.evaluate_dual(x, y, z)),
+            Node::Exp(ref n1, ref n2) => n1.evaluate_dual(x, y, z).exp(&n2.evaluate_dual(x, y, z)),
+            Node::Sin(ref n) => {
+                let u = n.evaluate_dual(x, y, z);
+                u.chain(u.value.sin(), u.value.cos())
+            }
+            Node::Cos(ref n) => {
+                let u = n.evaluate_dual(x, y, z);
+                u.chain(u.value.cos(), -u.value.sin())
+            }
+            Node::Tan(ref n) => {
+                let u = n.evaluate_dual(x, y, z);
+                u.chain(u.value.tan(), u.value.cos().powi(2).recip())
+            }
+            Node::Exponential(ref n) => {
+                let u = n.evaluate_dual(x, y, z);
+                let value = u.value.exp();
+                u.chain(value, value)
+            }
+            Node::Ln(ref n) => {
+                let u = n.evaluate_dual(x, y, z);
+                u.chain(u.value.ln(), u.value.recip())
+            }
+            Node::Sqrt(ref n) => {
+                let u = n.evaluate_dual(x, y, z);
+                let value = u.value.sqrt();
+                u.chain(value, S::from_f32(0.5) / value)
+            }
+            Node::Abs(ref n) => {
+                let u = n.evaluate_dual(x, y, z);
+                u.chain(u.value.abs(), u.value.signum())
+            }
+            Node::Min(ref n1, ref n2) => {
+                let (u, v) = (n1.evaluate_dual(x, y, z), n2.evaluate_dual(x, y, z));
+                if u.value <= v.value {
+                    u
+                } else {
+                    v
+                }
+            }
+            Node::Max(ref n1, ref n2) => {
+                let (u, v) = (n1.evaluate_dual(x, y, z), n2.evaluate_dual(x, y, z));
+                if u.value >= v.value {
+                    u
+                } else {
+                    v
+                }
+            }
+            Node::Atan2(ref n1, ref n2) => {
+                n1.evaluate_dual(x, y, z).atan2(&n2.evaluate_dual(x, y, z))
+            }
+            Node::Variable(c) => match c {
+                'x' => Dual {
+                    value: x,
+                    gradient: Vector3::unit_x(),
+                },
+                'y' => Dual {
+                    value: y,
+                    gradient: Vector3::unit_y(),
+                },
+                'z' => Dual {
+                    value: z,
+                    gradient: Vector3::unit_z(),
+                },
+                _ => Dual::constant(S::nan()),
+            },
+            Node::Parameter(_) => Dual::constant(S::nan()),
+            Node::Constant(c) => Dual::constant(S::from_f32(c)),
+        }
+    }
+
+    /// Enclose the expression's value and gradient over the bounding box.
+    /// Unbound parameters are treated as unbounded.
+    pub fn evaluate_interval_dual<S: Scalar>(&self, bb: &BoundingBox<S>) -> IntervalDual<S> {
+        match *self {
+            Node::Add(ref n1, ref n2) => n1
+                .evaluate_interval_dual(bb)
+                .add(&n2.evaluate_interval_dual(bb)),
+            Node::Sub(ref n1, ref n2) => n1
+                .evaluate_interval_dual(bb)
+                .sub(&n2.evaluate_interval_dual(bb)),
+            Node::Mul(ref n1, ref n2) if n1 == n2 => {
+                let u = n1.evaluate_interval_dual(bb);
+                let derivative = u.value.mul_hull(&Interval::constant(S::from_f32(2.0)));
+                u.chain(u.value.powi_hull(2), &derivative)
+            }
+            Node::Mul(ref n1, ref n2) => n1
+                .evaluate_interval_dual(bb)
+                .mul(&n2.evaluate_interval_dual(bb)),
+            Node::Div(ref n1, ref n2) => n1
+                .evaluate_interval_dual(bb)
+                .div(&n2.evaluate_interval_dual(bb)),
+            Node::Exp(ref n1, ref n2) => n1
+                .evaluate_interval_dual(bb)
+                .exp(&n2.evaluate_interval_dual(bb)),
+            Node::Sin(ref n) => {
+                let u = n.evaluate_interval_dual(bb);
+                u.chain(u.value.sin_hull(), &u.value.cos_hull())
+            }
+            Node::Cos(ref n) => {
+                let u = n.evaluate_interval_dual(bb);
+                let derivative = u.value.sin_hull().mul_hull(&Interval::constant(-S::one()));
+                u.chain(u.value.cos_hull(), &derivative)
+            }
+            Node::Tan(ref n) => {
+                let u = n.evaluate_interval_dual(bb);
+                let cos = u.value.cos_hull();
+                let derivative = Interval::constant(S::one()).div_hull(&cos.mul_hull(&cos));
+                u.chain(u.value.tan_hull(), &derivative)
+            }
+            Node::Exponential(ref n) => {
+                let u = n.evaluate_interval_dual(bb);
+                let value = u.value.exponential_hull();
+                u.chain(value, &value)
+            }
+            Node::Ln(ref n) => {
+                let u = n.evaluate_interval_dual(bb);
+                let derivative = Interval::constant(S::one()).div_hull(&u.value);
+                u.chain(u.value.ln_hull(), &derivative)
+            }
+            Node::Sqrt(ref n) => {
+                let u = n.evaluate_interval_dual(bb);
+                let value = u.value.sqrt_hull();
+                let derivative = Interval::constant(S::from_f32(0.5)).div_hull(&value);
+                u.chain(value, &derivative)
+            }
+            Node::Abs(ref n) => {
+                let u = n.evaluate_interval_dual(bb);
+                let one = S::one();
+                let derivative = if u.value.min > S::zero() {
+                    Interval::new(one, one)
+                } else if u.value.max < S::zero() {
+                    Interval::new(-one, -one)
+                } else {
+                    Interval::new(-one, one)
+                };
+                u.chain(u.value.abs_hull(), &derivative)
+            }
+            Node::Min(ref n1, ref n2) => {
+                let (u, v) = (n1.evaluate_interval_dual(bb), n2.evaluate_interval_dual(bb));
+                u.select(&v, u.value.min_hull(&v.value), u.value.max < v.value.min)
+            }
+            Node::Max(ref n1, ref n2) => {
+                let (u, v) = (n1.evaluate_interval_dual(bb), n2.evaluate_interval_dual(bb));
+                u.select(&v, u.value.max_hull(&v.value), u.value.min > v.value.max)
+            }
+            Node::Atan2(ref n1, ref n2) => n1
+                .evaluate_interval_dual(bb)
+                .atan2(&n2.evaluate_interval_dual(bb)),
+            Node::Variable(c) => {
+                let one = Interval::constant(S::one());
+                let mut result = IntervalDual::constant(match c {
+                    'x' => bb.x,
+                    'y' => bb.y,
+                    'z' => bb.z,
+                    _ => Interval::unbounded(),
+                });
+                match c {
+                    'x' => result.gradient[0] = one,
+                    'y' => result.gradient[1] = one,
+                    'z' => result.gradient[2] = one,
+                    _ => (),
+                }
+                result
+            }
+            Node::Parameter(_) => IntervalDual::constant(Interval::unbounded()),
+            Node::Constant(c) => IntervalDual::constant(Interval::constant(S::from_f32(c))),
+        }
+    }
+}
+
+/// If one component of the gradient enclosure excludes zero, the function is
+/// strictly monotone along that axis, and so any zero set inside the box is a
+/// single sheet that is a graph over the other two axes. Returns that axis.
+pub fn monotone_axis<S: Scalar>(gradient: &[Interval<S>; 3]) -> Option<usize> {
+    (0..3).find(|&axis| {
+        let g = gradient[axis];
+        !g.is_empty() && !g.contains_zero()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::function::Function;
+    use crate::parser::parse_expression;
+
+    fn cube(min: f32, max: f32) -> BoundingBox {
+        let interval = Interval::new(min, max);
+        BoundingBox {
+            x: interval,
+            y: interval,
+            z: interval,
+        }
+    }
+
+    #[test]
+    fn test_dual_matches_symbolic() {
+        let expressions = [
+            "x * y - z / x",
+            "x ^ 3 - 2 * y ^ 2 + z ^ 0.5",
+            "x ^ y + 2 ^ z",
+            "sin(x * y) + cos(z) * tan(y)",
+            "exp(x * z) + ln(y) + sqrt(x + y)",
+            "abs(x - z) + min(x, y) * max(y, z)",
+            "atan2(y, x) - atan2(z, -x * y)",
+        ];
+
+        for expression in expressions.iter() {
+            let input: Vec<char> = expression.chars().collect();
+            let root = parse_expression(&input, 0).unwrap();
+
+            for &(x, y, z) in &[(1.13f32, 0.732, 0.4939), (0.5, 1.25, 0.75)] {
+                let dual = root.evaluate_dual(x, y, z);
+                assert_similiar!(dual.value, Function::evaluate(&*root, x, y, z));
+                for (axis, var) in ['x', 'y', 'z'].iter().enumerate() {
+                    let expected: f32 = Function::evaluate(&root.derivative(*var), x, y, z);
+                    assert_similiar!(dual.gradient[axis], expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_interval_dual_encloses_gradient() {
+        let expressions = [
+            "x^2 + y^2 + z^2 - 1",
+            "x^3 + 2 * x - y * z",
+            "sin(x) * exp(y) + z / (x + 3)",
+            "sqrt(x + 2) - ln(y + 2) + abs(z)",
+            "max(x, y) - min(y, z)",
+        ];
+        let bb = cube(-0.5, 0.75);
+        let samples = [-0.5, -0.1, 0.0, 0.3, 0.75];
+
+        for expression in expressions.iter() {
+            let input: Vec<char> = expression.chars().collect();
+            let root = parse_expression(&input, 0).unwrap();
+            let enclosure = root.evaluate_interval_dual(&bb);
+
+            for &x in &samples {
+                for &y in &samples {
+                    for &z in &samples {
+                        let dual = root.evaluate_dual(x, y, z);
+                        let value = Interval::new(dual.value, dual.value);
+                        assert!(
+                            enclosure.value.min <= value.min && value.max <= enclosure.value.max
+                        );
+                        for axis in 0..3 {
+                            let g = dual.gradient[axis];
+                            assert!(
+                                enclosure.gradient[axis].min <= g
+                                    && g <= enclosure.gradient[axis].max,
+                                "{} at ({}, {}, {}): {:?} outside {:?}",
+                                expression,
+                                x,
+                                y,
+                                z,
+                                g,
+                                enclosure.gradient[axis]
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_monotone_axis() {
+        let input: Vec<char> = "x^2 + y^2 + z^2 - 1".chars().collect();
+        let root = parse_expression(&input, 0).unwrap();
+
+        // Away from the center the sphere is a graph over some plane
+        let bb = BoundingBox {
+            x: Interval::new(0.5, 1.0),
+            y: Interval::new(-0.25, 0.25),
+            z: Interval::new(-0.25, 0.25),
+        };
+        let gradient = root.evaluate_interval_dual(&bb).gradient;
+        assert_eq!(monotone_axis(&gradient), Some(0));
+
+        let gradient = root.evaluate_interval_dual(&cube(-1.0, 1.0)).gradient;
+        assert_eq!(monotone_axis(&gradient), None);
+    }
+}
@@ -0,0 +1,52 @@
+use crate::interval::Interval;
+use crate::scalar::Scalar;
+use cgmath::Vector3;
+use itertools::Itertools;
+
+/// An axis aligned box, a cell of a `MeshTree`
+#[derive(Debug, Copy, Clone)]
+pub struct BoundingBox<S = f32> {
+    pub x: Interval<S>,
+    pub y: Interval<S>,
+    pub z: Interval<S>,
+}
+
+impl<S: Scalar> BoundingBox<S> {
+    pub fn split(&self) -> Vec<BoundingBox<S>> {
+        let x_is = self.x.split();
+        let y_is = self.y.split();
+        let z_is = self.z.split();
+
+        x_is.iter()
+            .cartesian_product(y_is.iter())
+            .cartesian_product(z_is.iter())
+            .map(|((x, y), z)| BoundingBox {
+                x: x.clone(),
+                y: y.clone(),
+                z: z.clone(),
+            })
+            .collect()
+    }
+
+    pub fn center(&self) -> Vector3<S> {
+        Vector3::new(self.x.middle(), self.y.middle(), self.z.middle())
+    }
+
+    pub fn clamp_vector(&self, v: &mut Vector3<S>) {
+        v.x = self.x.clamp_value(v.x);
+        v.y = self.y.clamp_value(v.y);
+        v.z = self.z.clamp_value(v.z);
+    }
+
+    /// Corner `i` takes the max of x, y and z where bits 2, 1 and 0 of `i` are set
+    pub fn corner(&self, i: usize) -> Vector3<S> {
+        let pick = |interval: &Interval<S>, bit: usize| {
+            if i & (1 << bit) == 0 {
+                interval.min
+            } else {
+                interval.max
+            }
+        };
+        Vector3::new(pick(&self.x, 2), pick(&self.y, 1), pick(&self.z, 0))
+    }
+}
@@ -0,0 +1,778 @@
+use crate::bounding_box::BoundingBox;
+use crate::dag::{Dag, DagNode, NodeId};
+use crate::function::{Function, Parameters};
+use crate::function_ir::Node;
+use crate::interval::{merge_intervals_in_place, Interval, MAX_PIECES};
+use crate::scalar::Scalar;
+use cgmath::Vector3;
+use std::cell::{OnceCell, RefCell};
+use std::collections::HashMap;
+
+/// A single operation of the stack machine. Loads push a value, unary
+/// operations replace the top of the stack, and binary operations pop their
+/// right hand side then replace the left hand side with the result.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Instruction {
+    LoadX,
+    LoadY,
+    LoadZ,
+    LoadConstant(f32),
+    /// A parameter that was not bound when the function was compiled
+    LoadUnknown,
+    /// Copy the top of the stack into a slot, leaving it on the stack
+    Store(usize),
+    /// Push the value saved by `Store`
+    LoadSlot(usize),
+    Add,
+    Sub,
+    Mul,
+    /// Multiply the top of the stack by itself
+    Square,
+    Div,
+    Exp,
+    Min,
+    Max,
+    Atan2,
+    Sin,
+    Cos,
+    Tan,
+    Exponential,
+    Ln,
+    Sqrt,
+    Abs,
+}
+
+/// A `Node` tree flattened into postfix instructions. Evaluation runs over
+/// preallocated stacks, so neither scalar nor interval evaluation allocates
+/// beyond the `Vec` that `Function::evaluate_interval` has to return.
+///
+/// Division by an interval straddling zero splits the quotient in two, which
+/// functions with a division keep apart rather than joining them into a hull
+/// that may be everything. The pieces live in preallocated buffers too.
+///
+/// Repeated subexpressions are found through a `Dag` and only computed once,
+/// later uses load the saved value from a slot instead.
+///
+/// The stacks hold values of the scalar type the function is evaluated in.
+///
+/// The partial derivatives with respect to x, y and z are compiled the first
+/// time `Function::gradient` needs them, since most uses never ask for it.
+pub struct CompiledFunction<S: Scalar = f32> {
+    source: Node,
+    instructions: Vec<Instruction>,
+    partials: OnceCell<Vec<CompiledFunction<S>>>,
+    /// Whether there is a division, which can split an interval into pieces
+    splits: bool,
+    scalar_stack: RefCell<Vec<S>>,
+    interval_stack: RefCell<Vec<Interval<S>>>,
+    scalar_slots: RefCell<Vec<S>>,
+    interval_slots: RefCell<Vec<Interval<S>>>,
+    piece_buffers: RefCell<PieceBuffers<S>>,
+}
+
+impl<S: Scalar> CompiledFunction<S> {
+    pub fn compile(source: &Node) -> CompiledFunction<S> {
+        let (dag, root) = Dag::from_node(source);
+        let mut emitter = Emitter {
+            uses: dag.uses(root),
+            dag,
+            slots: HashMap::new(),
+            instructions: Vec::new(),
+        };
+        let stack_size = emitter.emit(root);
+        let slot_count = emitter.slots.len();
+        let splits = emitter.instructions.contains(&Instruction::Div);
+
+        CompiledFunction {
+            source: source.clone(),
+            instructions: emitter.instructions,
+            partials: OnceCell::new(),
+            splits,
+            scalar_stack: RefCell::new(vec![S::zero(); stack_size]),
+            interval_stack: RefCell::new(vec![Interval::constant(S::zero()); stack_size]),
+            scalar_slots: RefCell::new(vec![S::zero(); slot_count]),
+            interval_slots: RefCell::new(vec![Interval::constant(S::zero()); slot_count]),
+            piece_buffers: RefCell::new(PieceBuffers::new(stack_size, slot_count)),
+        }
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    pub fn source(&self) -> &Node {
+        &self.source
+    }
+
+    fn evaluate_interval_hull(
+        &self,
+        x: Interval<S>,
+        y: Interval<S>,
+        z: Interval<S>,
+    ) -> Interval<S> {
+        let mut stack = self.interval_stack.borrow_mut();
+        let mut slots = self.interval_slots.borrow_mut();
+        let mut top = 0;
+
+        for instruction in &self.instructions {
+            match *instruction {
+                Instruction::LoadX => {
+                    stack[top] = x;
+                    top += 1;
+                }
+                Instruction::LoadY => {
+                    stack[top] = y;
+                    top += 1;
+                }
+                Instruction::LoadZ => {
+                    stack[top] = z;
+                    top += 1;
+                }
+                Instruction::LoadConstant(c) => {
+                    stack[top] = Interval::constant(S::from_f32(c));
+                    top += 1;
+                }
+                Instruction::LoadUnknown => {
+                    stack[top] = Interval::unbounded();
+                    top += 1;
+                }
+                Instruction::Store(slot) => slots[slot] = stack[top - 1],
+                Instruction::LoadSlot(slot) => {
+                    stack[top] = slots[slot];
+                    top += 1;
+                }
+                Instruction::Add => {
+                    top -= 1;
+                    stack[top - 1] = stack[top - 1].add_hull(&stack[top]);
+                }
+                Instruction::Sub => {
+                    top -= 1;
+                    stack[top - 1] = stack[top - 1].sub_hull(&stack[top]);
+                }
+                Instruction::Mul => {
+                    top -= 1;
+                    stack[top - 1] = stack[top - 1].mul_hull(&stack[top]);
+                }
+                Instruction::Square => stack[top - 1] = stack[top - 1].powi_hull(2),
+                Instruction::Div => {
+                    top -= 1;
+                    stack[top - 1] = stack[top - 1].div_hull(&stack[top]);
+                }
+                Instruction::Exp => {
+                    top -= 1;
+                    stack[top - 1] = stack[top - 1].exp_hull(&stack[top]);
+                }
+                Instruction::Min => {
+                    top -= 1;
+                    stack[top - 1] = stack[top - 1].min_hull(&stack[top]);
+                }
+                Instruction::Max => {
+                    top -= 1;
+                    stack[top - 1] = stack[top - 1].max_hull(&stack[top]);
+                }
+                Instruction::Atan2 => {
+                    top -= 1;
+                    stack[top - 1] = stack[top - 1].atan2_hull(&stack[top]);
+                }
+                Instruction::Sin => stack[top - 1] = stack[top - 1].sin_hull(),
+                Instruction::Cos => stack[top - 1] = stack[top - 1].cos_hull(),
+                Instruction::Tan => stack[top - 1] = stack[top - 1].tan_hull(),
+                Instruction::Exponential => stack[top - 1] = stack[top - 1].exponential_hull(),
+                Instruction::Ln => stack[top - 1] = stack[top - 1].ln_hull(),
+                Instruction::Sqrt => stack[top - 1] = stack[top - 1].sqrt_hull(),
+                Instruction::Abs => stack[top - 1] = stack[top - 1].abs_hull(),
+            }
+        }
+
+        stack[0]
+    }
+
+    /// Like `evaluate_interval_hull`, but division keeps the two pieces of a
+    /// quotient whose divisor straddles zero apart. Each value on the stack is a
+    /// list of pieces, merged down to at most `MAX_PIECES` after every instruction.
+    fn evaluate_interval_pieces(
+        &self,
+        x: Interval<S>,
+        y: Interval<S>,
+        z: Interval<S>,
+    ) -> Vec<Interval<S>> {
+        let mut buffers = self.piece_buffers.borrow_mut();
+        let mut top = 0;
+
+        for instruction in &self.instructions {
+            match *instruction {
+                Instruction::LoadX => top = buffers.push(top, x),
+                Instruction::LoadY => top = buffers.push(top, y),
+                Instruction::LoadZ => top = buffers.push(top, z),
+                Instruction::LoadConstant(c) => {
+                    top = buffers.push(top, Interval::constant(S::from_f32(c)))
+                }
+                Instruction::LoadUnknown => top = buffers.push(top, Interval::unbounded()),
+                Instruction::Store(slot) => buffers.store(top - 1, slot),
+                Instruction::LoadSlot(slot) => top = buffers.load_slot(top, slot),
+                Instruction::Add => top = buffers.binary(top, |a, b, out| out.push(a.add_hull(b))),
+                Instruction::Sub => top = buffers.binary(top, |a, b, out| out.push(a.sub_hull(b))),
+                Instruction::Mul => top = buffers.binary(top, |a, b, out| out.push(a.mul_hull(b))),
+                Instruction::Square => buffers.unary(top, |a| a.powi_hull(2)),
+                Instruction::Div => {
+                    top = buffers.binary(top, |a, b, out| {
+                        let (quotient, rest) = a.div_split(b);
+                        out.push(quotient);
+                        out.extend(rest);
+                    })
+                }
+                Instruction::Exp => top = buffers.binary(top, |a, b, out| out.push(a.exp_hull(b))),
+                Instruction::Min => top = buffers.binary(top, |a, b, out| out.push(a.min_hull(b))),
+                Instruction::Max => top = buffers.binary(top, |a, b, out| out.push(a.max_hull(b))),
+                Instruction::Atan2 => {
+                    top = buffers.binary(top, |a, b, out| out.push(a.atan2_hull(b)))
+                }
+                Instruction::Sin => buffers.unary(top, Interval::sin_hull),
+                Instruction::Cos => buffers.unary(top, Interval::cos_hull),
+                Instruction::Tan => buffers.unary(top, Interval::tan_hull),
+                Instruction::Exponential => buffers.unary(top, Interval::exponential_hull),
+                Instruction::Ln => buffers.unary(top, Interval::ln_hull),
+                Instruction::Sqrt => buffers.unary(top, Interval::sqrt_hull),
+                Instruction::Abs => buffers.unary(top, Interval::abs_hull),
+            }
+        }
+
+        buffers.pieces(0).to_vec()
+    }
+}
+
+/// Preallocated room for `CompiledFunction::evaluate_interval_pieces`, with space
+/// for `MAX_PIECES` pieces in every stack entry and slot
+struct PieceBuffers<S> {
+    stack: Vec<Interval<S>>,
+    lengths: Vec<usize>,
+    /// The slot each stack entry was stored to or loaded from. Two uses of the
+    /// same value have to come from the same piece, so operations on them pair
+    /// the pieces one to one rather than trying every pair.
+    origins: Vec<Option<usize>>,
+    slots: Vec<Interval<S>>,
+    slot_lengths: Vec<usize>,
+    /// The pieces of the current operation, before they are merged
+    scratch: Vec<Interval<S>>,
+}
+
+impl<S: Scalar> PieceBuffers<S> {
+    fn new(stack_size: usize, slot_count: usize) -> PieceBuffers<S> {
+        let empty = Interval::empty();
+        PieceBuffers {
+            stack: vec![empty; stack_size * MAX_PIECES],
+            lengths: vec![0; stack_size],
+            origins: vec![None; stack_size],
+            slots: vec![empty; slot_count * MAX_PIECES],
+            slot_lengths: vec![0; slot_count],
+            scratch: Vec::with_capacity(2 * MAX_PIECES * MAX_PIECES),
+        }
+    }
+
+    fn pieces(&self, entry: usize) -> &[Interval<S>] {
+        let start = entry * MAX_PIECES;
+        &self.stack[start..start + self.lengths[entry]]
+    }
+
+    fn push(&mut self, top: usize, interval: Interval<S>) -> usize {
+        self.stack[top * MAX_PIECES] = interval;
+        self.lengths[top] = 1;
+        self.origins[top] = None;
+        top + 1
+    }
+
+    fn store(&mut self, entry: usize, slot: usize) {
+        let (start, length) = (entry * MAX_PIECES, self.lengths[entry]);
+        let slot_start = slot * MAX_PIECES;
+        self.slots[slot_start..slot_start + length]
+            .copy_from_slice(&self.stack[start..start + length]);
+        self.slot_lengths[slot] = length;
+        self.origins[entry] = Some(slot);
+    }
+
+    fn load_slot(&mut self, top: usize, slot: usize) -> usize {
+        let (slot_start, length) = (slot * MAX_PIECES, self.slot_lengths[slot]);
+        let start = top * MAX_PIECES;
+        self.stack[start..start + length]
+            .copy_from_slice(&self.slots[slot_start..slot_start + length]);
+        self.lengths[top] = length;
+        self.origins[top] = Some(slot);
+        top + 1
+    }
+
+    /// Merge the scratch pieces into the given entry
+    fn finish(&mut self, entry: usize) {
+        merge_intervals_in_place(&mut self.scratch);
+        let start = entry * MAX_PIECES;
+        self.stack[start..start + self.scratch.len()].copy_from_slice(&self.scratch);
+        self.lengths[entry] = self.scratch.len();
+        self.origins[entry] = None;
+    }
+
+    fn unary<F: Fn(&Interval<S>) -> Interval<S>>(&mut self, top: usize, op: F) {
+        let entry = top - 1;
+        self.scratch.clear();
+        let start = entry * MAX_PIECES;
+        for piece in &self.stack[start..start + self.lengths[entry]] {
+            self.scratch.push(op(piece));
+        }
+        self.finish(entry);
+    }
+
+    fn binary<F>(&mut self, top: usize, op: F) -> usize
+    where
+        F: Fn(&Interval<S>, &Interval<S>, &mut Vec<Interval<S>>),
+    {
+        let (entry1, entry2) = (top - 2, top - 1);
+        let (start1, start2) = (entry1 * MAX_PIECES, entry2 * MAX_PIECES);
+        let (length1, length2) = (self.lengths[entry1], self.lengths[entry2]);
+
+        self.scratch.clear();
+        if self.origins[entry1].is_some() && self.origins[entry1] == self.origins[entry2] {
+            for i in 0..length1 {
+                op(
+                    &self.stack[start1 + i],
+                    &self.stack[start2 + i],
+                    &mut self.scratch,
+                );
+            }
+        } else {
+            for i in 0..length1 {
+                for j in 0..length2 {
+                    op(
+                        &self.stack[start1 + i],
+                        &self.stack[start2 + j],
+                        &mut self.scratch,
+                    );
+                }
+            }
+        }
+        self.finish(entry1);
+        top - 1
+    }
+}
+
+struct Emitter {
+    dag: Dag,
+    uses: Vec<usize>,
+    /// The slot of each shared node that has already been emitted
+    slots: HashMap<NodeId, usize>,
+    instructions: Vec<Instruction>,
+}
+
+impl Emitter {
+    /// Append the instructions for a node, returning the stack depth needed to evaluate it
+    fn emit(&mut self, id: NodeId) -> usize {
+        if let Some(&slot) = self.slots.get(&id) {
+            self.instructions.push(Instruction::LoadSlot(slot));
+            return 1;
+        }
+
+        let depth = match *self.dag.node(id) {
+            DagNode::Add(n1, n2) => self.binary(n1, n2, Instruction::Add),
+            DagNode::Sub(n1, n2) => self.binary(n1, n2, Instruction::Sub),
+            DagNode::Mul(n1, n2) if n1 == n2 => self.unary(n1, Instruction::Square),
+            DagNode::Mul(n1, n2) => self.binary(n1, n2, Instruction::Mul),
+            DagNode::Div(n1, n2) => self.binary(n1, n2, Instruction::Div),
+            DagNode::Exp(n1, n2) => self.binary(n1, n2, Instruction::Exp),
+            DagNode::Min(n1, n2) => self.binary(n1, n2, Instruction::Min),
+            DagNode::Max(n1, n2) => self.binary(n1, n2, Instruction::Max),
+            DagNode::Atan2(n1, n2) => self.binary(n1, n2, Instruction::Atan2),
+            DagNode::Sin(n) => self.unary(n, Instruction::Sin),
+            DagNode::Cos(n) => self.unary(n, Instruction::Cos),
+            DagNode::Tan(n) => self.unary(n, Instruction::Tan),
+            DagNode::Exponential(n) => self.unary(n, Instruction::Exponential),
+            DagNode::Ln(n) => self.unary(n, Instruction::Ln),
+            DagNode::Sqrt(n) => self.unary(n, Instruction::Sqrt),
+            DagNode::Abs(n) => self.unary(n, Instruction::Abs),
+            // Loads are as cheap as reading a slot, so they are never shared
+            DagNode::Variable('x') => return self.load(Instruction::LoadX),
+            DagNode::Variable('y') => return self.load(Instruction::LoadY),
+            DagNode::Variable('z') => return self.load(Instruction::LoadZ),
+            DagNode::Variable(_) | DagNode::Parameter(_) => {
+                return self.load(Instruction::LoadUnknown)
+            }
+            DagNode::Constant(bits) => {
+                return self.load(Instruction::LoadConstant(f32::from_bits(bits)))
+            }
+        };
+
+        if self.uses[id] > 1 {
+            let slot = self.slots.len();
+            self.slots.insert(id, slot);
+            self.instructions.push(Instruction::Store(slot));
+        }
+
+        depth
+    }
+
+    fn load(&mut self, instruction: Instruction) -> usize {
+        self.instructions.push(instruction);
+        1
+    }
+
+    fn unary(&mut self, n: NodeId, instruction: Instruction) -> usize {
+        let depth = self.emit(n);
+        self.instructions.push(instruction);
+        depth
+    }
+
+    fn binary(&mut self, n1: NodeId, n2: NodeId, instruction: Instruction) -> usize {
+        let depth1 = self.emit(n1);
+        let depth2 = self.emit(n2);
+        self.instructions.push(instruction);
+        depth1.max(depth2 + 1)
+    }
+}
+
+impl<S: Scalar> Function<S> for CompiledFunction<S> {
+    fn evaluate(&self, x: S, y: S, z: S) -> S {
+        let mut stack = self.scalar_stack.borrow_mut();
+        let mut slots = self.scalar_slots.borrow_mut();
+        let mut top = 0;
+
+        for instruction in &self.instructions {
+            match *instruction {
+                Instruction::LoadX => {
+                    stack[top] = x;
+                    top += 1;
+                }
+                Instruction::LoadY => {
+                    stack[top] = y;
+                    top += 1;
+                }
+                Instruction::LoadZ => {
+                    stack[top] = z;
+                    top += 1;
+                }
+                Instruction::LoadConstant(c) => {
+                    stack[top] = S::from_f32(c);
+                    top += 1;
+                }
+                Instruction::LoadUnknown => {
+                    stack[top] = S::nan();
+                    top += 1;
+                }
+                Instruction::Store(slot) => slots[slot] = stack[top - 1],
+                Instruction::LoadSlot(slot) => {
+                    stack[top] = slots[slot];
+                    top += 1;
+                }
+                Instruction::Add => {
+                    top -= 1;
+                    stack[top - 1] = stack[top - 1] + stack[top];
+                }
+                Instruction::Sub => {
+                    top -= 1;
+                    stack[top - 1] = stack[top - 1] - stack[top];
+                }
+                Instruction::Mul => {
+                    top -= 1;
+                    stack[top - 1] = stack[top - 1] * stack[top];
+                }
+                Instruction::Square => stack[top - 1] = stack[top - 1] * stack[top - 1],
+                Instruction::Div => {
+                    top -= 1;
+                    stack[top - 1] = stack[top - 1] / stack[top];
+                }
+                Instruction::Exp => {
+                    top -= 1;
+                    stack[top - 1] = stack[top - 1].powf(stack[top]);
+                }
+                Instruction::Min => {
+                    top -= 1;
+                    stack[top - 1] = stack[top - 1].min(stack[top]);
+                }
+                Instruction::Max => {
+                    top -= 1;
+                    stack[top - 1] = stack[top - 1].max(stack[top]);
+                }
+                Instruction::Atan2 => {
+                    top -= 1;
+                    stack[top - 1] = stack[top - 1].atan2(stack[top]);
+                }
+                Instruction::Sin => stack[top - 1] = stack[top - 1].sin(),
+                Instruction::Cos => stack[top - 1] = stack[top - 1].cos(),
+                Instruction::Tan => stack[top - 1] = stack[top - 1].tan(),
+                Instruction::Exponential => stack[top - 1] = stack[top - 1].exp(),
+                Instruction::Ln => stack[top - 1] = stack[top - 1].ln(),
+                Instruction::Sqrt => stack[top - 1] = stack[top - 1].sqrt(),
+                Instruction::Abs => stack[top - 1] = stack[top - 1].abs(),
+            }
+        }
+
+        stack[0]
+    }
+
+    fn evaluate_interval(&self, bindings: &HashMap<char, Interval<S>>) -> Vec<Interval<S>> {
+        let unbound = Interval::unbounded();
+        let x = bindings.get(&'x').cloned().unwrap_or(unbound);
+        let y = bindings.get(&'y').cloned().unwrap_or(unbound);
+        let z = bindings.get(&'z').cloned().unwrap_or(unbound);
+
+        if self.splits {
+            return self.evaluate_interval_pieces(x, y, z);
+        }
+
+        let result = self.evaluate_interval_hull(x, y, z);
+        if result.is_empty() {
+            vec![]
+        } else {
+            vec![result]
+        }
+    }
+
+    fn bind_parameters(&self, parameters: &Parameters) -> CompiledFunction<S> {
+        CompiledFunction::compile(&self.source.bind(parameters).simplify())
+    }
+
+    fn gradient(&self, x: S, y: S, z: S) -> Vector3<S> {
+        let partials = self.partials.get_or_init(|| {
+            ['x', 'y', 'z']
+                .iter()
+                .map(|&var| CompiledFunction::compile(&self.source.derivative(var)))
+                .collect()
+        });
+        Vector3::new(
+            partials[0].evaluate(x, y, z),
+            partials[1].evaluate(x, y, z),
+            partials[2].evaluate(x, y, z),
+        )
+    }
+
+    fn gradient_interval(&self, bb: &BoundingBox<S>) -> Option<[Interval<S>; 3]> {
+        self.source.gradient_interval(bb)
+    }
+
+    fn affine_interval(&self, bb: &BoundingBox<S>) -> Option<Interval<S>> {
+        self.source.affine_interval(bb)
+    }
+
+    fn is_total(&self) -> bool {
+        self.source.is_total()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_expression;
+
+    const EXPRESSIONS: [&str; 7] = [
+        "x",
+        "x + y ^ z",
+        "x + y - (z / x) - y + z",
+        "3.2 ^ (0.01 / 8) + (4.0 * 3 + 2 - 3^7 - (4)) / z ^ 2",
+        "sin(x) * cos(y) + tan(z / 4) - exp(x / 3) + ln(y) * sqrt(z)",
+        "min(abs(x - 3), max(y, z)) - (x * (y * (z * (x - 1))))",
+        "(x - y) / (x - y + z) * (x - y) + sqrt(z + x * y) * (y * x + z)",
+    ];
+
+    #[test]
+    fn test_compile() {
+        let input: Vec<char> = "x * (y + 2) - sin(z)".chars().collect();
+        let root = parse_expression(&input, 0).unwrap();
+        let compiled: CompiledFunction = CompiledFunction::compile(&root);
+
+        assert_eq!(
+            compiled.instructions(),
+            &[
+                Instruction::LoadX,
+                Instruction::LoadY,
+                Instruction::LoadConstant(2.0),
+                Instruction::Add,
+                Instruction::Mul,
+                Instruction::LoadZ,
+                Instruction::Sin,
+                Instruction::Sub,
+            ]
+        );
+        assert_eq!(compiled.scalar_stack.borrow().len(), 3);
+    }
+
+    #[test]
+    fn test_shared_subexpressions() {
+        let input: Vec<char> = "sin(x * y) + sin(y * x)^2".chars().collect();
+        let root = parse_expression(&input, 0).unwrap();
+        let compiled: CompiledFunction = CompiledFunction::compile(&root);
+
+        assert_eq!(
+            compiled.instructions(),
+            &[
+                Instruction::LoadX,
+                Instruction::LoadY,
+                Instruction::Mul,
+                Instruction::Sin,
+                Instruction::Store(0),
+                Instruction::LoadSlot(0),
+                Instruction::LoadConstant(2.0),
+                Instruction::Exp,
+                Instruction::Add,
+            ]
+        );
+        assert_similiar!(
+            compiled.evaluate(1.13, 0.732, 0.0),
+            Function::evaluate(&*root, 1.13, 0.732, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_square() {
+        let input: Vec<char> = "sin(x) * sin(x) - y * y".chars().collect();
+        let root = parse_expression(&input, 0).unwrap();
+        let compiled: CompiledFunction = CompiledFunction::compile(&root);
+
+        assert_eq!(
+            compiled.instructions(),
+            &[
+                Instruction::LoadX,
+                Instruction::Sin,
+                Instruction::Square,
+                Instruction::LoadY,
+                Instruction::Square,
+                Instruction::Sub,
+            ]
+        );
+
+        let mut bindings = HashMap::new();
+        bindings.insert('x', Interval::new(-1.0, 1.0));
+        bindings.insert('y', Interval::new(-2.0, 1.0));
+        let result = compiled.evaluate_interval(&bindings);
+        assert_eq!(result.len(), 1);
+        assert!(result[0].min >= -4.0 - 1e-6 && result[0].max <= 1.0);
+        assert_eq!(result, root.evaluate_intervals(&bindings));
+        assert_similiar!(
+            compiled.evaluate(0.3, -1.5, 0.0),
+            Function::evaluate(&*root, 0.3, -1.5, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_evaluate_matches_tree() {
+        for expression in EXPRESSIONS.iter() {
+            let input: Vec<char> = expression.chars().collect();
+            let root = parse_expression(&input, 0).unwrap();
+            let compiled: CompiledFunction = CompiledFunction::compile(&root);
+
+            for &(x, y, z) in &[(1.13, 4.232, 2.0939), (-0.5, 0.25, 3.0), (2.0, 7.5, 0.1)] {
+                let expected = Function::evaluate(&*root, x, y, z);
+                let result = compiled.evaluate(x, y, z);
+                if expected.is_nan() {
+                    assert!(result.is_nan(), "{}", expression);
+                } else {
+                    assert_similiar!(result, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_gradient_matches_tree() {
+        for expression in EXPRESSIONS.iter() {
+            let input: Vec<char> = expression.chars().collect();
+            let root = parse_expression(&input, 0).unwrap();
+            let compiled: CompiledFunction = CompiledFunction::compile(&root);
+
+            for &(x, y, z) in &[(1.13, 4.232, 2.0939), (2.0, 7.5, 0.1)] {
+                let expected = Function::gradient(&*root, x, y, z);
+                let result = compiled.gradient(x, y, z);
+                for axis in 0..3 {
+                    assert_similiar!(result[axis], expected[axis]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_evaluate_f64() {
+        for expression in EXPRESSIONS.iter() {
+            let input: Vec<char> = expression.chars().collect();
+            let root = parse_expression(&input, 0).unwrap();
+            let compiled: CompiledFunction<f64> = CompiledFunction::compile(&root);
+
+            for &(x, y, z) in &[(1.13, 4.232, 2.0939), (1e6 + 0.1, 0.25, 3.0)] {
+                let expected: f64 = Function::evaluate(&*root, x, y, z);
+                let result = compiled.evaluate(x, y, z);
+                if expected.is_nan() {
+                    assert!(result.is_nan(), "{}", expression);
+                } else {
+                    assert_eq!(result, expected, "{}", expression);
+                }
+            }
+        }
+
+        // f32 can't tell x apart from x + 0.1 this far out
+        let input: Vec<char> = "x - 10000000".chars().collect();
+        let root = parse_expression(&input, 0).unwrap();
+        let compiled: CompiledFunction<f64> = CompiledFunction::compile(&root);
+        assert!(compiled.evaluate(1e7 + 0.1, 0.0, 0.0) > 0.09);
+        let compiled: CompiledFunction = CompiledFunction::compile(&root);
+        assert_eq!(compiled.evaluate(1e7 + 0.1, 0.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn test_evaluate_interval_matches_tree() {
+        let mut bindings = HashMap::new();
+        bindings.insert('x', Interval::new(0.5, 3.1));
+        bindings.insert('y', Interval::new(1.0, 5.0));
+        bindings.insert('z', Interval::new(1.0, 3.0));
+
+        for expression in EXPRESSIONS.iter() {
+            let input: Vec<char> = expression.chars().collect();
+            let root = parse_expression(&input, 0).unwrap();
+            let compiled: CompiledFunction = CompiledFunction::compile(&root);
+
+            let expected = Interval::hull(&root.evaluate_interval(&bindings));
+            let result = compiled.evaluate_interval(&bindings);
+            assert_eq!(result.len(), 1, "{}", expression);
+            assert_similiar!(result[0].min, expected.min);
+            assert_similiar!(result[0].max, expected.max);
+        }
+    }
+
+    #[test]
+    fn test_split_intervals() {
+        let mut bindings = HashMap::new();
+        bindings.insert('x', Interval::new(-1.0, 1.0));
+        bindings.insert('y', Interval::new(2.0, 3.0));
+        bindings.insert('z', Interval::new(1.5, 1.7));
+
+        for expression in &["1/x + 1/y - z", "y/x + z", "1/(x * y) - 1/z"] {
+            let input: Vec<char> = expression.chars().collect();
+            let root = parse_expression(&input, 0).unwrap();
+            let compiled: CompiledFunction = CompiledFunction::compile(&root);
+
+            let expected = root.evaluate_intervals(&bindings);
+            assert!(expected.len() > 1, "{}", expression);
+            assert_eq!(compiled.evaluate_interval(&bindings), expected);
+        }
+    }
+
+    #[test]
+    fn test_bind_parameters() {
+        let input: Vec<char> = "x^2 + y^2 - r".chars().collect();
+        let root = parse_expression(&input, 0).unwrap();
+        let compiled: CompiledFunction = CompiledFunction::compile(&root);
+        assert!(compiled.evaluate(1.0, 1.0, 0.0).is_nan());
+
+        let mut parameters = Parameters::new();
+        parameters.insert("r".to_string(), 2.0);
+        let bound = compiled.bind_parameters(&parameters);
+        assert_similiar!(bound.evaluate(1.0, 1.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn test_shared_pieces_pair_up() {
+        // Both uses of 1/x come from the same piece, so the difference stays
+        // small instead of spanning every pair of pieces.
+        let input: Vec<char> = "1/(1 + exp(1/x)) - 1/(1 + exp(1/x))".chars().collect();
+        let root = parse_expression(&input, 0).unwrap();
+        let compiled: CompiledFunction = CompiledFunction::compile(&root);
+
+        let mut bindings = HashMap::new();
+        bindings.insert('x', Interval::new(-1.0, 1.0));
+        for piece in compiled.evaluate_interval(&bindings) {
+            assert!(piece.min > -0.3 && piece.max < 0.3, "{:?}", piece);
+        }
+    }
+}
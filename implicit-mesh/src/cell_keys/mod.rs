@@ -1,5 +1,7 @@
 pub mod morton_keys;
 pub mod neighbors;
+pub mod quad_keys;
 
 pub use morton_keys::*;
 pub use neighbors::*;
+pub use quad_keys::*;
@@ -0,0 +1,174 @@
+use std::fmt;
+
+use super::morton_keys::Key;
+use super::neighbors::*;
+
+/// A quad key has a root bit followed by two bits per level, so this many levels fit
+pub const QUAD_COMPONENT_BIT_COUNT: u32 = 31;
+
+const DILATION_MASKS: [u64; 5] = [
+    0x0000_ffff_0000_ffff,
+    0x00ff_00ff_00ff_00ff,
+    0x0f0f_0f0f_0f0f_0f0f,
+    0x3333_3333_3333_3333,
+    0x5555_5555_5555_5555,
+];
+
+/// The 2D counterpart to `MortonKey`, the x and y components of the cell are
+/// interleaved below a leading root bit, with x in the higher bit of each pair
+#[derive(PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+pub struct QuadKey(pub u64);
+
+impl fmt::Debug for QuadKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "QuadKey({:b})", self.0)
+    }
+}
+
+impl Key for QuadKey {
+    fn root_key() -> QuadKey {
+        QuadKey(1)
+    }
+
+    fn child_key(&self, child: u64) -> QuadKey {
+        QuadKey(self.0 << 2 | child)
+    }
+
+    fn level(&self) -> u32 {
+        (63 - self.0.leading_zeros()) / 2
+    }
+
+    /// Neighbors that differ in z don't exist in the plane
+    fn neighbor_key(&self, neighbor: Neighbor) -> Option<QuadKey> {
+        if neighbor.z != NeighborRelation::Same {
+            return None;
+        }
+
+        let level = self.level();
+        let step = |c: u32, relation: NeighborRelation| match relation {
+            NeighborRelation::Less if c == 0 => None,
+            NeighborRelation::Less => Some(c - 1),
+            NeighborRelation::More if c + 1 == 1 << level => None,
+            NeighborRelation::More => Some(c + 1),
+            NeighborRelation::Same => Some(c),
+        };
+
+        let x = step(self.get_component(0), neighbor.x)?;
+        let y = step(self.get_component(1), neighbor.y)?;
+        Some(QuadKey::from_components(x, y, level))
+    }
+}
+
+impl QuadKey {
+    pub fn get_component(&self, component: usize) -> u32 {
+        let level = self.level();
+        let length_mask: u64 = (1 << (2 * level)) - 1;
+
+        // Drop the root bit, then line the component up with the low bit of each pair
+        let mut c = (self.0 & length_mask) >> (1 - component);
+        c &= DILATION_MASKS[4];
+        for i in 0..4 {
+            let gap_size = 1 << i;
+            c = (c | c >> gap_size) & DILATION_MASKS[3 - i];
+        }
+        c = (c | c >> 16) & 0xffff_ffff;
+        c as u32
+    }
+
+    pub fn dilate_component(c: u32) -> u64 {
+        let mut component = c as u64;
+        for (i, mask) in DILATION_MASKS.iter().enumerate() {
+            let gap_size = 16 >> i;
+            component = (component | component << gap_size) & mask;
+        }
+        component
+    }
+
+    pub fn from_components(x: u32, y: u32, level: u32) -> QuadKey {
+        let root = 1 << (2 * level);
+        QuadKey(root | QuadKey::dilate_component(x) << 1 | QuadKey::dilate_component(y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level() {
+        let mut k = QuadKey::root_key();
+        assert_eq!(k.level(), 0);
+        for i in 0..QUAD_COMPONENT_BIT_COUNT {
+            k = k.child_key((i % 4) as u64);
+            assert_eq!(k.level(), i + 1);
+        }
+    }
+
+    #[test]
+    fn components() {
+        let mut k = QuadKey::root_key();
+        for i in 0..16 {
+            k = k.child_key(i % 4);
+        }
+        assert_eq!(k.get_component(0), 0b0011001100110011);
+        assert_eq!(k.get_component(1), 0b0101010101010101);
+
+        let x = (1 << QUAD_COMPONENT_BIT_COUNT) - 1;
+        let y = 0b1011001;
+        let key = QuadKey::from_components(x, y, QUAD_COMPONENT_BIT_COUNT);
+        assert_eq!(key.level(), QUAD_COMPONENT_BIT_COUNT);
+        assert_eq!(key.get_component(0), x);
+        assert_eq!(key.get_component(1), y);
+
+        assert_eq!(QuadKey::from_components(0, 0, 0), QuadKey::root_key());
+        assert_eq!(QuadKey::from_components(0b10, 0b11, 2).0, 0b11101);
+    }
+
+    #[test]
+    fn neighbors() {
+        let key = QuadKey::root_key().child_key(0);
+        let neighbor = |x, y, z| Neighbor::from_components(x, y, z);
+        assert_eq!(
+            key.neighbor_key(neighbor(
+                NeighborRelation::More,
+                NeighborRelation::More,
+                NeighborRelation::Same
+            )),
+            Some(QuadKey(0b111))
+        );
+        assert_eq!(
+            key.neighbor_key(neighbor(
+                NeighborRelation::Less,
+                NeighborRelation::Same,
+                NeighborRelation::Same
+            )),
+            None
+        );
+        assert_eq!(
+            key.neighbor_key(neighbor(
+                NeighborRelation::Same,
+                NeighborRelation::Same,
+                NeighborRelation::More
+            )),
+            None
+        );
+
+        let key = QuadKey::from_components(5, 2, 3);
+        assert_eq!(
+            key.neighbor_key(neighbor(
+                NeighborRelation::Less,
+                NeighborRelation::More,
+                NeighborRelation::Same
+            )),
+            Some(QuadKey::from_components(4, 3, 3))
+        );
+        assert_eq!(
+            QuadKey::from_components(7, 2, 3).neighbor_key(neighbor(
+                NeighborRelation::More,
+                NeighborRelation::Same,
+                NeighborRelation::Same
+            )),
+            None
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use crate::function_ir::Node;
+use std::collections::HashMap;
+
+/// Index of a node in a `Dag`
+pub type NodeId = usize;
+
+/// A node whose children are other nodes of the same `Dag`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DagNode {
+    Add(NodeId, NodeId),
+    Sub(NodeId, NodeId),
+    Mul(NodeId, NodeId),
+    Div(NodeId, NodeId),
+    Exp(NodeId, NodeId),
+    Min(NodeId, NodeId),
+    Max(NodeId, NodeId),
+    Atan2(NodeId, NodeId),
+    Sin(NodeId),
+    Cos(NodeId),
+    Tan(NodeId),
+    Exponential(NodeId),
+    Ln(NodeId),
+    Sqrt(NodeId),
+    Abs(NodeId),
+    Variable(char),
+    Parameter(String),
+    /// The bits of the constant, so that nodes can be hashed
+    Constant(u32),
+}
+
+impl DagNode {
+    pub fn children(&self) -> Vec<NodeId> {
+        match *self {
+            DagNode::Add(n1, n2)
+            | DagNode::Sub(n1, n2)
+            | DagNode::Mul(n1, n2)
+            | DagNode::Div(n1, n2)
+            | DagNode::Exp(n1, n2)
+            | DagNode::Min(n1, n2)
+            | DagNode::Max(n1, n2)
+            | DagNode::Atan2(n1, n2) => vec![n1, n2],
+            DagNode::Sin(n)
+            | DagNode::Cos(n)
+            | DagNode::Tan(n)
+            | DagNode::Exponential(n)
+            | DagNode::Ln(n)
+            | DagNode::Sqrt(n)
+            | DagNode::Abs(n) => vec![n],
+            DagNode::Variable(_) | DagNode::Parameter(_) | DagNode::Constant(_) => vec![],
+        }
+    }
+}
+
+/// Hash consed expressions, where every distinct subexpression is stored once.
+/// Children always come before their parents, so evaluating the nodes in order
+/// computes each shared subexpression a single time.
+#[derive(Clone, Debug, Default)]
+pub struct Dag {
+    nodes: Vec<DagNode>,
+    ids: HashMap<DagNode, NodeId>,
+}
+
+impl Dag {
+    pub fn new() -> Dag {
+        Dag::default()
+    }
+
+    /// A dag holding a single expression, along with the id of its root
+    pub fn from_node(node: &Node) -> (Dag, NodeId) {
+        let mut dag = Dag::new();
+        let root = dag.insert(node);
+        (dag, root)
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn node(&self, id: NodeId) -> &DagNode {
+        &self.nodes[id]
+    }
+
+    /// Add an expression, reusing any subexpressions that are already present.
+    /// Operands of commutative operations are put in a fixed order first, so
+    /// `x * y` and `y * x` are shared too.
+    pub fn insert(&mut self, node: &Node) -> NodeId {
+        let dag_node = match *node {
+            Node::Add(ref n1, ref n2) => {
+                let (a, b) = self.insert_commutative(n1, n2);
+                DagNode::Add(a, b)
+            }
+            Node::Mul(ref n1, ref n2) => {
+                let (a, b) = self.insert_commutative(n1, n2);
+                DagNode::Mul(a, b)
+            }
+            Node::Min(ref n1, ref n2) => {
+                let (a, b) = self.insert_commutative(n1, n2);
+                DagNode::Min(a, b)
+            }
+            Node::Max(ref n1, ref n2) => {
+                let (a, b) = self.insert_commutative(n1, n2);
+                DagNode::Max(a, b)
+            }
+            Node::Sub(ref n1, ref n2) => DagNode::Sub(self.insert(n1), self.insert(n2)),
+            Node::Div(ref n1, ref n2) => DagNode::Div(self.insert(n1), self.insert(n2)),
+            Node::Exp(ref n1, ref n2) => DagNode::Exp(self.insert(n1), self.insert(n2)),
+            Node::Atan2(ref n1, ref n2) => DagNode::Atan2(self.insert(n1), self.insert(n2)),
+            Node::Sin(ref n) => DagNode::Sin(self.insert(n)),
+            Node::Cos(ref n) => DagNode::Cos(self.insert(n)),
+            Node::Tan(ref n) => DagNode::Tan(self.insert(n)),
+            Node::Exponential(ref n) => DagNode::Exponential(self.insert(n)),
+            Node::Ln(ref n) => DagNode::Ln(self.insert(n)),
+            Node::Sqrt(ref n) => DagNode::Sqrt(self.insert(n)),
+            Node::Abs(ref n) => DagNode::Abs(self.insert(n)),
+            Node::Variable(c) => DagNode::Variable(c),
+            Node::Parameter(ref name) => DagNode::Parameter(name.clone()),
+            Node::Constant(c) => DagNode::Constant(c.to_bits()),
+        };
+
+        if let Some(&id) = self.ids.get(&dag_node) {
+            return id;
+        }
+
+        let id = self.nodes.len();
+        self.nodes.push(dag_node.clone());
+        self.ids.insert(dag_node, id);
+        id
+    }
+
+    fn insert_commutative(&mut self, n1: &Node, n2: &Node) -> (NodeId, NodeId) {
+        let a = self.insert(n1);
+        let b = self.insert(n2);
+        (a.min(b), a.max(b))
+    }
+
+    /// Rebuild the expression tree for a node
+    pub fn to_node(&self, id: NodeId) -> Node {
+        let b = |id| Box::new(self.to_node(id));
+        match self.nodes[id] {
+            DagNode::Add(n1, n2) => Node::Add(b(n1), b(n2)),
+            DagNode::Sub(n1, n2) => Node::Sub(b(n1), b(n2)),
+            DagNode::Mul(n1, n2) => Node::Mul(b(n1), b(n2)),
+            DagNode::Div(n1, n2) => Node::Div(b(n1), b(n2)),
+            DagNode::Exp(n1, n2) => Node::Exp(b(n1), b(n2)),
+            DagNode::Min(n1, n2) => Node::Min(b(n1), b(n2)),
+            DagNode::Max(n1, n2) => Node::Max(b(n1), b(n2)),
+            DagNode::Atan2(n1, n2) => Node::Atan2(b(n1), b(n2)),
+            DagNode::Sin(n) => Node::Sin(b(n)),
+            DagNode::Cos(n) => Node::Cos(b(n)),
+            DagNode::Tan(n) => Node::Tan(b(n)),
+            DagNode::Exponential(n) => Node::Exponential(b(n)),
+            DagNode::Ln(n) => Node::Ln(b(n)),
+            DagNode::Sqrt(n) => Node::Sqrt(b(n)),
+            DagNode::Abs(n) => Node::Abs(b(n)),
+            DagNode::Variable(c) => Node::Variable(c),
+            DagNode::Parameter(ref name) => Node::Parameter(name.clone()),
+            DagNode::Constant(bits) => Node::Constant(f32::from_bits(bits)),
+        }
+    }
+
+    /// How many times each node is used as an operand by root or the nodes below it
+    pub fn uses(&self, root: NodeId) -> Vec<usize> {
+        let mut uses = vec![0; self.nodes.len()];
+        let mut reachable = vec![false; self.nodes.len()];
+        reachable[root] = true;
+
+        // Parents come after their children, so walking backwards sees every
+        // parent of a node before the node itself
+        for id in (0..=root).rev() {
+            if !reachable[id] {
+                continue;
+            }
+            match self.nodes[id] {
+                // Squares only read their operand once
+                DagNode::Mul(n1, n2) if n1 == n2 => {
+                    uses[n1] += 1;
+                    reachable[n1] = true;
+                }
+                ref node => {
+                    for child in node.children() {
+                        uses[child] += 1;
+                        reachable[child] = true;
+                    }
+                }
+            }
+        }
+
+        uses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_expression;
+
+    fn build(expression: &str) -> (Dag, NodeId, Node) {
+        let input: Vec<char> = expression.chars().collect();
+        let root = *parse_expression(&input, 0).unwrap();
+        let (dag, id) = Dag::from_node(&root);
+        (dag, id, root)
+    }
+
+    #[test]
+    fn test_sharing() {
+        // x, y, x * y, sin(x * y), 2, sin(x * y)^2, and the sum
+        let (shared, root, _) = build("sin(x * y) + sin(y * x)^2");
+        assert_eq!(shared.len(), 7);
+
+        let uses = shared.uses(root);
+        let sin = shared
+            .nodes
+            .iter()
+            .position(|n| matches!(n, DagNode::Sin(_)))
+            .unwrap();
+        assert_eq!(uses[sin], 2);
+        assert_eq!(uses[root], 0);
+
+        // Without sharing the tree comes back unchanged
+        let (unshared, root, node) = build("x * (y + 2) - sin(z)");
+        assert_eq!(unshared.len(), 8);
+        assert_eq!(unshared.to_node(root), node);
+    }
+}
@@ -0,0 +1,238 @@
+use crate::function_ir::Node;
+
+fn constant(c: f32) -> Box<Node> {
+    Box::new(Node::Constant(c))
+}
+
+fn boxed(n: &Node) -> Box<Node> {
+    Box::new(n.clone())
+}
+
+impl Node {
+    /// Returns the simplified partial derivative with respect to the given variable.
+    /// Parameters are treated as constants.
+    pub fn derivative(&self, var: char) -> Node {
+        self.differentiate(var).simplify()
+    }
+
+    /// True if the variable appears anywhere in the expression
+    pub fn depends_on(&self, var: char) -> bool {
+        match *self {
+            Node::Variable(c) => c == var,
+            _ => self.children().iter().any(|child| child.depends_on(var)),
+        }
+    }
+
+    fn differentiate(&self, var: char) -> Node {
+        match *self {
+            Node::Add(ref u, ref v) => Node::Add(
+                Box::new(u.differentiate(var)),
+                Box::new(v.differentiate(var)),
+            ),
+            Node::Sub(ref u, ref v) => Node::Sub(
+                Box::new(u.differentiate(var)),
+                Box::new(v.differentiate(var)),
+            ),
+            // u'v + uv'
+            Node::Mul(ref u, ref v) => Node::Add(
+                Box::new(Node::Mul(Box::new(u.differentiate(var)), v.clone())),
+                Box::new(Node::Mul(u.clone(), Box::new(v.differentiate(var)))),
+            ),
+            // (u'v - uv') / v^2
+            Node::Div(ref u, ref v) => Node::Div(
+                Box::new(Node::Sub(
+                    Box::new(Node::Mul(Box::new(u.differentiate(var)), v.clone())),
+                    Box::new(Node::Mul(u.clone(), Box::new(v.differentiate(var)))),
+                )),
+                Box::new(Node::Exp(v.clone(), constant(2.0))),
+            ),
+            Node::Exp(ref u, ref v) => differentiate_power(u, v, var),
+            // cos(u) u'
+            Node::Sin(ref u) => Node::Mul(
+                Box::new(Node::Cos(u.clone())),
+                Box::new(u.differentiate(var)),
+            ),
+            // -sin(u) u'
+            Node::Cos(ref u) => Node::Mul(
+                Box::new(Node::Mul(constant(-1.0), Box::new(Node::Sin(u.clone())))),
+                Box::new(u.differentiate(var)),
+            ),
+            // u' / cos(u)^2
+            Node::Tan(ref u) => Node::Div(
+                Box::new(u.differentiate(var)),
+                Box::new(Node::Exp(Box::new(Node::Cos(u.clone())), constant(2.0))),
+            ),
+            // e^u u'
+            Node::Exponential(ref u) => Node::Mul(
+                Box::new(Node::Exponential(u.clone())),
+                Box::new(u.differentiate(var)),
+            ),
+            // u' / u
+            Node::Ln(ref u) => Node::Div(Box::new(u.differentiate(var)), u.clone()),
+            // u' / (2 sqrt(u))
+            Node::Sqrt(ref u) => Node::Div(
+                Box::new(u.differentiate(var)),
+                Box::new(Node::Mul(constant(2.0), Box::new(Node::Sqrt(u.clone())))),
+            ),
+            // u u' / |u|, undefined where u is zero
+            Node::Abs(ref u) => Node::Div(
+                Box::new(Node::Mul(u.clone(), Box::new(u.differentiate(var)))),
+                Box::new(Node::Abs(u.clone())),
+            ),
+            Node::Min(ref u, ref v) => differentiate_extremum(u, v, var, -1.0),
+            Node::Max(ref u, ref v) => differentiate_extremum(u, v, var, 1.0),
+            // (u' v - u v') / (u^2 + v^2)
+            Node::Atan2(ref u, ref v) => Node::Div(
+                Box::new(Node::Sub(
+                    Box::new(Node::Mul(Box::new(u.differentiate(var)), v.clone())),
+                    Box::new(Node::Mul(u.clone(), Box::new(v.differentiate(var)))),
+                )),
+                Box::new(Node::Add(
+                    Box::new(Node::Exp(u.clone(), constant(2.0))),
+                    Box::new(Node::Exp(v.clone(), constant(2.0))),
+                )),
+            ),
+            Node::Variable(c) => Node::Constant(if c == var { 1.0 } else { 0.0 }),
+            Node::Parameter(_) | Node::Constant(_) => Node::Constant(0.0),
+        }
+    }
+}
+
+fn differentiate_power(u: &Node, v: &Node, var: char) -> Node {
+    if !v.depends_on(var) {
+        // v u^(v - 1) u'
+        Node::Mul(
+            Box::new(Node::Mul(
+                boxed(v),
+                Box::new(Node::Exp(
+                    boxed(u),
+                    Box::new(Node::Sub(boxed(v), constant(1.0))),
+                )),
+            )),
+            Box::new(u.differentiate(var)),
+        )
+    } else if !u.depends_on(var) {
+        // u^v ln(u) v'
+        Node::Mul(
+            Box::new(Node::Mul(
+                Box::new(Node::Exp(boxed(u), boxed(v))),
+                Box::new(Node::Ln(boxed(u))),
+            )),
+            Box::new(v.differentiate(var)),
+        )
+    } else {
+        // u^v (v' ln(u) + v u' / u)
+        Node::Mul(
+            Box::new(Node::Exp(boxed(u), boxed(v))),
+            Box::new(Node::Add(
+                Box::new(Node::Mul(
+                    Box::new(v.differentiate(var)),
+                    Box::new(Node::Ln(boxed(u))),
+                )),
+                Box::new(Node::Div(
+                    Box::new(Node::Mul(boxed(v), Box::new(u.differentiate(var)))),
+                    boxed(u),
+                )),
+            )),
+        )
+    }
+}
+
+/// Uses min(u, v) = (u + v - |u - v|) / 2 and max(u, v) = (u + v + |u - v|) / 2,
+/// sign selects between the two
+fn differentiate_extremum(u: &Node, v: &Node, var: char, sign: f32) -> Node {
+    let du = u.differentiate(var);
+    let dv = v.differentiate(var);
+    let difference = Node::Sub(boxed(u), boxed(v));
+    let abs_derivative = Node::Div(
+        Box::new(Node::Mul(
+            Box::new(difference.clone()),
+            Box::new(Node::Sub(Box::new(du.clone()), Box::new(dv.clone()))),
+        )),
+        Box::new(Node::Abs(Box::new(difference))),
+    );
+
+    Node::Mul(
+        constant(0.5),
+        Box::new(Node::Add(
+            Box::new(Node::Add(Box::new(du), Box::new(dv))),
+            Box::new(Node::Mul(constant(sign), Box::new(abs_derivative))),
+        )),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::function::Function;
+    use crate::parser::parse_expression;
+
+    fn derivative(expression: &str, var: char) -> String {
+        let input: Vec<char> = expression.chars().collect();
+        let root = parse_expression(&input, 0).unwrap();
+        format!("{}", root.derivative(var))
+    }
+
+    #[test]
+    fn test_derivative() {
+        assert_eq!(derivative("x^2 + y^2 + z^2 - 100", 'x'), "2 * x");
+        assert_eq!(derivative("x^2 + y^2 + z^2 - 100", 'y'), "2 * y");
+        assert_eq!(derivative("3 * x * y", 'y'), "3 * x");
+        assert_eq!(derivative("sin(x)", 'x'), "cos(x)");
+        assert_eq!(derivative("radius * z", 'z'), "radius");
+        assert_eq!(derivative("x + y", 'z'), "0");
+    }
+
+    #[test]
+    fn test_derivative_matches_finite_difference() {
+        let expressions = [
+            "x * y - z / x",
+            "x ^ 3 - 2 * y ^ 2 + z ^ 0.5",
+            "x ^ y + 2 ^ z + y ^ x",
+            "sin(x * y) + cos(z) * tan(y)",
+            "exp(x * z) + ln(y) + sqrt(x + y)",
+            "abs(x - z) + min(x, y) * max(y, z)",
+            "atan2(y, x) - atan2(z, -x * y)",
+        ];
+        let h = 0.001;
+
+        for expression in expressions.iter() {
+            let input: Vec<char> = expression.chars().collect();
+            let root = parse_expression(&input, 0).unwrap();
+
+            for &(x, y, z) in &[(1.13, 0.732, 0.4939), (0.5, 1.25, 0.75)] {
+                let f = |x, y, z| Function::evaluate(&*root, x, y, z);
+                let expected = [
+                    (f(x + h, y, z) - f(x - h, y, z)) / (2.0 * h),
+                    (f(x, y + h, z) - f(x, y - h, z)) / (2.0 * h),
+                    (f(x, y, z + h) - f(x, y, z - h)) / (2.0 * h),
+                ];
+
+                for (var, expected) in ['x', 'y', 'z'].iter().zip(expected.iter()) {
+                    let result: f32 = Function::evaluate(&root.derivative(*var), x, y, z);
+                    assert!(
+                        (result - expected).abs() < 0.01 * expected.abs().max(1.0),
+                        "d/d{} {} = {}, expected {}",
+                        var,
+                        expression,
+                        result,
+                        expected
+                    );
+                }
+
+                let gradient = Function::gradient(&*root, x, y, z);
+                assert_similiar!(
+                    gradient.x,
+                    Function::evaluate(&root.derivative('x'), x, y, z)
+                );
+                assert_similiar!(
+                    gradient.y,
+                    Function::evaluate(&root.derivative('y'), x, y, z)
+                );
+                assert_similiar!(
+                    gradient.z,
+                    Function::evaluate(&root.derivative('z'), x, y, z)
+                );
+            }
+        }
+    }
+}
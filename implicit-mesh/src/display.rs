@@ -0,0 +1,352 @@
+use crate::function::Relation;
+use crate::function_ir::Node;
+use std::fmt;
+
+// Binding strength of each kind of node, used to decide where parentheses
+// are needed. These follow the parser's operator table, atoms are anything
+// that never needs parentheses.
+const SUM: u8 = 1;
+const PRODUCT: u8 = 2;
+const NEGATION: u8 = 3;
+const POWER: u8 = 4;
+const FRACTION: u8 = 5;
+const ATOM: u8 = 6;
+
+/// The operand of a unary minus, if the node is one the parser would produce from
+/// `-operand`. Negative constants are left alone, they print as `-c` by themselves.
+fn negated(node: &Node) -> Option<&Node> {
+    match *node {
+        Node::Mul(ref n1, ref n2) if **n1 == Node::Constant(-1.0) => match **n2 {
+            Node::Constant(c) if c < 0.0 => None,
+            _ => Some(n2),
+        },
+        _ => None,
+    }
+}
+
+fn precedence(node: &Node) -> u8 {
+    if negated(node).is_some() {
+        return NEGATION;
+    }
+
+    match *node {
+        Node::Constant(c) if c < 0.0 => NEGATION,
+        Node::Add(..) | Node::Sub(..) => SUM,
+        Node::Mul(..) | Node::Div(..) => PRODUCT,
+        Node::Exp(..) => POWER,
+        _ => ATOM,
+    }
+}
+
+/// Writes the expression with the fewest parentheses needed for the parser to
+/// produce the same tree again. Negative constants are written with a leading
+/// minus, which parses back as a multiplication by -1.
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(n) = negated(self) {
+            write!(f, "-")?;
+            return write_operand(f, n, NEGATION);
+        }
+
+        match *self {
+            Node::Add(ref n1, ref n2) => write_binary(f, n1, " + ", n2, SUM, SUM + 1),
+            Node::Sub(ref n1, ref n2) => write_binary(f, n1, " - ", n2, SUM, SUM + 1),
+            Node::Mul(ref n1, ref n2) => write_binary(f, n1, " * ", n2, PRODUCT, PRODUCT + 1),
+            Node::Div(ref n1, ref n2) => write_binary(f, n1, " / ", n2, PRODUCT, PRODUCT + 1),
+            // ^ is right associative
+            Node::Exp(ref n1, ref n2) => write_binary(f, n1, "^", n2, POWER + 1, POWER),
+            Node::Sin(ref n) => write!(f, "sin({})", n),
+            Node::Cos(ref n) => write!(f, "cos({})", n),
+            Node::Tan(ref n) => write!(f, "tan({})", n),
+            Node::Exponential(ref n) => write!(f, "exp({})", n),
+            Node::Ln(ref n) => write!(f, "ln({})", n),
+            Node::Sqrt(ref n) => write!(f, "sqrt({})", n),
+            Node::Abs(ref n) => write!(f, "abs({})", n),
+            Node::Min(ref n1, ref n2) => write!(f, "min({}, {})", n1, n2),
+            Node::Max(ref n1, ref n2) => write!(f, "max({}, {})", n1, n2),
+            Node::Atan2(ref n1, ref n2) => write!(f, "atan2({}, {})", n1, n2),
+            Node::Variable(c) => write!(f, "{}", c),
+            Node::Parameter(ref name) => write!(f, "{}", name),
+            Node::Constant(c) => write!(f, "{}", c),
+        }
+    }
+}
+
+/// For left associative operations the left operand can bind as loosely as the
+/// operation itself, while the right operand has to bind tighter, and the other way
+/// around for right associative ones. The parser takes a unary minus anywhere an
+/// operand can start, so a negation on the right never needs parentheses.
+fn write_binary(
+    f: &mut fmt::Formatter,
+    n1: &Node,
+    op: &str,
+    n2: &Node,
+    left_precedence: u8,
+    right_precedence: u8,
+) -> fmt::Result {
+    write_operand(f, n1, left_precedence)?;
+    write!(f, "{}", op)?;
+    if precedence(n2) == NEGATION {
+        write!(f, "{}", n2)
+    } else {
+        write_operand(f, n2, right_precedence)
+    }
+}
+
+fn write_operand(f: &mut fmt::Formatter, n: &Node, min_precedence: u8) -> fmt::Result {
+    if precedence(n) < min_precedence {
+        write!(f, "({})", n)
+    } else {
+        write!(f, "{}", n)
+    }
+}
+
+impl fmt::Display for Relation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match *self {
+            Relation::Equal => "=",
+            Relation::Less => "<",
+            Relation::LessEqual => "<=",
+            Relation::Greater => ">",
+            Relation::GreaterEqual => ">=",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+impl Relation {
+    pub fn to_latex(&self) -> &'static str {
+        match *self {
+            Relation::Equal => "=",
+            Relation::Less => "<",
+            Relation::LessEqual => "\\le",
+            Relation::Greater => ">",
+            Relation::GreaterEqual => "\\ge",
+        }
+    }
+}
+
+pub(crate) static GREEK_LETTERS: [&str; 24] = [
+    "alpha", "beta", "gamma", "delta", "epsilon", "zeta", "eta", "theta", "iota", "kappa",
+    "lambda", "mu", "nu", "xi", "omicron", "pi", "rho", "sigma", "tau", "upsilon", "phi", "chi",
+    "psi", "omega",
+];
+
+fn latex_identifier(name: &str) -> String {
+    if GREEK_LETTERS.contains(&name) {
+        format!("\\{}", name)
+    } else if name.chars().count() == 1 {
+        name.to_string()
+    } else {
+        format!("\\mathrm{{{}}}", name)
+    }
+}
+
+/// In LaTeX a leading minus reads as applying after powers, so negations are
+/// treated like sums there, and fractions need grouping when raised to a power
+fn latex_precedence(node: &Node) -> u8 {
+    if negated(node).is_some() {
+        return SUM;
+    }
+
+    match *node {
+        Node::Constant(c) if c < 0.0 => SUM,
+        Node::Add(..) | Node::Sub(..) => SUM,
+        Node::Mul(..) => PRODUCT,
+        Node::Exp(..) => POWER,
+        Node::Div(..) => FRACTION,
+        _ => ATOM,
+    }
+}
+
+fn latex_operand(n: &Node, min_precedence: u8) -> String {
+    if latex_precedence(n) < min_precedence {
+        format!("\\left({}\\right)", n.to_latex())
+    } else {
+        n.to_latex()
+    }
+}
+
+fn latex_binary(n1: &Node, op: &str, n2: &Node, left_precedence: u8) -> String {
+    format!(
+        "{}{}{}",
+        latex_operand(n1, left_precedence),
+        op,
+        latex_operand(n2, left_precedence + 1)
+    )
+}
+
+impl Node {
+    /// Render the expression as LaTeX math, without delimiters
+    pub fn to_latex(&self) -> String {
+        if let Some(n) = negated(self) {
+            return format!("-{}", latex_operand(n, PRODUCT));
+        }
+
+        match *self {
+            Node::Add(ref n1, ref n2) => latex_binary(n1, " + ", n2, SUM),
+            Node::Sub(ref n1, ref n2) => latex_binary(n1, " - ", n2, SUM),
+            Node::Mul(ref n1, ref n2) => match (&**n1, &**n2) {
+                // Write coefficients next to what they multiply, i.e. 2x
+                (Node::Constant(c), Node::Variable(_))
+                | (Node::Constant(c), Node::Parameter(_))
+                | (Node::Constant(c), Node::Exp(..))
+                    if *c >= 0.0 =>
+                {
+                    format!("{}{}", c, latex_operand(n2, POWER))
+                }
+                _ => latex_binary(n1, " \\cdot ", n2, PRODUCT),
+            },
+            Node::Div(ref n1, ref n2) => {
+                format!("\\frac{{{}}}{{{}}}", n1.to_latex(), n2.to_latex())
+            }
+            Node::Exp(ref n1, ref n2) => {
+                format!("{}^{{{}}}", latex_operand(n1, ATOM), n2.to_latex())
+            }
+            Node::Sin(ref n) => format!("\\sin\\left({}\\right)", n.to_latex()),
+            Node::Cos(ref n) => format!("\\cos\\left({}\\right)", n.to_latex()),
+            Node::Tan(ref n) => format!("\\tan\\left({}\\right)", n.to_latex()),
+            Node::Exponential(ref n) => format!("e^{{{}}}", n.to_latex()),
+            Node::Ln(ref n) => format!("\\ln\\left({}\\right)", n.to_latex()),
+            Node::Sqrt(ref n) => format!("\\sqrt{{{}}}", n.to_latex()),
+            Node::Abs(ref n) => format!("\\left|{}\\right|", n.to_latex()),
+            Node::Min(ref n1, ref n2) => {
+                format!("\\min\\left({}, {}\\right)", n1.to_latex(), n2.to_latex())
+            }
+            Node::Max(ref n1, ref n2) => {
+                format!("\\max\\left({}, {}\\right)", n1.to_latex(), n2.to_latex())
+            }
+            Node::Atan2(ref n1, ref n2) => format!(
+                "\\operatorname{{atan2}}\\left({}, {}\\right)",
+                n1.to_latex(),
+                n2.to_latex()
+            ),
+            Node::Variable(c) => c.to_string(),
+            Node::Parameter(ref name) => latex_identifier(name),
+            Node::Constant(c) => c.to_string(),
+        }
+    }
+}
+
+/// The parser turns `lhs op rhs` into `lhs - rhs` compared against zero,
+/// so split the difference back out when printing a constraint
+fn constraint_sides(f: &Node) -> (&Node, Option<&Node>) {
+    match *f {
+        Node::Sub(ref lhs, ref rhs) => (lhs, Some(rhs)),
+        _ => (f, None),
+    }
+}
+
+/// Write a parsed constraint in the `lhs op rhs` form it was most likely entered as
+pub fn constraint_to_string(f: &Node, relation: Relation) -> String {
+    match constraint_sides(f) {
+        (lhs, Some(rhs)) => format!("{} {} {}", lhs, relation, rhs),
+        (lhs, None) => format!("{} {} 0", lhs, relation),
+    }
+}
+
+/// The LaTeX version of `constraint_to_string`
+pub fn constraint_to_latex(f: &Node, relation: Relation) -> String {
+    match constraint_sides(f) {
+        (lhs, Some(rhs)) => format!(
+            "{} {} {}",
+            lhs.to_latex(),
+            relation.to_latex(),
+            rhs.to_latex()
+        ),
+        (lhs, None) => format!("{} {} 0", lhs.to_latex(), relation.to_latex()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_expression, parse_latex_expression, parse_relation};
+
+    fn parse(expression: &str) -> Node {
+        let input: Vec<char> = expression.chars().collect();
+        *parse_expression(&input, 0).unwrap()
+    }
+
+    #[test]
+    fn test_display() {
+        let cases = [
+            (
+                "x^2 + sin(y) * 2 - min(z, a) / 4",
+                "x^2 + sin(y) * 2 - min(z, a) / 4",
+            ),
+            ("((x + y) + z)", "x + y + z"),
+            ("x + (y + z)", "x + (y + z)"),
+            ("x - (y - z)", "x - (y - z)"),
+            ("(x * y) / (z * 2)", "x * y / (z * 2)"),
+            ("(x + 1) ^ (y * 2)", "(x + 1)^(y * 2)"),
+            ("(x ^ 2) ^ 3", "(x^2)^3"),
+            ("-x ^ 2", "-x^2"),
+            ("(-x) ^ 2", "(-x)^2"),
+            ("x ^ y ^ z", "x^y^z"),
+            ("x ^ -(y + 1)", "x^-(y + 1)"),
+            ("-(x * y)", "-(x * y)"),
+            ("2x(y + 1)", "2 * x * (y + 1)"),
+            ("-sin(x) * -3", "-sin(x) * -3"),
+            ("2 * (-x)", "2 * -x"),
+        ];
+
+        for (input, expected) in cases.iter() {
+            let root = parse(input);
+            let printed = format!("{}", root);
+            assert_eq!(printed, *expected);
+
+            // Printing is minimal but never changes the tree
+            assert_eq!(parse(&printed), root);
+        }
+
+        // Negative constants come back as a multiplication by -1
+        assert_eq!(format!("{}", Node::Constant(-2.5)), "-2.5");
+    }
+
+    #[test]
+    fn test_latex() {
+        let cases = [
+            ("x^2 + y^2 + z^2", "x^{2} + y^{2} + z^{2}"),
+            ("2 * x - 3 * y ^ 2", "2x - 3y^{2}"),
+            ("x * y / (z + 1)", "\\frac{x \\cdot y}{z + 1}"),
+            ("(x / 2) ^ 2", "\\left(\\frac{x}{2}\\right)^{2}"),
+            ("-x ^ 2", "-x^{2}"),
+            ("(-x) ^ 2", "\\left(-x\\right)^{2}"),
+            (
+                "sqrt(abs(x)) + exp(-y)",
+                "\\sqrt{\\left|x\\right|} + e^{-y}",
+            ),
+            (
+                "sin(theta) * radius",
+                "\\sin\\left(\\theta\\right) \\cdot \\mathrm{radius}",
+            ),
+            ("atan2(y, x)", "\\operatorname{atan2}\\left(y, x\\right)"),
+            (
+                "max(x, ln(y))",
+                "\\max\\left(x, \\ln\\left(y\\right)\\right)",
+            ),
+        ];
+
+        for (input, expected) in cases.iter() {
+            let root = parse(input);
+            let latex = root.to_latex();
+            assert_eq!(latex, *expected);
+
+            // The LaTeX front end reads it back as the same tree
+            let latex: Vec<char> = latex.chars().collect();
+            assert_eq!(*parse_latex_expression(&latex, 0).unwrap(), root);
+        }
+    }
+
+    #[test]
+    fn test_constraint() {
+        let input: Vec<char> = "x^2 + y^2 <= r^2".chars().collect();
+        let (f, relation) = parse_relation(&input, 0).unwrap();
+        assert_eq!(constraint_to_string(&f, relation), "x^2 + y^2 <= r^2");
+        assert_eq!(
+            constraint_to_latex(&f, relation),
+            "x^{2} + y^{2} \\le r^{2}"
+        );
+    }
+}
@@ -1,10 +1,75 @@
+use crate::bounding_box::BoundingBox;
 use crate::interval::Interval;
+use crate::scalar::Scalar;
+use cgmath::Vector3;
 use std::collections::HashMap;
 
-pub trait Function: Sized {
-    fn evaluate(&self, x: f32, y: f32, z: f32) -> f32;
+/// Values for the named, non axis variables of a function
+pub type Parameters = HashMap<String, f32>;
 
-    fn evaluate_interval(&self, bindings: &HashMap<char, Interval>) -> Vec<Interval>;
+/// A function of x, y and z, evaluated in the scalar type `S`
+pub trait Function<S: Scalar = f32>: Sized {
+    fn evaluate(&self, x: S, y: S, z: S) -> S;
+
+    fn evaluate_interval(&self, bindings: &HashMap<char, Interval<S>>) -> Vec<Interval<S>>;
+
+    /// Returns a copy of the function with its parameters replaced by the given values
+    fn bind_parameters(&self, parameters: &Parameters) -> Self;
+
+    /// The gradient at a point, by default estimated with central differences
+    fn gradient(&self, x: S, y: S, z: S) -> Vector3<S> {
+        let h = S::from_f32(0.001);
+        let width = h + h;
+        Vector3::new(
+            (self.evaluate(x + h, y, z) - self.evaluate(x - h, y, z)) / width,
+            (self.evaluate(x, y + h, z) - self.evaluate(x, y - h, z)) / width,
+            (self.evaluate(x, y, z + h) - self.evaluate(x, y, z - h)) / width,
+        )
+    }
+
+    /// An enclosure of each gradient component over the bounding box,
+    /// if the function is able to provide one
+    fn gradient_interval(&self, _bb: &BoundingBox<S>) -> Option<[Interval<S>; 3]> {
+        None
+    }
+
+    /// An enclosure of the function over the bounding box using affine arithmetic,
+    /// which keeps track of how values depend on x, y and z,
+    /// if the function is able to provide one
+    fn affine_interval(&self, _bb: &BoundingBox<S>) -> Option<Interval<S>> {
+        None
+    }
+
+    /// Whether the function is defined at every point. Interval evaluation only
+    /// encloses the values where it is, so a box where i.e. `sqrt(x) * 0 = 0`
+    /// seems to hold everywhere is only trusted for functions that say so.
+    fn is_total(&self) -> bool {
+        false
+    }
+}
+
+/// How a function's value is compared against zero
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Relation {
+    Equal,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl Relation {
+    /// Returns true if every value in the interval satisfies the relation
+    pub fn holds_for<S: Scalar>(&self, interval: &Interval<S>) -> bool {
+        let zero = S::zero();
+        match *self {
+            Relation::Equal => interval.min == zero && interval.max == zero,
+            Relation::Less => interval.max < zero,
+            Relation::LessEqual => interval.max <= zero,
+            Relation::Greater => interval.min > zero,
+            Relation::GreaterEqual => interval.min >= zero,
+        }
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -12,15 +77,20 @@ pub struct ConstFunction {
     pub c: f32,
 }
 
-impl Function for ConstFunction {
-    fn evaluate(&self, _x: f32, _y: f32, _z: f32) -> f32 {
-        self.c
+impl<S: Scalar> Function<S> for ConstFunction {
+    fn evaluate(&self, _x: S, _y: S, _z: S) -> S {
+        S::from_f32(self.c)
+    }
+
+    fn evaluate_interval(&self, _bindings: &HashMap<char, Interval<S>>) -> Vec<Interval<S>> {
+        vec![Interval::constant(S::from_f32(self.c))]
+    }
+
+    fn bind_parameters(&self, _parameters: &Parameters) -> ConstFunction {
+        *self
     }
 
-    fn evaluate_interval(&self, _bindings: &HashMap<char, Interval>) -> Vec<Interval> {
-        vec![Interval {
-            min: self.c,
-            max: self.c,
-        }]
+    fn is_total(&self) -> bool {
+        true
     }
 }
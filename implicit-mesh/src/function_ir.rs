@@ -1,32 +1,61 @@
-use crate::function::Function;
+use crate::bounding_box::BoundingBox;
+use crate::function::{Function, Parameters};
 use crate::interval::*;
-use std::collections::HashMap;
+use crate::scalar::Scalar;
+use cgmath::Vector3;
+use std::collections::{BTreeSet, HashMap};
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum Node {
     Add(Box<Node>, Box<Node>),
     Sub(Box<Node>, Box<Node>),
     Mul(Box<Node>, Box<Node>),
     Div(Box<Node>, Box<Node>),
     Exp(Box<Node>, Box<Node>),
+    Sin(Box<Node>),
+    Cos(Box<Node>),
+    Tan(Box<Node>),
+    Exponential(Box<Node>),
+    Ln(Box<Node>),
+    Sqrt(Box<Node>),
+    Abs(Box<Node>),
+    Min(Box<Node>, Box<Node>),
+    Max(Box<Node>, Box<Node>),
+    /// The angle of the point (x, y) for `Atan2(y, x)`
+    Atan2(Box<Node>, Box<Node>),
     Variable(char),
+    Parameter(String),
     Constant(f32),
 }
 
 impl Node {
-    pub fn evaluate(&self, bindings: &HashMap<char, f32>) -> f32 {
+    pub fn evaluate<S: Scalar>(&self, bindings: &HashMap<char, S>) -> S {
         match *self {
             Node::Add(ref n1, ref n2) => n1.evaluate(&bindings) + n2.evaluate(&bindings),
             Node::Sub(ref n1, ref n2) => n1.evaluate(&bindings) - n2.evaluate(&bindings),
             Node::Mul(ref n1, ref n2) => n1.evaluate(&bindings) * n2.evaluate(&bindings),
             Node::Div(ref n1, ref n2) => n1.evaluate(&bindings) / n2.evaluate(&bindings),
             Node::Exp(ref n1, ref n2) => n1.evaluate(&bindings).powf(n2.evaluate(&bindings)),
-            Node::Constant(c) => c,
-            Node::Variable(v) => bindings.get(&v).unwrap().clone(),
+            Node::Sin(ref n) => n.evaluate(&bindings).sin(),
+            Node::Cos(ref n) => n.evaluate(&bindings).cos(),
+            Node::Tan(ref n) => n.evaluate(&bindings).tan(),
+            Node::Exponential(ref n) => n.evaluate(&bindings).exp(),
+            Node::Ln(ref n) => n.evaluate(&bindings).ln(),
+            Node::Sqrt(ref n) => n.evaluate(&bindings).sqrt(),
+            Node::Abs(ref n) => n.evaluate(&bindings).abs(),
+            Node::Min(ref n1, ref n2) => n1.evaluate(&bindings).min(n2.evaluate(&bindings)),
+            Node::Max(ref n1, ref n2) => n1.evaluate(&bindings).max(n2.evaluate(&bindings)),
+            Node::Atan2(ref n1, ref n2) => n1.evaluate(&bindings).atan2(n2.evaluate(&bindings)),
+            Node::Constant(c) => S::from_f32(c),
+            Node::Variable(v) => bindings.get(&v).cloned().unwrap_or_else(S::nan),
+            Node::Parameter(_) => S::nan(),
         }
     }
 
-    pub fn evaluate_intervals(&self, bindings: &HashMap<char, Interval>) -> Vec<Interval> {
+    pub fn evaluate_intervals<S: Scalar>(
+        &self,
+        bindings: &HashMap<char, Interval<S>>,
+    ) -> Vec<Interval<S>> {
         match *self {
             Node::Add(ref n1, ref n2) => {
                 permute_intervals(&n1, &n2, &bindings, |(interval1, interval2)| {
@@ -38,6 +67,10 @@ impl Node {
                     interval1.sub(interval2)
                 })
             }
+            // A product of the same expression is a square, which can't be negative
+            Node::Mul(ref n1, ref n2) if n1 == n2 => {
+                map_intervals(&n1, &bindings, |interval| interval.powi(2))
+            }
             Node::Mul(ref n1, ref n2) => {
                 permute_intervals(&n1, &n2, &bindings, |(interval1, interval2)| {
                     interval1.mul(interval2)
@@ -53,14 +86,131 @@ impl Node {
                     interval1.div(interval2)
                 })
             }
-            Node::Constant(c) => vec![Interval { min: c, max: c }],
-            Node::Variable(v) => vec![bindings.get(&v).unwrap().clone()],
+            Node::Sin(ref n) => map_intervals(&n, &bindings, |interval| interval.sin()),
+            Node::Cos(ref n) => map_intervals(&n, &bindings, |interval| interval.cos()),
+            Node::Tan(ref n) => map_intervals(&n, &bindings, |interval| interval.tan()),
+            Node::Exponential(ref n) => {
+                map_intervals(&n, &bindings, |interval| interval.exponential())
+            }
+            Node::Ln(ref n) => map_intervals(&n, &bindings, |interval| interval.ln()),
+            Node::Sqrt(ref n) => map_intervals(&n, &bindings, |interval| interval.sqrt()),
+            Node::Abs(ref n) => map_intervals(&n, &bindings, |interval| interval.abs()),
+            Node::Min(ref n1, ref n2) => {
+                permute_intervals(&n1, &n2, &bindings, |(interval1, interval2)| {
+                    interval1.min(interval2)
+                })
+            }
+            Node::Max(ref n1, ref n2) => {
+                permute_intervals(&n1, &n2, &bindings, |(interval1, interval2)| {
+                    interval1.max(interval2)
+                })
+            }
+            Node::Atan2(ref n1, ref n2) => {
+                permute_intervals(&n1, &n2, &bindings, |(interval1, interval2)| {
+                    interval1.atan2(interval2)
+                })
+            }
+            Node::Constant(c) => vec![Interval::constant(S::from_f32(c))],
+            Node::Variable(v) => vec![bindings
+                .get(&v)
+                .cloned()
+                .unwrap_or_else(Interval::unbounded)],
+            // An unbound parameter could be anything
+            Node::Parameter(_) => vec![Interval::unbounded()],
+        }
+    }
+
+    /// Rebuild this node with `f` applied to each of its direct children
+    pub fn map_children<F>(&self, mut f: F) -> Node
+    where
+        F: FnMut(&Node) -> Node,
+    {
+        let mut m = |n: &Box<Node>| Box::new(f(n));
+        match *self {
+            Node::Add(ref n1, ref n2) => Node::Add(m(n1), m(n2)),
+            Node::Sub(ref n1, ref n2) => Node::Sub(m(n1), m(n2)),
+            Node::Mul(ref n1, ref n2) => Node::Mul(m(n1), m(n2)),
+            Node::Div(ref n1, ref n2) => Node::Div(m(n1), m(n2)),
+            Node::Exp(ref n1, ref n2) => Node::Exp(m(n1), m(n2)),
+            Node::Sin(ref n) => Node::Sin(m(n)),
+            Node::Cos(ref n) => Node::Cos(m(n)),
+            Node::Tan(ref n) => Node::Tan(m(n)),
+            Node::Exponential(ref n) => Node::Exponential(m(n)),
+            Node::Ln(ref n) => Node::Ln(m(n)),
+            Node::Sqrt(ref n) => Node::Sqrt(m(n)),
+            Node::Abs(ref n) => Node::Abs(m(n)),
+            Node::Min(ref n1, ref n2) => Node::Min(m(n1), m(n2)),
+            Node::Max(ref n1, ref n2) => Node::Max(m(n1), m(n2)),
+            Node::Atan2(ref n1, ref n2) => Node::Atan2(m(n1), m(n2)),
+            Node::Variable(_) | Node::Parameter(_) | Node::Constant(_) => self.clone(),
+        }
+    }
+
+    /// The direct children of this node
+    pub fn children(&self) -> Vec<&Node> {
+        match *self {
+            Node::Add(ref n1, ref n2)
+            | Node::Sub(ref n1, ref n2)
+            | Node::Mul(ref n1, ref n2)
+            | Node::Div(ref n1, ref n2)
+            | Node::Exp(ref n1, ref n2)
+            | Node::Min(ref n1, ref n2)
+            | Node::Max(ref n1, ref n2)
+            | Node::Atan2(ref n1, ref n2) => vec![n1, n2],
+            Node::Sin(ref n)
+            | Node::Cos(ref n)
+            | Node::Tan(ref n)
+            | Node::Exponential(ref n)
+            | Node::Ln(ref n)
+            | Node::Sqrt(ref n)
+            | Node::Abs(ref n) => vec![n],
+            Node::Variable(_) | Node::Parameter(_) | Node::Constant(_) => vec![],
+        }
+    }
+
+    /// The names of all parameters used in the expression
+    pub fn parameters(&self) -> BTreeSet<String> {
+        let mut result = BTreeSet::new();
+        self.collect_parameters(&mut result);
+        result
+    }
+
+    fn collect_parameters(&self, result: &mut BTreeSet<String>) {
+        if let Node::Parameter(ref name) = *self {
+            result.insert(name.clone());
+        }
+
+        for child in self.children() {
+            child.collect_parameters(result);
+        }
+    }
+
+    /// Replace any bound parameters with constants
+    pub fn bind(&self, parameters: &Parameters) -> Node {
+        match *self {
+            Node::Parameter(ref name) => match parameters.get(name) {
+                Some(value) => Node::Constant(*value),
+                None => self.clone(),
+            },
+            _ => self.map_children(|child| child.bind(parameters)),
+        }
+    }
+
+    /// Replace parameters with expressions. Replacements are not substituted into
+    /// themselves, so a replacement may refer to a parameter of the same name.
+    pub fn substitute(&self, replacements: &HashMap<String, Node>) -> Node {
+        match *self {
+            Node::Parameter(ref name) => match replacements.get(name) {
+                Some(node) => node.clone(),
+                None => self.clone(),
+            },
+            _ => self.map_children(|child| child.substitute(replacements)),
         }
     }
 }
 
-impl Function for Node {
-    fn evaluate(&self, x: f32, y: f32, z: f32) -> f32 {
+impl<S: Scalar> Function<S> for Node {
+    fn evaluate(&self, x: S, y: S, z: S) -> S {
         let mut bindings = HashMap::new();
         bindings.insert('x', x);
         bindings.insert('y', y);
@@ -69,9 +219,29 @@ impl Function for Node {
         self.evaluate(&bindings)
     }
 
-    fn evaluate_interval(&self, bindings: &HashMap<char, Interval>) -> Vec<Interval> {
+    fn evaluate_interval(&self, bindings: &HashMap<char, Interval<S>>) -> Vec<Interval<S>> {
         self.evaluate_intervals(&bindings)
     }
+
+    fn bind_parameters(&self, parameters: &Parameters) -> Node {
+        self.bind(parameters)
+    }
+
+    fn gradient(&self, x: S, y: S, z: S) -> Vector3<S> {
+        self.evaluate_dual(x, y, z).gradient
+    }
+
+    fn gradient_interval(&self, bb: &BoundingBox<S>) -> Option<[Interval<S>; 3]> {
+        Some(self.evaluate_interval_dual(bb).gradient)
+    }
+
+    fn affine_interval(&self, bb: &BoundingBox<S>) -> Option<Interval<S>> {
+        Some(self.evaluate_affine_form(bb).to_interval())
+    }
+
+    fn is_total(&self) -> bool {
+        Node::is_total(self)
+    }
 }
 
 #[cfg(test)]
@@ -85,7 +255,7 @@ mod tests {
         let mut input: Vec<char>;
         let mut root;
         let mut bindings = HashMap::new();
-        bindings.insert('x', 1.13);
+        bindings.insert('x', 1.13f32);
         bindings.insert('y', 4.232);
         bindings.insert('z', 2.0939);
 
@@ -129,7 +299,7 @@ mod tests {
         bindings.insert(
             'x',
             Interval {
-                min: 0.01,
+                min: 0.01f32,
                 max: 3.1,
             },
         );
@@ -212,4 +382,36 @@ mod tests {
         result = root.evaluate_interval(&bindings);
         assert!(result[0].contains_zero());
     }
+
+    #[test]
+    fn test_parameters() {
+        let input: Vec<char> = "a * x^2 + radius - sin(a)".chars().collect();
+        let root = parse_expression(&input, 0).unwrap();
+
+        let names: Vec<String> = root.parameters().into_iter().collect();
+        assert_eq!(names, vec!["a".to_string(), "radius".to_string()]);
+
+        // Unbound parameters don't panic, they are just unknown
+        assert!(Function::evaluate(&*root, 1.0f32, 2.0, 3.0).is_nan());
+        let mut bindings = HashMap::new();
+        bindings.insert('x', Interval::new(-1.0f32, 1.0));
+        let result = root.evaluate_interval(&bindings);
+        assert!(result[0].min.is_infinite() || result[0].min.is_nan());
+
+        let mut parameters = Parameters::new();
+        parameters.insert("a".to_string(), 2.0);
+        parameters.insert("radius".to_string(), 0.5);
+        let bound = Function::<f32>::bind_parameters(&*root, &parameters);
+        assert!(bound.parameters().is_empty());
+        assert_similiar!(
+            Function::evaluate(&bound, 3.0, 0.0, 0.0),
+            18.5 - 2.0f32.sin()
+        );
+
+        // Partially bound expressions keep the rest as parameters
+        parameters.remove("radius");
+        let bound = Function::<f32>::bind_parameters(&*root, &parameters);
+        let names: Vec<String> = bound.parameters().into_iter().collect();
+        assert_eq!(names, vec!["radius".to_string()]);
+    }
 }
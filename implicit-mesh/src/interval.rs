@@ -1,91 +1,619 @@
 use crate::function::Function;
+use crate::scalar::Scalar;
 use itertools::Itertools;
+use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::f32;
 
-#[derive(Copy, Clone, Debug)]
-pub struct Interval {
-    pub min: f32,
-    pub max: f32,
+// Bounds are rounded outward, the lower one towards negative infinity and the
+// upper one towards positive infinity, so an enclosure computed in floating point
+// still contains every real value of the function. The basic operations use error
+// free transformations to tell which way rounding to nearest went, so exact results
+// stay exact. Library functions such as sin aren't correctly rounded, so their
+// results are widened by an ulp, which assumes they are accurate to within one.
+// Turning off the `outward-rounding` feature rounds to nearest instead.
+
+/// Round a lower bound given the error of rounding it to nearest, which is the
+/// exact result minus the rounded one. An unknown (NaN) error always rounds.
+fn round_down<S: Scalar>(value: S, error: S) -> S {
+    if cfg!(feature = "outward-rounding") && (error < S::zero() || error.is_nan()) {
+        value.next_down()
+    } else {
+        value
+    }
+}
+
+fn round_up<S: Scalar>(value: S, error: S) -> S {
+    if cfg!(feature = "outward-rounding") && (error > S::zero() || error.is_nan()) {
+        value.next_up()
+    } else {
+        value
+    }
+}
+
+/// Below the normal range the error terms can't be represented exactly
+fn checked_error<S: Scalar>(result: S, error: S) -> S {
+    if result.abs() < S::min_positive_value() && result != S::zero() {
+        S::nan()
+    } else {
+        error
+    }
+}
+
+/// Arithmetic on infinite operands is exact, but the error terms come out as NaN
+fn has_infinite<S: Scalar>(a: S, b: S) -> bool {
+    a.is_infinite() || b.is_infinite()
+}
+
+/// The error of `a + b` rounded to nearest, using Knuth's two sum
+fn add_error<S: Scalar>(a: S, b: S, sum: S) -> S {
+    if has_infinite(a, b) {
+        return S::zero();
+    }
+    let b_virtual = sum - a;
+    let a_virtual = sum - b_virtual;
+    checked_error(sum, (a - a_virtual) + (b - b_virtual))
+}
+
+fn add_down<S: Scalar>(a: S, b: S) -> S {
+    let sum = a + b;
+    round_down(sum, add_error(a, b, sum))
+}
+
+fn add_up<S: Scalar>(a: S, b: S) -> S {
+    let sum = a + b;
+    round_up(sum, add_error(a, b, sum))
+}
+
+/// Zero times anything is zero, even for the unbounded ends of an interval
+fn mul_error<S: Scalar>(a: S, b: S, product: S) -> S {
+    if a == S::zero() || b == S::zero() || has_infinite(a, b) {
+        S::zero()
+    } else {
+        checked_error(product, a.mul_add(b, -product))
+    }
 }
 
-impl Interval {
-    pub fn new(min: f32, max: f32) -> Interval {
+fn mul_down<S: Scalar>(a: S, b: S) -> S {
+    if a == S::zero() || b == S::zero() {
+        return S::zero();
+    }
+    let product = a * b;
+    round_down(product, mul_error(a, b, product))
+}
+
+fn mul_up<S: Scalar>(a: S, b: S) -> S {
+    if a == S::zero() || b == S::zero() {
+        return S::zero();
+    }
+    let product = a * b;
+    round_up(product, mul_error(a, b, product))
+}
+
+/// The remainder `a - quotient * b` is exact, and has the sign of the error times b
+fn div_error<S: Scalar>(a: S, b: S, quotient: S) -> S {
+    if has_infinite(a, b) {
+        return S::zero();
+    }
+    let remainder = (-quotient).mul_add(b, a);
+    checked_error(quotient, remainder * b.signum())
+}
+
+fn div_down<S: Scalar>(a: S, b: S) -> S {
+    let quotient = a / b;
+    round_down(quotient, div_error(a, b, quotient))
+}
+
+fn div_up<S: Scalar>(a: S, b: S) -> S {
+    let quotient = a / b;
+    round_up(quotient, div_error(a, b, quotient))
+}
+
+/// The remainder `a - root^2` is exact, and has the sign of the error
+fn sqrt_error<S: Scalar>(a: S, root: S) -> S {
+    checked_error(root, (-root).mul_add(root, a))
+}
+
+/// Bounds on `base^n` for a non-negative base, by repeated squaring
+fn powi_bounds<S: Scalar>(base: S, n: u32) -> (S, S) {
+    let (mut low, mut high) = (S::one(), S::one());
+    let (mut square_low, mut square_high) = (base, base);
+    let mut n = n;
+    while n > 0 {
+        if n & 1 == 1 {
+            low = mul_down(low, square_low);
+            high = mul_up(high, square_high);
+        }
+        n >>= 1;
+        if n > 0 {
+            square_low = mul_down(square_low, square_low);
+            square_high = mul_up(square_high, square_high);
+        }
+    }
+    (low, high)
+}
+
+/// The lower bound for the result of a library function
+fn widen_down<S: Scalar>(value: S) -> S {
+    round_down(value, S::nan())
+}
+
+fn widen_up<S: Scalar>(value: S) -> S {
+    round_up(value, S::nan())
+}
+
+/// `powf` is monotone in the base and the power for positive bases. Corners
+/// outside the domain, such as negative bases to fractional powers, are NaN and left out.
+fn powf_corners<S: Scalar>(base: &Interval<S>, power: &Interval<S>) -> Interval<S> {
+    corner_bounds(
+        [base.min, base.max]
+            .iter()
+            .cartesian_product(&[power.min, power.max])
+            .map(|(base, power)| {
+                let value = base.powf(*power);
+                (widen_down(value), widen_up(value))
+            }),
+    )
+}
+
+/// The lowest and highest of the values that aren't NaN, for taking the hull
+/// of an operation at the corners of its inputs
+fn corner_bounds<S: Scalar, I: Iterator<Item = (S, S)>>(corners: I) -> Interval<S> {
+    corners
+        .filter(|(low, high)| !low.is_nan() && !high.is_nan())
+        .fold(Interval::empty(), |hull, (low, high)| {
+            if hull.is_empty() {
+                Interval::new(low, high)
+            } else {
+                Interval::new(hull.min.min(low), hull.max.max(high))
+            }
+        })
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Interval<S = f32> {
+    pub min: S,
+    pub max: S,
+}
+
+impl<S: Scalar> Interval<S> {
+    pub fn new(min: S, max: S) -> Interval<S> {
         Interval { min, max }
     }
 
-    pub fn add(&self, other: &Interval) -> Vec<Interval> {
-        vec![Interval {
-            min: self.min + other.min,
-            max: self.max + other.max,
-        }]
+    /// The interval holding only the given value
+    pub fn constant(value: S) -> Interval<S> {
+        Interval::new(value, value)
+    }
+
+    /// The whole real line, for values that could be anything
+    pub fn unbounded() -> Interval<S> {
+        Interval::new(S::neg_infinity(), S::infinity())
     }
 
-    pub fn sub(&self, other: &Interval) -> Vec<Interval> {
-        vec![Interval {
-            min: self.min - other.max,
-            max: self.max - other.min,
-        }]
+    /// The interval containing no values, represented with NaN bounds so that
+    /// it propagates through arithmetic and never contains zero
+    pub fn empty() -> Interval<S> {
+        Interval::new(S::nan(), S::nan())
     }
 
-    pub fn mul(&self, other: &Interval) -> Vec<Interval> {
-        let minmax = [self.min, self.max]
+    pub fn is_empty(&self) -> bool {
+        self.min.is_nan() || self.max.is_nan()
+    }
+
+    /// The smallest single interval containing all the given intervals
+    pub fn hull(intervals: &[Interval<S>]) -> Interval<S> {
+        intervals
             .iter()
-            .cartesian_product(&[other.min, other.max])
-            .map(|(min, max)| min * max)
-            .minmax()
-            .into_option()
-            .unwrap();
+            .filter(|i| !i.is_empty())
+            .fold(Interval::empty(), |hull, i| Interval {
+                min: if hull.is_empty() {
+                    i.min
+                } else {
+                    hull.min.min(i.min)
+                },
+                max: if hull.is_empty() {
+                    i.max
+                } else {
+                    hull.max.max(i.max)
+                },
+            })
+    }
 
-        vec![Interval {
-            min: minmax.0,
-            max: minmax.1,
-        }]
+    /// The values in both intervals, empty if they don't overlap
+    pub fn intersect(&self, other: &Interval<S>) -> Interval<S> {
+        let (min, max) = (self.min.max(other.min), self.max.min(other.max));
+        if self.is_empty() || other.is_empty() || min > max {
+            Interval::empty()
+        } else {
+            Interval::new(min, max)
+        }
+    }
+
+    // The `*_hull` operations return a single interval, which for operations
+    // with split results is the hull of the pieces. They don't allocate, which
+    // is what the bytecode evaluator relies on.
+
+    pub fn add_hull(&self, other: &Interval<S>) -> Interval<S> {
+        Interval {
+            min: add_down(self.min, other.min),
+            max: add_up(self.max, other.max),
+        }
     }
 
-    pub fn div(&self, other: &Interval) -> Vec<Interval> {
+    pub fn sub_hull(&self, other: &Interval<S>) -> Interval<S> {
+        Interval {
+            min: add_down(self.min, -other.max),
+            max: add_up(self.max, -other.min),
+        }
+    }
+
+    pub fn mul_hull(&self, other: &Interval<S>) -> Interval<S> {
+        if self.is_empty() || other.is_empty() {
+            return Interval::empty();
+        }
+
+        corner_bounds(
+            [self.min, self.max]
+                .iter()
+                .cartesian_product(&[other.min, other.max])
+                .map(|(a, b)| (mul_down(*a, *b), mul_up(*a, *b))),
+        )
+    }
+
+    pub fn div_hull(&self, other: &Interval<S>) -> Interval<S> {
+        if other.is_empty() {
+            return Interval::empty();
+        }
+
+        // 1 / x is decreasing on either side of zero
+        let one = S::one();
         let inverse = match (other.min, other.max) {
-            (_, _) if !other.contains_zero() => Interval {
-                min: 1.0 / other.min,
-                max: 1.0 / other.max,
+            (min, max) if !other.contains_zero() => Interval {
+                min: div_down(one, max),
+                max: div_up(one, min),
             },
-            (min, max) if max == 0.0 => Interval {
-                min: -f32::INFINITY,
-                max: 1.0 / min,
+            (min, max) if max == S::zero() => Interval {
+                min: S::neg_infinity(),
+                max: div_up(one, min),
             },
-            (min, max) if min == 0.0 => Interval {
-                min: 1.0 / max,
-                max: f32::INFINITY,
-            },
-            (_min, _max) => Interval {
-                min: -f32::INFINITY,
-                max: f32::INFINITY,
+            (min, max) if min == S::zero() => Interval {
+                min: div_down(one, max),
+                max: S::infinity(),
             },
+            (_min, _max) => Interval::unbounded(),
         };
 
-        self.mul(&inverse)
+        self.mul_hull(&inverse)
     }
 
-    pub fn exp(&self, power: &Interval) -> Vec<Interval> {
-        let minmax = [self.min, self.max]
-            .iter()
-            .cartesian_product(&[power.min, power.max])
-            .map(|(base, power)| base.powf(*power))
-            .minmax()
-            .into_option()
-            .unwrap();
+    /// Integer powers, where even powers are never negative and odd powers are monotone
+    pub fn powi_hull(&self, n: i32) -> Interval<S> {
+        if self.is_empty() {
+            return Interval::empty();
+        }
+
+        if n == 0 {
+            return Interval::constant(S::one());
+        } else if n < 0 {
+            return Interval::constant(S::one()).div_hull(&self.powi_hull(-n));
+        }
+
+        let n = n as u32;
+        let (min_low, min_high) = powi_bounds(self.min.abs(), n);
+        let (max_low, max_high) = powi_bounds(self.max.abs(), n);
+        if n % 2 == 1 {
+            Interval {
+                min: if self.min < S::zero() {
+                    -min_high
+                } else {
+                    min_low
+                },
+                max: if self.max < S::zero() {
+                    -max_low
+                } else {
+                    max_high
+                },
+            }
+        } else if self.min >= S::zero() {
+            Interval::new(min_low, max_high)
+        } else if self.max <= S::zero() {
+            Interval::new(max_low, min_high)
+        } else {
+            Interval::new(S::zero(), min_high.max(max_high))
+        }
+    }
+
+    pub fn exp_hull(&self, power: &Interval<S>) -> Interval<S> {
+        if self.is_empty() || power.is_empty() {
+            return Interval::empty();
+        }
+
+        if power.min == power.max && power.min.fract() == S::zero() {
+            if let Some(n) = power.min.to_i32() {
+                return self.powi_hull(n);
+            }
+        }
+
+        // Negative bases only have values at integer powers, which can fall
+        // between the corners with either sign, but never beyond the largest magnitude
+        if self.min < S::zero() && power.min < power.max {
+            let largest = Interval::new(S::zero(), self.min.abs().max(self.max.abs()));
+            let magnitude = powf_corners(&largest, power).max;
+            return Interval::new(-magnitude, magnitude);
+        }
+
+        let bounds = powf_corners(self, power);
+        Interval {
+            min: if self.contains_zero() {
+                S::zero()
+            } else {
+                bounds.min
+            },
+            max: bounds.max,
+        }
+    }
+
+    pub fn sin_hull(&self) -> Interval<S> {
+        if self.is_empty() {
+            return Interval::empty();
+        }
+
+        // sin peaks at pi/2 + 2k*pi and bottoms out at -pi/2 + 2k*pi, so we
+        // only need to check the endpoints and whether either extremum is covered
+        let one = S::one();
+        let period = S::PI() + S::PI();
+        if self.max - self.min >= period {
+            return Interval::new(-one, one);
+        }
+
+        let (a, b) = (self.min.sin(), self.max.sin());
+        Interval {
+            min: if self.contains_periodic(-S::FRAC_PI_2(), period) {
+                -one
+            } else {
+                widen_down(a.min(b)).max(-one)
+            },
+            max: if self.contains_periodic(S::FRAC_PI_2(), period) {
+                one
+            } else {
+                widen_up(a.max(b)).min(one)
+            },
+        }
+    }
+
+    pub fn cos_hull(&self) -> Interval<S> {
+        if self.is_empty() {
+            return Interval::empty();
+        }
+
+        let one = S::one();
+        let period = S::PI() + S::PI();
+        if self.max - self.min >= period {
+            return Interval::new(-one, one);
+        }
+
+        let (a, b) = (self.min.cos(), self.max.cos());
+        Interval {
+            min: if self.contains_periodic(S::PI(), period) {
+                -one
+            } else {
+                widen_down(a.min(b)).max(-one)
+            },
+            max: if self.contains_periodic(S::zero(), period) {
+                one
+            } else {
+                widen_up(a.max(b)).min(one)
+            },
+        }
+    }
+
+    pub fn tan_hull(&self) -> Interval<S> {
+        if self.is_empty() {
+            return Interval::empty();
+        }
 
-        vec![Interval {
-            min: if self.contains_zero() { 0.0 } else { minmax.0 },
-            max: minmax.1,
-        }]
+        if self.max - self.min >= S::PI() || self.contains_periodic(S::FRAC_PI_2(), S::PI()) {
+            Interval::unbounded()
+        } else {
+            Interval::new(widen_down(self.min.tan()), widen_up(self.max.tan()))
+        }
+    }
+
+    pub fn exponential_hull(&self) -> Interval<S> {
+        if self.is_empty() {
+            return Interval::empty();
+        }
+
+        Interval::new(
+            widen_down(self.min.exp()).max(S::zero()),
+            widen_up(self.max.exp()),
+        )
+    }
+
+    pub fn ln_hull(&self) -> Interval<S> {
+        // Outside of the domain there is nothing to enclose
+        if self.max <= S::zero() {
+            return Interval::empty();
+        }
+
+        Interval::new(
+            widen_down(self.min.max(S::zero()).ln()),
+            widen_up(self.max.ln()),
+        )
+    }
+
+    pub fn sqrt_hull(&self) -> Interval<S> {
+        if self.max < S::zero() {
+            return Interval::empty();
+        }
+
+        let (min, max) = (self.min.max(S::zero()), self.max);
+        let (low, high) = (min.sqrt(), max.sqrt());
+        Interval::new(
+            round_down(low, sqrt_error(min, low)).max(S::zero()),
+            round_up(high, sqrt_error(max, high)),
+        )
+    }
+
+    pub fn abs_hull(&self) -> Interval<S> {
+        if self.contains_zero() {
+            Interval::new(S::zero(), self.max.max(-self.min))
+        } else if self.max < S::zero() {
+            Interval::new(-self.max, -self.min)
+        } else {
+            *self
+        }
+    }
+
+    /// The angle of the points (x, self), which jumps from pi to -pi across
+    /// the negative x axis
+    pub fn atan2_hull(&self, x: &Interval<S>) -> Interval<S> {
+        if self.is_empty() || x.is_empty() {
+            Interval::empty()
+        } else if self.crosses_branch_cut(x) {
+            Interval::new(-S::PI(), S::PI())
+        } else {
+            atan2_corners(self.min, self.max, x)
+        }
+    }
+
+    pub fn min_hull(&self, other: &Interval<S>) -> Interval<S> {
+        // f32::min ignores NaN, which would make the empty interval disappear
+        if self.is_empty() || other.is_empty() {
+            return Interval::empty();
+        }
+
+        Interval::new(self.min.min(other.min), self.max.min(other.max))
     }
 
-    pub fn middle(&self) -> f32 {
-        (self.min + self.max) / 2.0
+    pub fn max_hull(&self, other: &Interval<S>) -> Interval<S> {
+        if self.is_empty() || other.is_empty() {
+            return Interval::empty();
+        }
+
+        Interval::new(self.min.max(other.min), self.max.max(other.max))
     }
 
-    pub fn split(&self) -> [Interval; 2] {
+    pub fn add(&self, other: &Interval<S>) -> Vec<Interval<S>> {
+        vec![self.add_hull(other)]
+    }
+
+    pub fn sub(&self, other: &Interval<S>) -> Vec<Interval<S>> {
+        vec![self.sub_hull(other)]
+    }
+
+    pub fn mul(&self, other: &Interval<S>) -> Vec<Interval<S>> {
+        vec![self.mul_hull(other)]
+    }
+
+    /// Dividing by an interval that straddles zero gives two unbounded pieces,
+    /// one for each side of zero, which only join up when the numerator contains zero too
+    pub fn div(&self, other: &Interval<S>) -> Vec<Interval<S>> {
+        match self.div_split(other) {
+            (low, Some(high)) => merge_intervals(vec![low, high]),
+            (quotient, None) => non_empty(quotient),
+        }
+    }
+
+    /// The pieces of `div` without allocating, the second is only there when
+    /// the divisor straddles zero. They aren't merged, and may be empty.
+    pub fn div_split(&self, other: &Interval<S>) -> (Interval<S>, Option<Interval<S>>) {
+        if other.min < S::zero() && S::zero() < other.max {
+            (
+                self.div_hull(&Interval::new(other.min, S::zero())),
+                Some(self.div_hull(&Interval::new(S::zero(), other.max))),
+            )
+        } else {
+            (self.div_hull(other), None)
+        }
+    }
+
+    pub fn exp(&self, power: &Interval<S>) -> Vec<Interval<S>> {
+        non_empty(self.exp_hull(power))
+    }
+
+    pub fn powi(&self, n: i32) -> Vec<Interval<S>> {
+        non_empty(self.powi_hull(n))
+    }
+
+    pub fn sin(&self) -> Vec<Interval<S>> {
+        vec![self.sin_hull()]
+    }
+
+    pub fn cos(&self) -> Vec<Interval<S>> {
+        vec![self.cos_hull()]
+    }
+
+    pub fn tan(&self) -> Vec<Interval<S>> {
+        if self.max - self.min >= S::PI() {
+            return vec![Interval::unbounded()];
+        }
+
+        // tan is increasing between asymptotes, so crossing one splits the
+        // result into two unbounded pieces
+        let (a, b) = (widen_down(self.min.tan()), widen_up(self.max.tan()));
+        if self.contains_periodic(S::FRAC_PI_2(), S::PI()) {
+            vec![
+                Interval::new(a, S::infinity()),
+                Interval::new(S::neg_infinity(), b),
+            ]
+        } else {
+            vec![Interval::new(a, b)]
+        }
+    }
+
+    pub fn exponential(&self) -> Vec<Interval<S>> {
+        vec![self.exponential_hull()]
+    }
+
+    pub fn ln(&self) -> Vec<Interval<S>> {
+        non_empty(self.ln_hull())
+    }
+
+    pub fn sqrt(&self) -> Vec<Interval<S>> {
+        non_empty(self.sqrt_hull())
+    }
+
+    pub fn abs(&self) -> Vec<Interval<S>> {
+        vec![self.abs_hull()]
+    }
+
+    pub fn atan2(&self, x: &Interval<S>) -> Vec<Interval<S>> {
+        // Split at the negative x axis into a piece ending at pi and one starting at -pi
+        if self.crosses_branch_cut(x) {
+            vec![
+                atan2_corners(S::zero(), self.max, x),
+                atan2_corners(self.min, -S::zero(), x),
+            ]
+        } else {
+            vec![self.atan2_hull(x)]
+        }
+    }
+
+    pub fn min(&self, other: &Interval<S>) -> Vec<Interval<S>> {
+        vec![self.min_hull(other)]
+    }
+
+    pub fn max(&self, other: &Interval<S>) -> Vec<Interval<S>> {
+        vec![self.max_hull(other)]
+    }
+
+    /// Does the interval contain any point `offset + k * period` for integer k
+    fn contains_periodic(&self, offset: S, period: S) -> bool {
+        let k = ((self.min - offset) / period).ceil();
+        offset + k * period <= self.max
+    }
+
+    /// Do the points (x, self) include some on the negative x axis and some below it
+    fn crosses_branch_cut(&self, x: &Interval<S>) -> bool {
+        x.min < S::zero() && self.min < S::zero() && self.max >= S::zero()
+    }
+
+    pub fn middle(&self) -> S {
+        (self.min + self.max) / S::from_f32(2.0)
+    }
+
+    pub fn split(&self) -> [Interval<S>; 2] {
         let middle = self.middle();
         [
             Interval {
@@ -100,10 +628,10 @@ impl Interval {
     }
 
     pub fn contains_zero(&self) -> bool {
-        self.min <= 0.0 && self.max >= 0.0
+        self.min <= S::zero() && self.max >= S::zero()
     }
 
-    pub fn clamp_value(&self, v: f32) -> f32 {
+    pub fn clamp_value(&self, v: S) -> S {
         if v > self.max {
             self.max
         } else if v < self.min {
@@ -114,23 +642,99 @@ impl Interval {
     }
 }
 
-pub fn permute_intervals<A, F>(
+/// Away from the branch cut the extreme angles over a box are at its corners
+fn atan2_corners<S: Scalar>(y_min: S, y_max: S, x: &Interval<S>) -> Interval<S> {
+    corner_bounds(
+        [y_min, y_max]
+            .iter()
+            .cartesian_product(&[x.min, x.max])
+            .map(|(y, x)| {
+                let angle = y.atan2(*x);
+                (widen_down(angle), widen_up(angle))
+            }),
+    )
+}
+
+/// The most pieces an evaluation keeps. Binary operations make a piece for every
+/// pair of pieces in their operands, so without a cap the count could grow
+/// exponentially with the number of divisions in an expression.
+pub const MAX_PIECES: usize = 8;
+
+/// Sort the pieces and join any that overlap, then join the pieces with the
+/// smallest gaps between them until there are at most `MAX_PIECES` left.
+/// Empty pieces are dropped.
+pub fn merge_intervals<S: Scalar>(mut intervals: Vec<Interval<S>>) -> Vec<Interval<S>> {
+    merge_intervals_in_place(&mut intervals);
+    intervals
+}
+
+/// `merge_intervals` without allocating, for evaluation over preallocated buffers
+pub fn merge_intervals_in_place<S: Scalar>(intervals: &mut Vec<Interval<S>>) {
+    intervals.retain(|i| !i.is_empty());
+    intervals.sort_unstable_by(|a, b| a.min.partial_cmp(&b.min).unwrap_or(Ordering::Equal));
+
+    let mut merged = 0;
+    for i in 0..intervals.len() {
+        let interval = intervals[i];
+        if merged > 0 && interval.min <= intervals[merged - 1].max {
+            let last = &mut intervals[merged - 1];
+            last.max = last.max.max(interval.max);
+        } else {
+            intervals[merged] = interval;
+            merged += 1;
+        }
+    }
+    intervals.truncate(merged);
+
+    while intervals.len() > MAX_PIECES {
+        let gap = |i: usize| intervals[i].min - intervals[i - 1].max;
+        let closest = (1..intervals.len())
+            .min_by(|&a, &b| gap(a).partial_cmp(&gap(b)).unwrap_or(Ordering::Equal))
+            .unwrap();
+        intervals[closest - 1].max = intervals[closest].max;
+        intervals.remove(closest);
+    }
+}
+
+fn non_empty<S: Scalar>(interval: Interval<S>) -> Vec<Interval<S>> {
+    if interval.is_empty() {
+        vec![]
+    } else {
+        vec![interval]
+    }
+}
+
+pub fn permute_intervals<S, A, F>(
     node1: &Box<A>,
     node2: &Box<A>,
-    bindings: &HashMap<char, Interval>,
+    bindings: &HashMap<char, Interval<S>>,
     op: F,
-) -> Vec<Interval>
+) -> Vec<Interval<S>>
 where
-    F: FnMut((&Interval, &Interval)) -> Vec<Interval>,
-    A: Function,
+    S: Scalar,
+    F: FnMut((&Interval<S>, &Interval<S>)) -> Vec<Interval<S>>,
+    A: Function<S>,
 {
     let n1_i = node1.evaluate_interval(&bindings);
     let n2_i = node2.evaluate_interval(&bindings);
 
-    n1_i.iter().cartesian_product(&n2_i).map(op).concat()
+    merge_intervals(n1_i.iter().cartesian_product(&n2_i).map(op).concat())
 }
 
-pub fn contains_zero(intervals: &[Interval]) -> bool {
+pub fn map_intervals<S, A, F>(
+    node: &Box<A>,
+    bindings: &HashMap<char, Interval<S>>,
+    op: F,
+) -> Vec<Interval<S>>
+where
+    S: Scalar,
+    F: FnMut(&Interval<S>) -> Vec<Interval<S>>,
+    A: Function<S>,
+{
+    merge_intervals(node.evaluate_interval(&bindings).iter().map(op).concat())
+}
+
+pub fn contains_zero<S: Scalar>(intervals: &[Interval<S>]) -> bool {
     for interval in intervals {
         if interval.contains_zero() {
             return true;
@@ -144,6 +748,10 @@ mod tests {
     use super::*;
     use assert::*;
 
+    /// Results of library functions are widened by an ulp, which is more than
+    /// `f32::EPSILON` away for values above 1
+    const WIDENED_EPSILON: f32 = 4.0 * f32::EPSILON;
+
     #[test]
     fn test_add() {
         let a = Interval::new(1.0, 2.0);
@@ -191,4 +799,453 @@ mod tests {
         close(r[0].min, -2.0, f32::EPSILON);
         close(r[0].max, 2.0, f32::EPSILON);
     }
+
+    #[test]
+    fn test_div() {
+        let r = Interval::new(1.0, 2.0).div(&Interval::new(2.0, 4.0));
+        assert_eq!(r, vec![Interval::new(0.25, 1.0)]);
+
+        // A divisor straddling zero splits the result around the gap
+        let r = Interval::new(1.0, 2.0).div(&Interval::new(-1.0, 1.0));
+        assert_eq!(
+            r,
+            vec![
+                Interval::new(-f32::INFINITY, -1.0),
+                Interval::new(1.0, f32::INFINITY)
+            ]
+        );
+        let r = Interval::new(-2.0, -1.0).div(&Interval::new(-1.0, 2.0));
+        assert_eq!(
+            r,
+            vec![
+                Interval::new(-f32::INFINITY, -0.5),
+                Interval::new(1.0, f32::INFINITY)
+            ]
+        );
+
+        // Unless the numerator contains zero as well
+        let r = Interval::new(-1.0, 1.0).div(&Interval::new(-1.0, 1.0));
+        assert_eq!(r, vec![Interval::new(-f32::INFINITY, f32::INFINITY)]);
+        let r = Interval::new(0.0, 0.0).div(&Interval::new(-1.0, 1.0));
+        assert_eq!(r, vec![Interval::new(0.0, 0.0)]);
+
+        // Touching zero only leaves one side
+        let r = Interval::new(1.0, 2.0).div(&Interval::new(0.0, 4.0));
+        assert_eq!(r, vec![Interval::new(0.25, f32::INFINITY)]);
+    }
+
+    #[test]
+    fn test_merge_intervals() {
+        let r = merge_intervals(vec![
+            Interval::new(3.0, 4.0),
+            Interval::empty(),
+            Interval::new(-1.0, 1.0),
+            Interval::new(0.5, 2.0),
+            Interval::new(2.0, 2.5),
+        ]);
+        assert_eq!(r, vec![Interval::new(-1.0, 2.5), Interval::new(3.0, 4.0)]);
+        assert!(merge_intervals(vec![Interval::<f32>::empty()]).is_empty());
+
+        // Past the cap the pieces closest together are joined first
+        let mut pieces: Vec<Interval> = (0..MAX_PIECES)
+            .map(|i| Interval::new(10.0 * i as f32, 10.0 * i as f32 + 1.0))
+            .collect();
+        pieces.push(Interval::new(1.5, 2.0));
+        let r = merge_intervals(pieces);
+        assert_eq!(r.len(), MAX_PIECES);
+        assert_eq!(r[0], Interval::new(0.0, 2.0));
+        assert_eq!(r[1], Interval::new(10.0, 11.0));
+    }
+
+    #[test]
+    fn test_sin() {
+        let r = Interval::new(0.0, 1.0).sin();
+        assert_eq!(r.len(), 1);
+        close(r[0].min, 0.0, f32::EPSILON);
+        close(r[0].max, 1.0f32.sin(), f32::EPSILON);
+
+        // Covers the peak at pi / 2
+        let r = Interval::new(1.0, 2.0).sin();
+        close(r[0].min, 1.0f32.sin(), f32::EPSILON);
+        close(r[0].max, 1.0, f32::EPSILON);
+
+        // Covers the trough at 3pi / 2 two periods out
+        let r = Interval::new(16.0, 17.5).sin();
+        close(r[0].min, -1.0, f32::EPSILON);
+        close(r[0].max, 16.0f32.sin().max(17.5f32.sin()), f32::EPSILON);
+
+        let r = Interval::new(-100.0, 100.0).sin();
+        close(r[0].min, -1.0, f32::EPSILON);
+        close(r[0].max, 1.0, f32::EPSILON);
+    }
+
+    #[test]
+    fn test_cos() {
+        let r = Interval::new(-0.5, 0.25).cos();
+        close(r[0].min, 0.5f32.cos(), f32::EPSILON);
+        close(r[0].max, 1.0, f32::EPSILON);
+
+        let r = Interval::new(3.0, 3.5).cos();
+        close(r[0].min, -1.0, f32::EPSILON);
+        close(r[0].max, 3.0f32.cos().max(3.5f32.cos()), f32::EPSILON);
+
+        let r = Interval::new(0.5, 1.0).cos();
+        close(r[0].min, 1.0f32.cos(), f32::EPSILON);
+        close(r[0].max, 0.5f32.cos(), f32::EPSILON);
+    }
+
+    #[test]
+    fn test_tan() {
+        let r = Interval::new(-0.5, 0.5).tan();
+        assert_eq!(r.len(), 1);
+        close(r[0].min, (-0.5f32).tan(), WIDENED_EPSILON);
+        close(r[0].max, 0.5f32.tan(), WIDENED_EPSILON);
+
+        // Straddles the asymptote at pi / 2
+        let r = Interval::new(1.0, 2.0).tan();
+        assert_eq!(r.len(), 2);
+        close(r[0].min, 1.0f32.tan(), WIDENED_EPSILON);
+        assert_eq!(r[0].max, f32::INFINITY);
+        assert_eq!(r[1].min, -f32::INFINITY);
+        close(r[1].max, 2.0f32.tan(), WIDENED_EPSILON);
+    }
+
+    #[test]
+    fn test_atan2() {
+        let pi = std::f32::consts::PI;
+
+        // The first quadrant
+        let r = Interval::new(0.0, 1.0).atan2(&Interval::new(1.0, 2.0));
+        assert_eq!(r.len(), 1);
+        close(r[0].min, 0.0, f32::EPSILON);
+        close(r[0].max, pi / 4.0, f32::EPSILON);
+
+        // Around the origin every angle is possible
+        let r = Interval::new(-1.0, 1.0).atan2_hull(&Interval::new(-1.0, 1.0));
+        close(r.min, -pi, f32::EPSILON);
+        close(r.max, pi, f32::EPSILON);
+
+        // Straddles the negative x axis
+        let r = Interval::new(-1.0, 1.0).atan2(&Interval::new(-2.0, -1.0));
+        assert_eq!(r.len(), 2);
+        close(r[0].min, 1.0f32.atan2(-1.0), WIDENED_EPSILON);
+        close(r[0].max, pi, WIDENED_EPSILON);
+        close(r[1].min, -pi, WIDENED_EPSILON);
+        close(r[1].max, (-1.0f32).atan2(-1.0), WIDENED_EPSILON);
+    }
+
+    #[test]
+    fn test_powi() {
+        // Even powers can't be negative, even when the base straddles zero
+        assert_eq!(
+            Interval::new(-1.0, 1.0).powi_hull(2),
+            Interval::new(0.0, 1.0)
+        );
+        assert_eq!(
+            Interval::new(-3.0, 2.0).powi_hull(4),
+            Interval::new(0.0, 81.0)
+        );
+        assert_eq!(
+            Interval::new(-2.0, -1.0).powi_hull(2),
+            Interval::new(1.0, 4.0)
+        );
+        assert_eq!(
+            Interval::new(1.0, 3.0).powi_hull(2),
+            Interval::new(1.0, 9.0)
+        );
+
+        // Odd powers are monotone and keep their sign
+        assert_eq!(
+            Interval::new(-2.0, 1.0).powi_hull(3),
+            Interval::new(-8.0, 1.0)
+        );
+        assert_eq!(
+            Interval::new(-3.0, -2.0).powi_hull(3),
+            Interval::new(-27.0, -8.0)
+        );
+
+        assert_eq!(
+            Interval::new(-5.0, 5.0).powi_hull(0),
+            Interval::new(1.0, 1.0)
+        );
+        assert_eq!(
+            Interval::new(2.0, 4.0).powi_hull(-1),
+            Interval::new(0.25, 0.5)
+        );
+        assert_eq!(
+            Interval::new(-1.0, 2.0).powi_hull(-2),
+            Interval::new(0.25, f32::INFINITY)
+        );
+        assert!(Interval::<f32>::empty().powi_hull(2).is_empty());
+    }
+
+    #[test]
+    fn test_exp() {
+        // Constant integer powers use powi
+        let r = Interval::new(-1.0, 1.0).exp(&Interval::new(2.0, 2.0));
+        assert_eq!(r, vec![Interval::new(0.0, 1.0)]);
+        let r = Interval::new(-2.0, 1.0).exp(&Interval::new(3.0, 3.0));
+        assert_eq!(r, vec![Interval::new(-8.0, 1.0)]);
+
+        // Fractional powers of negative bases have no value
+        assert!(Interval::new(-2.0, -1.0)
+            .exp(&Interval::new(0.5, 0.5))
+            .is_empty());
+        let r = Interval::new(4.0, 9.0).exp_hull(&Interval::new(0.5, 0.5));
+        close(r.min, 2.0, WIDENED_EPSILON);
+        close(r.max, 3.0, WIDENED_EPSILON);
+
+        // A varying power of a negative base takes either sign
+        let r = Interval::new(-2.0, 2.0).exp_hull(&Interval::new(1.0, 3.0));
+        assert!(r.min <= -8.0 && r.max >= 8.0);
+    }
+
+    #[test]
+    fn test_ln_sqrt_domain() {
+        assert!(Interval::new(-2.0, -1.0).ln().is_empty());
+        assert!(Interval::new(-2.0, -1.0).sqrt().is_empty());
+
+        let r = Interval::new(-1.0, 4.0).sqrt();
+        close(r[0].min, 0.0, f32::EPSILON);
+        close(r[0].max, 2.0, f32::EPSILON);
+
+        let r = Interval::new(-1.0, 1.0).ln();
+        assert_eq!(r[0].min, -f32::INFINITY);
+        close(r[0].max, 0.0, f32::EPSILON);
+    }
+
+    #[test]
+    fn test_abs_min_max() {
+        let r = Interval::new(-3.0, 2.0).abs();
+        close(r[0].min, 0.0, f32::EPSILON);
+        close(r[0].max, 3.0, f32::EPSILON);
+
+        let r = Interval::new(-3.0, -2.0).abs();
+        close(r[0].min, 2.0, f32::EPSILON);
+        close(r[0].max, 3.0, f32::EPSILON);
+
+        let a = Interval::new(-1.0, 3.0);
+        let b = Interval::new(0.0, 2.0);
+        let r = a.min(&b);
+        close(r[0].min, -1.0, f32::EPSILON);
+        close(r[0].max, 2.0, f32::EPSILON);
+        let r = a.max(&b);
+        close(r[0].min, 0.0, f32::EPSILON);
+        close(r[0].max, 3.0, f32::EPSILON);
+    }
+
+    #[test]
+    fn test_special_values() {
+        // Zero times an unbounded end is zero rather than NaN
+        let r = Interval::new(0.0, 0.0).mul_hull(&Interval::new(-f32::INFINITY, f32::INFINITY));
+        assert_eq!(r, Interval::new(0.0, 0.0));
+        let r = Interval::new(0.0, 0.0).div_hull(&Interval::new(-1.0, 1.0));
+        assert_eq!(r, Interval::new(0.0, 0.0));
+
+        // Empty intervals stay empty
+        let empty = Interval::empty();
+        let one = Interval::new(1.0, 1.0);
+        assert!(empty.add_hull(&one).is_empty());
+        assert!(one.mul_hull(&empty).is_empty());
+        assert!(one.div_hull(&empty).is_empty());
+        assert!(empty.exp_hull(&one).is_empty());
+
+        // Corners outside the domain of powf are left out
+        let r = Interval::new(-8.0, 8.0).exp_hull(&Interval::new(0.5, 0.5));
+        assert!(r.max >= 8.0f32.sqrt());
+        let r = Interval::new(-8.0, -1.0).exp_hull(&Interval::new(0.5, 0.5));
+        assert!(r.is_empty());
+    }
+
+    #[cfg(feature = "outward-rounding")]
+    #[test]
+    fn test_outward_rounding() {
+        // Exact results are left alone
+        let r = Interval::new(1.0f32, 2.0).add_hull(&Interval::new(2.0, 3.0));
+        assert_eq!(r, Interval::new(3.0, 5.0));
+        let r = Interval::new(1.5, 2.0).mul_hull(&Interval::new(-4.0, 0.25));
+        assert_eq!(r, Interval::new(-8.0, 0.5));
+        let r = Interval::new(1.0, 4.0).div_hull(&Interval::new(2.0, 2.0));
+        assert_eq!(r, Interval::new(0.5, 2.0));
+        assert_eq!(Interval::new(4.0, 9.0).sqrt_hull(), Interval::new(2.0, 3.0));
+
+        // 1 + 1e-8 rounds to 1 when rounding to nearest
+        let r = Interval::new(1.0f32, 1.0).add_hull(&Interval::new(1e-8, 1e-8));
+        assert_eq!(r.min, 1.0);
+        assert!(r.max > 1.0);
+
+        // (1 + 2^-12)^2 = 1 + 2^-11 + 2^-24 is a tie that rounds down to even
+        let a = 1.0 + 2.0f32.powi(-12);
+        let r = Interval::new(a, a).mul_hull(&Interval::new(a, a));
+        assert_eq!(r.min, 1.0 + 2.0f32.powi(-11));
+        assert!(r.max > r.min);
+
+        let third = Interval::new(1.0f32, 1.0).div_hull(&Interval::new(3.0, 3.0));
+        assert!(third.min < third.max);
+        assert!(third.min <= 1.0 / 3.0 && 1.0 / 3.0 <= third.max);
+
+        let r = Interval::new(2.0f32, 2.0).sqrt_hull();
+        assert!(r.min < r.max);
+
+        // The true value of (x + y) - x - y is zero, but rounding to nearest gives -1e-8
+        let x = Interval::new(1.0f32, 1.0);
+        let y = Interval::new(1e-8, 1e-8);
+        let r = x.add_hull(&y).sub_hull(&x).sub_hull(&y);
+        assert!(r.contains_zero());
+
+        // Overflow rounds the lower bound back to the largest finite value
+        let r = Interval::new(f32::MAX, f32::MAX).add_hull(&Interval::new(f32::MAX, f32::MAX));
+        assert_eq!(r, Interval::new(f32::MAX, f32::INFINITY));
+    }
+
+    #[cfg(feature = "outward-rounding")]
+    #[test]
+    fn test_outward_rounding_f64() {
+        // 1 + 1e-8 is exact enough in f64, 1 + 1e-17 is not
+        let r = Interval::new(1.0f64, 1.0).add_hull(&Interval::new(1e-8, 1e-8));
+        assert!(r.min > 1.0);
+        let r = Interval::new(1.0f64, 1.0).add_hull(&Interval::new(1e-17, 1e-17));
+        assert_eq!(r.min, 1.0);
+        assert_eq!(r.max, Scalar::next_up(1.0f64));
+
+        let third = Interval::new(1.0f64, 1.0).div_hull(&Interval::new(3.0, 3.0));
+        assert!(third.min < third.max);
+        assert!(third.min <= 1.0 / 3.0 && 1.0 / 3.0 <= third.max);
+        assert!(third.max - third.min < 1e-15);
+
+        let r = Interval::new(f64::MAX, f64::MAX).add_hull(&Interval::new(f64::MAX, f64::MAX));
+        assert_eq!(r, Interval::new(f64::MAX, f64::INFINITY));
+    }
+
+    /// A xorshift generator, so the sampled boxes are the same on every run
+    struct Samples(u32);
+
+    impl Samples {
+        fn next(&mut self) -> f32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0 as f32 / u32::MAX as f32
+        }
+    }
+
+    /// Rounding to nearest can miss samples, so this only holds with outward rounding
+    #[cfg(feature = "outward-rounding")]
+    #[test]
+    fn test_enclosures_contain_samples() {
+        use crate::bytecode::CompiledFunction;
+        use crate::function::Function;
+        use crate::parser::parse_expression;
+        use itertools::iproduct;
+        use std::collections::HashMap;
+
+        let expressions = [
+            "x + y * z - 0.1",
+            "(x + y) - x - y",
+            "x * y - z / (x + 2)",
+            "1 / (x - y) + 1 / z",
+            "x^2 - y^3 + z^0.5",
+            "sin(x * y) + cos(z) * tan(x)",
+            "exp(x / 4) - ln(y) + sqrt(z)",
+            "abs(x - y) + min(x, z) - max(y, z)",
+            "atan2(y, x) + atan2(z, y)",
+            "sin(x)^2 + cos(x)^2 - 1",
+            "x * x - y^3 + (z + 1)^-2 - (x - y)^4",
+        ];
+        let widths = [0.0, 1e-6, 1e-3, 0.5, 4.0];
+        let steps = 4;
+
+        let mut samples = Samples(0x2545_f491);
+        for expression in expressions.iter() {
+            let input: Vec<char> = expression.chars().collect();
+            let root = parse_expression(&input, 0).unwrap();
+            let compiled = CompiledFunction::compile(&root);
+
+            for _ in 0..40 {
+                let width = widths[(samples.next() * widths.len() as f32) as usize % widths.len()];
+                let mut bindings = HashMap::new();
+                for variable in &['x', 'y', 'z'] {
+                    let min = (samples.next() - 0.5) * 20.0;
+                    bindings.insert(*variable, Interval::new(min, min + width));
+                }
+
+                let enclosures = root.evaluate_intervals(&bindings);
+                let hull = compiled.evaluate_interval(&bindings);
+                let point = |variable: char, i: usize| {
+                    let interval = bindings[&variable];
+                    let t = i as f32 / steps as f32;
+                    if i == steps {
+                        interval.max
+                    } else {
+                        interval.min + (interval.max - interval.min) * t
+                    }
+                };
+
+                for (i, j, k) in iproduct!(0..=steps, 0..=steps, 0..=steps) {
+                    let mut values = HashMap::new();
+                    values.insert('x', point('x', i));
+                    values.insert('y', point('y', j));
+                    values.insert('z', point('z', k));
+                    let value = root.evaluate(&values);
+                    if value.is_nan() {
+                        continue;
+                    }
+
+                    let contains = |intervals: &[Interval]| {
+                        intervals.iter().any(|r| r.min <= value && value <= r.max)
+                    };
+                    assert!(
+                        contains(&enclosures),
+                        "{} = {} at {:?} is outside {:?}",
+                        expression,
+                        value,
+                        values,
+                        enclosures
+                    );
+                    assert!(
+                        contains(&hull),
+                        "{} = {} at {:?} is outside the compiled {:?}",
+                        expression,
+                        value,
+                        values,
+                        hull
+                    );
+                }
+            }
+        }
+
+        // Nowhere defined stays nowhere defined, whatever it's combined with
+        let undefined = [
+            "sin(sqrt(x))",
+            "cos(sqrt(x))",
+            "tan(sqrt(x))",
+            "exp(sqrt(x))",
+            "ln(sqrt(x))",
+            "abs(sqrt(x))",
+            "sqrt(x)^2",
+            "y^sqrt(x)",
+            "min(sqrt(x), y)",
+            "max(y, sqrt(x))",
+            "atan2(sqrt(x), y)",
+            "atan2(y, sqrt(x))",
+            "y + sqrt(x)",
+            "y * sqrt(x)",
+            "y / sqrt(x)",
+        ];
+        let mut bindings = HashMap::new();
+        bindings.insert('x', Interval::new(-3.0, -1.0));
+        bindings.insert('y', Interval::new(-1.0, 2.0));
+        for expression in undefined.iter() {
+            let input: Vec<char> = expression.chars().collect();
+            let root = parse_expression(&input, 0).unwrap();
+            let compiled: CompiledFunction = CompiledFunction::compile(&root);
+            assert_eq!(root.evaluate_intervals(&bindings), vec![], "{}", expression);
+            assert_eq!(
+                compiled.evaluate_interval(&bindings),
+                vec![],
+                "{}",
+                expression
+            );
+        }
+    }
 }
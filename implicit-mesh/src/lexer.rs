@@ -0,0 +1,333 @@
+use crate::display::GREEK_LETTERS;
+use crate::function::Relation;
+use crate::parser_error::Span;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    /// The digits of a number, parsed later so errors can point at them
+    Number(String),
+    Identifier(String),
+    /// One of `+ - * / ^`
+    Operator(char),
+    Relation(Relation),
+    LeftParen,
+    RightParen,
+    /// Only in LaTeX, where braces group like parentheses
+    LeftBrace,
+    RightBrace,
+    /// Only in LaTeX, `|x|` is the absolute value of x
+    Bar,
+    /// A LaTeX command the parser gives meaning to, such as `\frac`, without the backslash
+    Command(String),
+    Comma,
+    /// Ends a definition, i.e. `r = x^2 + y^2;`
+    Semicolon,
+    /// A character that can't start any token, left for the parser to report
+    Unknown(char),
+    /// Always the last token, its span is just past the input
+    End,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+impl Token {
+    fn new(kind: TokenKind, start: usize, end: usize) -> Token {
+        Token {
+            kind,
+            span: Span::new(start, end),
+        }
+    }
+}
+
+/// Split the input into tokens, starting at current_index.
+/// Whitespace only separates tokens, and the result always ends with `TokenKind::End`.
+pub fn tokenize(input: &[char], current_index: usize) -> Vec<Token> {
+    tokenize_with(input, current_index, false)
+}
+
+/// Split LaTeX math into tokens. Here every letter is its own identifier, longer names
+/// come from commands like `\theta` or `\mathrm{radius}`, and sizing or spacing
+/// commands such as `\left` and `\,` are dropped.
+pub fn tokenize_latex(input: &[char], current_index: usize) -> Vec<Token> {
+    tokenize_with(input, current_index, true)
+}
+
+fn tokenize_with(input: &[char], current_index: usize, latex: bool) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut index = current_index;
+
+    while index < input.len() {
+        let start = index;
+        let c = input[index];
+        index += 1;
+
+        let kind = match c {
+            c if c.is_whitespace() => continue,
+            '\\' if latex => {
+                let (kind, end) = latex_command(input, index);
+                index = end;
+                match kind {
+                    Some(kind) => kind,
+                    None => continue,
+                }
+            }
+            // e^u is how exp(u) gets written
+            'e' if latex && index < input.len() && input[index] == '^' => {
+                index += 1;
+                TokenKind::Command("exp".to_string())
+            }
+            c if latex && c.is_alphabetic() => TokenKind::Identifier(c.to_string()),
+            // Digits can follow the first letter, i.e. atan2
+            c if c.is_alphabetic() => {
+                while index < input.len() && input[index].is_alphanumeric() {
+                    index += 1;
+                }
+                TokenKind::Identifier(input[start..index].iter().collect())
+            }
+            d if d.is_digit(10) => {
+                index = number_end(input, index);
+                TokenKind::Number(input[start..index].iter().collect())
+            }
+            '+' | '-' | '*' | '/' | '^' => TokenKind::Operator(c),
+            '=' => TokenKind::Relation(Relation::Equal),
+            '<' | '>' => {
+                let or_equal = index < input.len() && input[index] == '=';
+                if or_equal {
+                    index += 1;
+                }
+                match (c, or_equal) {
+                    ('<', false) => TokenKind::Relation(Relation::Less),
+                    ('<', true) => TokenKind::Relation(Relation::LessEqual),
+                    ('>', false) => TokenKind::Relation(Relation::Greater),
+                    _ => TokenKind::Relation(Relation::GreaterEqual),
+                }
+            }
+            '(' => TokenKind::LeftParen,
+            ')' => TokenKind::RightParen,
+            '{' if latex => TokenKind::LeftBrace,
+            '}' if latex => TokenKind::RightBrace,
+            '|' if latex => TokenKind::Bar,
+            ',' => TokenKind::Comma,
+            ';' => TokenKind::Semicolon,
+            c => TokenKind::Unknown(c),
+        };
+
+        tokens.push(Token::new(kind, start, index));
+    }
+
+    tokens.push(Token::new(TokenKind::End, input.len(), input.len() + 1));
+    tokens
+}
+
+/// Lex the command whose name starts at current_index, just after the backslash.
+/// Returns None for commands that don't mean anything here, along with the index
+/// after the command.
+fn latex_command(input: &[char], current_index: usize) -> (Option<TokenKind>, usize) {
+    let mut index = current_index;
+    while index < input.len() && input[index].is_alphabetic() {
+        index += 1;
+    }
+
+    // Single character commands, i.e. the spacing in \, or \;
+    if index == current_index {
+        if index < input.len() {
+            let kind = match input[index] {
+                '{' => Some(TokenKind::LeftBrace),
+                '}' => Some(TokenKind::RightBrace),
+                '|' => Some(TokenKind::Bar),
+                _ => None,
+            };
+            return (kind, index + 1);
+        }
+        return (Some(TokenKind::Unknown('\\')), index);
+    }
+
+    let name: String = input[current_index..index].iter().collect();
+    let kind = match name.as_str() {
+        "left" | "right" | "big" | "Big" | "bigg" | "Bigg" | "quad" | "qquad" => None,
+        "cdot" | "times" => Some(TokenKind::Operator('*')),
+        "div" => Some(TokenKind::Operator('/')),
+        "lt" => Some(TokenKind::Relation(Relation::Less)),
+        "le" | "leq" | "leqslant" => Some(TokenKind::Relation(Relation::LessEqual)),
+        "gt" => Some(TokenKind::Relation(Relation::Greater)),
+        "ge" | "geq" | "geqslant" => Some(TokenKind::Relation(Relation::GreaterEqual)),
+        "mathrm" | "operatorname" | "text" | "mathit" => {
+            return latex_name(input, index);
+        }
+        name if GREEK_LETTERS.contains(&name) => Some(TokenKind::Identifier(name.to_string())),
+        _ => Some(TokenKind::Command(name)),
+    };
+
+    (kind, index)
+}
+
+/// The braced name after a command like `\mathrm`, as an identifier
+fn latex_name(input: &[char], current_index: usize) -> (Option<TokenKind>, usize) {
+    let mut index = current_index;
+    while index < input.len() && input[index] == ' ' {
+        index += 1;
+    }
+    if index >= input.len() || input[index] != '{' {
+        return (Some(TokenKind::Unknown('\\')), current_index);
+    }
+
+    let start = index + 1;
+    let mut end = start;
+    while end < input.len() && input[end].is_alphanumeric() {
+        end += 1;
+    }
+    if end == start || !input[start].is_alphabetic() || end >= input.len() || input[end] != '}' {
+        return (Some(TokenKind::Unknown('\\')), current_index);
+    }
+
+    let name = input[start..end].iter().collect();
+    (Some(TokenKind::Identifier(name)), end + 1)
+}
+
+/// Numbers are digits with at most one decimal point, which may come last
+fn number_end(input: &[char], current_index: usize) -> usize {
+    let mut index = current_index;
+    let mut found_decimal = false;
+
+    while index < input.len() {
+        if input[index].is_digit(10) {
+            index += 1;
+        } else if !found_decimal && input[index] == '.' {
+            found_decimal = true;
+            index += 1;
+        } else {
+            break;
+        }
+    }
+
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(text: &str) -> Vec<TokenKind> {
+        let input: Vec<char> = text.chars().collect();
+        tokenize(&input, 0).into_iter().map(|t| t.kind).collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            kinds("2x^2 <= radius"),
+            vec![
+                TokenKind::Number("2".to_string()),
+                TokenKind::Identifier("x".to_string()),
+                TokenKind::Operator('^'),
+                TokenKind::Number("2".to_string()),
+                TokenKind::Relation(Relation::LessEqual),
+                TokenKind::Identifier("radius".to_string()),
+                TokenKind::End,
+            ]
+        );
+
+        assert_eq!(
+            kinds("atan2 2r"),
+            vec![
+                TokenKind::Identifier("atan2".to_string()),
+                TokenKind::Number("2".to_string()),
+                TokenKind::Identifier("r".to_string()),
+                TokenKind::End,
+            ]
+        );
+
+        assert_eq!(
+            kinds("min(0.5, 1.)>$"),
+            vec![
+                TokenKind::Identifier("min".to_string()),
+                TokenKind::LeftParen,
+                TokenKind::Number("0.5".to_string()),
+                TokenKind::Comma,
+                TokenKind::Number("1.".to_string()),
+                TokenKind::RightParen,
+                TokenKind::Relation(Relation::Greater),
+                TokenKind::Unknown('$'),
+                TokenKind::End,
+            ]
+        );
+
+        // A second decimal point ends the number
+        assert_eq!(
+            kinds("0.131."),
+            vec![
+                TokenKind::Number("0.131".to_string()),
+                TokenKind::Unknown('.'),
+                TokenKind::End,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_latex() {
+        let input: Vec<char> =
+            "\\frac{xy}{\\theta} \\cdot e^{x} \\le \\left|\\mathrm{radius}\\right|"
+                .chars()
+                .collect();
+        let latex_kinds: Vec<TokenKind> = tokenize_latex(&input, 0)
+            .into_iter()
+            .map(|t| t.kind)
+            .collect();
+        assert_eq!(
+            latex_kinds,
+            vec![
+                TokenKind::Command("frac".to_string()),
+                TokenKind::LeftBrace,
+                TokenKind::Identifier("x".to_string()),
+                TokenKind::Identifier("y".to_string()),
+                TokenKind::RightBrace,
+                TokenKind::LeftBrace,
+                TokenKind::Identifier("theta".to_string()),
+                TokenKind::RightBrace,
+                TokenKind::Operator('*'),
+                TokenKind::Command("exp".to_string()),
+                TokenKind::LeftBrace,
+                TokenKind::Identifier("x".to_string()),
+                TokenKind::RightBrace,
+                TokenKind::Relation(Relation::LessEqual),
+                TokenKind::Bar,
+                TokenKind::Identifier("radius".to_string()),
+                TokenKind::Bar,
+                TokenKind::End,
+            ]
+        );
+
+        // Braces and bars are only special in LaTeX
+        assert_eq!(
+            kinds("{|}"),
+            vec![
+                TokenKind::Unknown('{'),
+                TokenKind::Unknown('|'),
+                TokenKind::Unknown('}'),
+                TokenKind::End,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let input: Vec<char> = "  xy >= 12.5".chars().collect();
+        let spans: Vec<Span> = tokenize(&input, 0).into_iter().map(|t| t.span).collect();
+        assert_eq!(
+            spans,
+            vec![
+                Span::new(2, 4),
+                Span::new(5, 7),
+                Span::new(8, 12),
+                Span::new(12, 13),
+            ]
+        );
+
+        let spans: Vec<Span> = tokenize(&input, 5).into_iter().map(|t| t.span).collect();
+        assert_eq!(spans[0], Span::new(5, 7));
+    }
+}
@@ -1,15 +1,29 @@
 extern crate cgmath;
 extern crate geoprim;
 extern crate itertools;
+extern crate num_traits;
 extern crate serde_json;
 
 #[macro_use]
 mod util;
 
+pub mod affine;
+pub mod autodiff;
+pub mod bounding_box;
+pub mod bytecode;
 pub mod cell_keys;
+pub mod dag;
+pub mod derivative;
+pub mod display;
 pub mod function;
 pub mod function_ir;
 pub mod interval;
+pub mod lexer;
 pub mod mesh_tree;
+pub mod parametric;
 pub mod parser;
 pub mod parser_error;
+pub mod program;
+pub mod quad_tree;
+pub mod scalar;
+pub mod simplify;
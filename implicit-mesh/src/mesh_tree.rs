@@ -1,61 +1,351 @@
-use super::cell_keys::{Key, MortonKey, Neighbor};
+use super::cell_keys::{Key, MortonKey, Neighbor, NeighborRelation};
 use super::interval::Interval;
+use crate::autodiff::monotone_axis;
+pub use crate::bounding_box::BoundingBox;
 use crate::function::*;
 use crate::geoprim::*;
 use crate::interval::contains_zero;
-use cgmath::Vector3;
-use itertools::Itertools;
+use crate::scalar::Scalar;
+use cgmath::{InnerSpace, Matrix, Matrix3, SquareMatrix, Vector3, Zero};
 use std::collections::{HashMap, HashSet};
 use std::iter::FromIterator;
 use std::iter::Iterator;
 
+/// Vertices with triangles and lines between them, each given by indices into `vertices`
 pub struct Geometry {
     pub vertices: Vec<Vector3<f32>>,
     pub triangles: Vec<u32>,
     pub lines: Vec<u32>,
 }
 
-#[derive(Debug, Copy, Clone)]
-pub struct BoundingBox {
-    pub x: Interval,
-    pub y: Interval,
-    pub z: Interval,
+impl Geometry {
+    fn is_finite(&self, index: u32) -> bool {
+        let v = self.vertices[index as usize];
+        v.x.is_finite() && v.y.is_finite() && v.z.is_finite()
+    }
+
+    /// Add a line, unless one of its ends isn't finite
+    pub fn add_line(&mut self, a: u32, b: u32) {
+        if self.is_finite(a) && self.is_finite(b) {
+            self.lines.extend(&[a, b]);
+        }
+    }
+
+    /// Add a triangle, unless one of its corners isn't finite
+    pub fn add_triangle(&mut self, corners: [u32; 3]) {
+        if corners.iter().all(|c| self.is_finite(*c)) {
+            self.triangles.extend(&corners);
+        }
+    }
+
+    pub fn add_to_plot(&self, add_vertices: bool, plot: &mut Plot) {
+        let point = |index: &u32| {
+            let v = self.vertices[*index as usize];
+            Point::new(v.x, v.y, v.z)
+        };
+
+        if add_vertices {
+            for v in &self.vertices {
+                if v.x.is_finite() && v.y.is_finite() && v.z.is_finite() {
+                    plot.add_point(Point::new(v.x, v.y, v.z));
+                }
+            }
+        }
+
+        for line in self.lines.chunks(2) {
+            plot.add_line(LineSegment::new(point(&line[0]), point(&line[1])));
+        }
+    }
+
+    fn floats(&self, indices: &[u32]) -> Vec<f32> {
+        let mut result = Vec::with_capacity(indices.len() * 3);
+        for index in indices {
+            let v = self.vertices[*index as usize];
+            result.extend(&[v.x, v.y, v.z]);
+        }
+        result
+    }
+
+    /// The ends of each line, in the same layout as `MeshTree::get_edge_floats`
+    pub fn get_edge_floats(&self) -> Vec<f32> {
+        self.floats(&self.lines)
+    }
+
+    /// The corners of each triangle, in the same layout as `MeshTree::get_interior_floats`
+    pub fn get_triangle_floats(&self) -> Vec<f32> {
+        self.floats(&self.triangles)
+    }
+
+    pub fn get_vertex_floats(&self) -> Vec<f32> {
+        let finite: Vec<u32> = (0..self.vertices.len() as u32)
+            .filter(|i| self.is_finite(*i))
+            .collect();
+        self.floats(&finite)
+    }
 }
 
-impl BoundingBox {
-    pub fn split(&self) -> Vec<BoundingBox> {
-        let x_is = self.x.split();
-        let y_is = self.y.split();
-        let z_is = self.z.split();
-
-        x_is.iter()
-            .cartesian_product(y_is.iter())
-            .cartesian_product(z_is.iter())
-            .map(|((x, y), z)| BoundingBox {
-                x: x.clone(),
-                y: y.clone(),
-                z: z.clone(),
-            })
-            .collect()
+/// Where a cell sits relative to the solution set of a relation
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CellClass {
+    /// Every point in the cell satisfies the relation
+    Inside,
+    /// No point in the cell satisfies the relation
+    Outside,
+    /// The cell may contain part of the boundary, and needs to be refined
+    Boundary,
+}
+
+impl CellClass {
+    /// Classify a cell from an enclosure of the function over it
+    pub fn from_intervals<S: Scalar>(intervals: &[Interval<S>], relation: Relation) -> CellClass {
+        if intervals.is_empty() {
+            CellClass::Outside
+        } else if intervals.iter().all(|i| relation.holds_for(i)) {
+            // Checked before looking for zero, so a function that is
+            // identically zero satisfies an equation everywhere in the cell
+            CellClass::Inside
+        } else if contains_zero(intervals) {
+            CellClass::Boundary
+        } else if intervals.iter().any(|i| relation.holds_for(i)) {
+            // Split results can jump across zero without containing it,
+            // so we have to keep refining
+            CellClass::Boundary
+        } else {
+            CellClass::Outside
+        }
+    }
+}
+
+/// Equations that hold everywhere or nowhere have no boundary to mesh
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Degeneracy {
+    /// Every point in the bounding box satisfies the equations, i.e. `0 = 0`
+    Everywhere,
+    /// No point in the bounding box satisfies the equations, i.e. `1 = 0`
+    Nowhere,
+}
+
+/// Convert a vertex to the f32 point that plots are made of
+fn to_point<S: Scalar>(v: &Vector3<S>) -> Point {
+    Point::new(v.x.as_f32(), v.y.as_f32(), v.z.as_f32())
+}
+
+/// Convert a vertex to the f32 vector that geometry is made of
+fn to_f32_vector<S: Scalar>(v: &Vector3<S>) -> Vector3<f32> {
+    Vector3::new(v.x.as_f32(), v.y.as_f32(), v.z.as_f32())
+}
+
+/// Where the zero set of `f` crosses the segment from `low` to `high`, found by
+/// bisection. None if `f` has the same sign at both ends.
+fn crossing<S: Scalar, F: Function<S>>(
+    f: &Box<F>,
+    mut low: Vector3<S>,
+    mut high: Vector3<S>,
+) -> Option<Vector3<S>> {
+    let evaluate = |point: &Vector3<S>| f.evaluate(point.x, point.y, point.z);
+    let low_value = evaluate(&low);
+    if low_value == S::zero() {
+        return Some(low);
+    }
+
+    if (low_value > S::zero()) == (evaluate(&high) > S::zero()) {
+        return None;
+    }
+
+    let two = S::from_f32(2.0);
+    for _ in 0..20 {
+        let middle = (low + high) / two;
+        if (evaluate(&middle) > S::zero()) == (low_value > S::zero()) {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+
+    Some((low + high) / two)
+}
+
+/// Minimise `|A x - b|` given `ata = AᵀA` and `atb = Aᵀb`, with the pseudo-inverse
+/// of `ata`. Directions where `ata` is less than a tenth as stiff as the stiffest
+/// one are left at zero, so nearly parallel planes don't send the result flying.
+/// `ata` is symmetric, and is diagonalised with Jacobi rotations.
+fn solve_least_squares<S: Scalar>(mut ata: Matrix3<S>, atb: Vector3<S>) -> Vector3<S> {
+    let (one, two) = (S::one(), S::from_f32(2.0));
+    let mut eigenvectors = Matrix3::identity();
+    for _ in 0..8 {
+        for &(p, q) in &[(0, 1), (0, 2), (1, 2)] {
+            if ata[q][p] == S::zero() {
+                continue;
+            }
+
+            let theta = (ata[q][q] - ata[p][p]) / (two * ata[q][p]);
+            let t = theta.signum() / (theta.abs() + (theta * theta + one).sqrt());
+            let c = one / (t * t + one).sqrt();
+            let s = t * c;
+
+            let mut rotation = Matrix3::identity();
+            rotation[p][p] = c;
+            rotation[q][q] = c;
+            rotation[q][p] = s;
+            rotation[p][q] = -s;
+            ata = rotation.transpose() * ata * rotation;
+            eigenvectors = eigenvectors * rotation;
+        }
+    }
+
+    let stiffest = (0..3).map(|i| ata[i][i]).fold(S::zero(), S::max);
+    let mut result = Vector3::zero();
+    for i in 0..3 {
+        let eigenvalue = ata[i][i];
+        if eigenvalue > S::zero() && eigenvalue * S::from_f32(10.0) >= stiffest {
+            let v = eigenvectors[i];
+            result += v * (v.dot(atb) / eigenvalue);
+        }
     }
+    result
+}
+
+/// Pairs of corners, as indexed by `BoundingBox::corner`, joined by the edges of a box
+const BOX_EDGES: [(usize, usize); 12] = [
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+    (0, 2),
+    (1, 3),
+    (4, 6),
+    (5, 7),
+    (0, 1),
+    (2, 3),
+    (4, 5),
+    (6, 7),
+];
 
-    pub fn contains_root<F: Function>(&self, f: &Box<F>) -> bool {
+/// Append a vertex to a float buffer, which is f32 whatever the mesh was computed in
+fn extend_floats<S: Scalar>(result: &mut Vec<f32>, v: &Vector3<S>) {
+    result.extend(&[v.x.as_f32(), v.y.as_f32(), v.z.as_f32()]);
+}
+
+impl<S: Scalar> BoundingBox<S> {
+    fn bindings(&self) -> HashMap<char, Interval<S>> {
         let mut bindings = HashMap::new();
         bindings.insert('x', self.x);
         bindings.insert('y', self.y);
         bindings.insert('z', self.z);
-        let intervals = f.evaluate_interval(&bindings);
+        bindings
+    }
+
+    pub fn contains_root<F: Function<S>>(&self, f: &Box<F>) -> bool {
+        let intervals = f.evaluate_interval(&self.bindings());
         contains_zero(&intervals)
     }
 
-    pub fn center(&self) -> Vector3<f32> {
-        Vector3::new(self.x.middle(), self.y.middle(), self.z.middle())
+    pub fn classify<F: Function<S>>(&self, f: &Box<F>, relation: Relation) -> CellClass {
+        let intervals = f.evaluate_interval(&self.bindings());
+        CellClass::from_intervals(&intervals, relation)
+    }
+
+    /// Like `classify`, but narrows the intervals down to the function's affine
+    /// enclosure when it has one. Both are enclosures, so their intersection is too.
+    pub fn classify_affine<F: Function<S>>(&self, f: &Box<F>, relation: Relation) -> CellClass {
+        let mut intervals = f.evaluate_interval(&self.bindings());
+        if let Some(affine) = f.affine_interval(self) {
+            intervals = intervals
+                .iter()
+                .map(|i| i.intersect(&affine))
+                .filter(|i| !i.is_empty())
+                .collect();
+        }
+        CellClass::from_intervals(&intervals, relation)
+    }
+
+    /// Where the zero set crosses the line along `axis` through grid point `(i, j)`
+    /// of an `n` by `n` grid over the other two axes. Assumes `f` is monotone along
+    /// `axis` in the box, so there is at most one crossing.
+    fn sheet_point<F: Function<S>>(
+        &self,
+        f: &Box<F>,
+        axis: usize,
+        i: usize,
+        j: usize,
+        n: usize,
+    ) -> Option<Vector3<S>> {
+        let intervals = [self.x, self.y, self.z];
+        let (u, w) = ((axis + 1) % 3, (axis + 2) % 3);
+        let lerp = |interval: &Interval<S>, step: usize| {
+            interval.min
+                + (interval.max - interval.min) * S::from_f64(step as f64) / S::from_f64(n as f64)
+        };
+
+        let mut point = Vector3::zero();
+        point[u] = lerp(&intervals[u], i);
+        point[w] = lerp(&intervals[w], j);
+
+        let (mut low, mut high) = (point, point);
+        low[axis] = intervals[axis].min;
+        high[axis] = intervals[axis].max;
+        crossing(f, low, high)
     }
 
-    pub fn clamp_vector(&self, v: &mut Vector3<f32>) {
-        v.x = self.x.clamp_value(v.x);
-        v.y = self.y.clamp_value(v.y);
-        v.z = self.z.clamp_value(v.z);
+    /// The dual contouring vertex of the box: the point closest to the tangent
+    /// planes where the zero sets of `fs` cross the edges of the box, in the least
+    /// squares sense. Directions the planes leave free, along a flat sheet say, stay
+    /// at the average of the crossings, and the result is clamped to the box.
+    /// Boxes with no crossings on their edges get their center.
+    fn qef_vertex<F: Function<S>>(&self, fs: &[&Box<F>]) -> Vector3<S> {
+        let corners: Vec<Vector3<S>> = (0..8).map(|i| self.corner(i)).collect();
+
+        // Central differences at a step relative to the box, rather than
+        // `Function::gradient`, since derivatives of i.e. max are undefined where
+        // their arguments tie, which is where the features are
+        let h = (self.x.max - self.x.min) * S::from_f32(0.001);
+        let gradient = |f: &Box<F>, p: Vector3<S>| {
+            let mut result = Vector3::zero();
+            for axis in 0..3 {
+                let (mut low, mut high) = (p, p);
+                low[axis] -= h;
+                high[axis] += h;
+                result[axis] = (f.evaluate(high.x, high.y, high.z)
+                    - f.evaluate(low.x, low.y, low.z))
+                    / (h + h);
+            }
+            result
+        };
+
+        let mut crossings = Vec::new();
+        for f in fs {
+            for (a, b) in BOX_EDGES.iter() {
+                if let Some(p) = crossing(f, corners[*a], corners[*b]) {
+                    crossings.push((p, gradient(f, p)));
+                }
+            }
+        }
+
+        if crossings.is_empty() {
+            return self.center();
+        }
+
+        let mass_point = crossings
+            .iter()
+            .fold(Vector3::zero(), |sum, (p, _)| sum + p)
+            / S::from_f64(crossings.len() as f64);
+
+        let mut ata = Matrix3::zero();
+        let mut atb = Vector3::zero();
+        for (p, gradient) in &crossings {
+            let length = gradient.magnitude();
+            if !(length.is_finite() && length > S::zero()) {
+                continue;
+            }
+
+            let n = gradient / length;
+            ata += Matrix3::from_cols(n * n.x, n * n.y, n * n.z);
+            atb += n * n.dot(p - mass_point);
+        }
+
+        let mut vertex = mass_point + solve_least_squares(ata, atb);
+        self.clamp_vector(&mut vertex);
+        vertex
     }
 
     pub fn add_to_plot(&self, plot: &mut Plot) {
@@ -66,7 +356,7 @@ impl BoundingBox {
         for x in &[self.x.min, self.x.max] {
             for y in &[self.y.min, self.y.max] {
                 for z in &[self.z.min, self.z.max] {
-                    points.push(Point::new(*x, *y, *z));
+                    points.push(Point::new(x.as_f32(), y.as_f32(), z.as_f32()));
                 }
             }
         }
@@ -97,7 +387,7 @@ impl BoundingBox {
         for x in &[self.x.min, self.x.max] {
             for y in &[self.y.min, self.y.max] {
                 for z in &[self.z.min, self.z.max] {
-                    points.push(Point::new(*x, *y, *z));
+                    points.push(Point::new(x.as_f32(), y.as_f32(), z.as_f32()));
                 }
             }
         }
@@ -124,36 +414,199 @@ impl BoundingBox {
             result.extend(&[p_1.x, p_1.y, p_1.z, p_2.x, p_2.y, p_2.z]);
         }
     }
+
+    /// Add two triangles for each face of the box
+    pub fn add_face_floats(&self, result: &mut Vec<f32>) {
+        let mut points = Vec::new();
+        for x in &[self.x.min, self.x.max] {
+            for y in &[self.y.min, self.y.max] {
+                for z in &[self.z.min, self.z.max] {
+                    points.push(Point::new(x.as_f32(), y.as_f32(), z.as_f32()));
+                }
+            }
+        }
+
+        let faces = [
+            [0, 1, 3, 2],
+            [4, 5, 7, 6],
+            [0, 1, 5, 4],
+            [2, 3, 7, 6],
+            [0, 2, 6, 4],
+            [1, 3, 7, 5],
+        ];
+
+        for [a, b, c, d] in &faces {
+            for p in &[*a, *b, *c, *a, *c, *d] {
+                let point = points[*p];
+                result.extend(&[point.x, point.y, point.z]);
+            }
+        }
+    }
 }
 
-pub struct MeshTree<K: Key, F: Function> {
-    function: Box<F>,
+/// Cells are split and functions evaluated in the scalar type `S`,
+/// see `Scalar` for when f64 is worth it
+pub struct MeshTree<K: Key, F: Function<S>, S: Scalar = f32> {
+    sources: Vec<(Box<F>, Relation)>,
+    constraints: Vec<(Box<F>, Relation)>,
+    parameters: Parameters,
+    bounding_box: BoundingBox<S>,
     pub level: u32,
-    solution_map: HashMap<K, BoundingBox>,
-    vertex_map: HashMap<K, Vector3<f32>>,
+    monotone_pruning: bool,
+    affine_arithmetic: bool,
+    solution_map: HashMap<K, BoundingBox<S>>,
+    interior_map: HashMap<K, BoundingBox<S>>,
+    sheet_map: HashMap<K, (BoundingBox<S>, usize)>,
+    sheet_edges: Vec<(Vector3<S>, Vector3<S>)>,
+    vertex_map: HashMap<K, Vector3<S>>,
     edge_set: HashSet<(K, K)>,
-    triangle_set: HashSet<[K; 3]>,
+    quad_set: Vec<[K; 4]>,
 }
 
-impl<F: Function> MeshTree<MortonKey, F> {
-    pub fn new(f: Box<F>, bb: BoundingBox) -> MeshTree<MortonKey, F> {
+impl<S: Scalar, F: Function<S>> MeshTree<MortonKey, F, S> {
+    pub fn new(f: Box<F>, bb: BoundingBox<S>) -> MeshTree<MortonKey, F, S> {
+        MeshTree::new_relation(f, Relation::Equal, bb)
+    }
+
+    /// Mesh the region where `f` satisfies `relation`. Cells along the boundary
+    /// are refined each level, while cells found to be fully inside are kept
+    /// in the interior map at the level they were found.
+    pub fn new_relation(
+        f: Box<F>,
+        relation: Relation,
+        bb: BoundingBox<S>,
+    ) -> MeshTree<MortonKey, F, S> {
+        MeshTree::new_system(vec![(f, relation)], bb)
+    }
+
+    /// Mesh the set of points satisfying every constraint at once.
+    /// Two or more equations will generally intersect in curves, see `is_curve`.
+    pub fn new_system(
+        constraints: Vec<(Box<F>, Relation)>,
+        bb: BoundingBox<S>,
+    ) -> MeshTree<MortonKey, F, S> {
         let mut result = MeshTree {
-            function: f,
+            constraints: MeshTree::<MortonKey, F, S>::bind_constraints(
+                &constraints,
+                &Parameters::new(),
+            ),
+            sources: constraints,
+            parameters: Parameters::new(),
+            bounding_box: bb,
             level: 0,
+            monotone_pruning: false,
+            affine_arithmetic: false,
             edge_set: HashSet::new(),
             solution_map: HashMap::new(),
+            interior_map: HashMap::new(),
+            sheet_map: HashMap::new(),
+            sheet_edges: Vec::new(),
             vertex_map: HashMap::new(),
-            triangle_set: HashSet::new(),
+            quad_set: Vec::new(),
         };
 
         let root_key = MortonKey::root_key();
-        if bb.contains_root(&result.function) {
-            result.solution_map.insert(root_key, bb);
-        }
+        result.insert_cell(root_key, bb);
 
         result
     }
 
+    fn bind_constraints(
+        constraints: &[(Box<F>, Relation)],
+        parameters: &Parameters,
+    ) -> Vec<(Box<F>, Relation)> {
+        constraints
+            .iter()
+            .map(|(f, relation)| (Box::new(f.bind_parameters(parameters)), *relation))
+            .collect()
+    }
+
+    pub fn get_parameters(&self) -> &Parameters {
+        &self.parameters
+    }
+
+    /// Bind new parameter values and re-mesh back down to the current level.
+    /// Vertices, edges and triangles need to be regenerated afterwards.
+    pub fn set_parameters(&mut self, parameters: Parameters) {
+        let level = self.level;
+
+        self.constraints =
+            MeshTree::<MortonKey, F, S>::bind_constraints(&self.sources, &parameters);
+        self.parameters = parameters;
+        self.level = 0;
+        self.solution_map.clear();
+        self.interior_map.clear();
+        self.sheet_map.clear();
+        self.sheet_edges.clear();
+        self.vertex_map.clear();
+        self.edge_set.clear();
+        self.quad_set.clear();
+        self.insert_cell(MortonKey::root_key(), self.bounding_box);
+
+        while self.level < level {
+            self.next_level();
+        }
+    }
+
+    /// Opt in to leaving boundary cells unrefined once the gradient proves the
+    /// surface passes through them as a single sheet. Those cells are meshed by
+    /// sampling the sheet at the resolution of the current level instead.
+    /// Only applies to a single equation, and re-meshes back down to the current level.
+    pub fn set_monotone_pruning(&mut self, enabled: bool) {
+        self.monotone_pruning = enabled;
+        self.set_parameters(self.parameters.clone());
+    }
+
+    /// Opt in to classifying cells with affine arithmetic as well as intervals,
+    /// which costs more per cell but keeps fewer cells where the constraints
+    /// use the same variable in several places. Re-meshes back down to the current level.
+    pub fn set_affine_arithmetic(&mut self, enabled: bool) {
+        self.affine_arithmetic = enabled;
+        self.set_parameters(self.parameters.clone());
+    }
+
+    /// The axis along which the only constraint is strictly monotone in the cell, if any.
+    /// Inequalities are left alone, their cells have to be refined to find the interior.
+    fn sheet_axis(&self, bb: &BoundingBox<S>) -> Option<usize> {
+        if self.constraints.len() != 1 || self.constraints[0].1 != Relation::Equal {
+            return None;
+        }
+
+        self.constraints[0]
+            .0
+            .gradient_interval(bb)
+            .and_then(|gradient| monotone_axis(&gradient))
+    }
+
+    fn classify(&self, bb: &BoundingBox<S>) -> CellClass {
+        let mut class = CellClass::Inside;
+        for (f, relation) in &self.constraints {
+            let constraint_class = if self.affine_arithmetic {
+                bb.classify_affine(f, *relation)
+            } else {
+                bb.classify(f, *relation)
+            };
+            match constraint_class {
+                CellClass::Outside => return CellClass::Outside,
+                CellClass::Boundary => class = CellClass::Boundary,
+                CellClass::Inside => (),
+            }
+        }
+        class
+    }
+
+    fn insert_cell(&mut self, key: MortonKey, bb: BoundingBox<S>) {
+        match self.classify(&bb) {
+            CellClass::Boundary => {
+                self.solution_map.insert(key, bb);
+            }
+            CellClass::Inside => {
+                self.interior_map.insert(key, bb);
+            }
+            CellClass::Outside => (),
+        }
+    }
+
     pub fn get_level(&self) -> u32 {
         self.level
     }
@@ -162,35 +615,85 @@ impl<F: Function> MeshTree<MortonKey, F> {
         self.solution_map.len()
     }
 
+    pub fn get_interior_cell_count(&self) -> usize {
+        self.interior_map.len()
+    }
+
+    /// The number of cells left unrefined by monotone pruning
+    pub fn get_sheet_cell_count(&self) -> usize {
+        self.sheet_map.len()
+    }
+
+    /// Whether the system turned out to hold everywhere or nowhere, in which case
+    /// there is nothing left to refine. Constraints should be simplified first,
+    /// so that i.e. `x - x = 0` is seen as `0 = 0`. A system is only said to hold
+    /// everywhere if its constraints are defined everywhere.
+    pub fn degeneracy(&self) -> Option<Degeneracy> {
+        if !self.solution_map.is_empty() || !self.sheet_map.is_empty() {
+            None
+        } else if self.interior_map.contains_key(&MortonKey::root_key()) {
+            if self.constraints.iter().all(|(f, _)| f.is_total()) {
+                Some(Degeneracy::Everywhere)
+            } else {
+                None
+            }
+        } else if self.interior_map.is_empty() {
+            Some(Degeneracy::Nowhere)
+        } else {
+            None
+        }
+    }
+
+    /// With more than one equation the solution is expected to be made of curves
+    /// rather than surfaces, and should be meshed with `generate_curve_set`
+    pub fn is_curve(&self) -> bool {
+        self.constraints
+            .iter()
+            .filter(|(_, relation)| *relation == Relation::Equal)
+            .count()
+            > 1
+    }
+
     pub fn next_level(&mut self) {
         self.vertex_map.clear();
         self.edge_set.clear();
-        self.triangle_set.clear();
+        self.quad_set.clear();
+        self.sheet_edges.clear();
         self.level += 1;
 
-        let mut new_solution_map = HashMap::new();
+        let old_solution_map = std::mem::replace(&mut self.solution_map, HashMap::new());
+
+        for (key, bb) in &old_solution_map {
+            if self.monotone_pruning {
+                if let Some(axis) = self.sheet_axis(bb) {
+                    self.sheet_map.insert(*key, (*bb, axis));
+                    continue;
+                }
+            }
 
-        for (key, bb) in &self.solution_map {
             let child_keys: Vec<MortonKey> = (0..8u64).map(|i| key.child_key(i)).collect();
             let child_bb = bb.split();
 
             for i in 0..8 {
-                let child_key = child_keys[i];
-                let child_bb = child_bb[i];
-                if child_bb.contains_root(&self.function) {
-                    new_solution_map.insert(child_key, child_bb);
-                }
+                self.insert_cell(child_keys[i], child_bb[i]);
             }
         }
-
-        self.solution_map = new_solution_map;
     }
 
+    /// One vertex per solution cell. Surfaces get the dual contouring vertex of
+    /// each cell, see `generate_quad_set`, while curves go through cell centers.
     pub fn generate_vertex_map(&mut self) {
-        self.vertex_map.clear();
+        let surfaces = self.surface_constraints();
+        let mut vertex_map = HashMap::new();
         for (key, bb) in &self.solution_map {
-            self.vertex_map.insert(key.clone(), bb.center());
+            let vertex = if surfaces.is_empty() {
+                bb.center()
+            } else {
+                bb.qef_vertex(&surfaces)
+            };
+            vertex_map.insert(*key, vertex);
         }
+        self.vertex_map = vertex_map;
     }
 
     pub fn generate_edge_set(&mut self) {
@@ -204,6 +707,65 @@ impl<F: Function> MeshTree<MortonKey, F> {
                     self.edge_set.insert((key.clone(), n_k.clone()));
                 })
         }
+
+        self.generate_sheet_edges();
+    }
+
+    /// Sample each pruned cell's sheet on a grid as fine as the current level,
+    /// by bisecting along the monotone axis, and connect neighboring samples.
+    fn generate_sheet_edges(&mut self) {
+        self.sheet_edges.clear();
+        if self.sheet_map.is_empty() {
+            return;
+        }
+
+        let f = &self.constraints[0].0;
+        for (key, (bb, axis)) in &self.sheet_map {
+            let n = 1usize << (self.level - key.level());
+            let samples: Vec<Vec<Option<Vector3<S>>>> = (0..=n)
+                .map(|i| (0..=n).map(|j| bb.sheet_point(f, *axis, i, j, n)).collect())
+                .collect();
+
+            for i in 0..=n {
+                for j in 0..=n {
+                    if let Some(p) = samples[i][j] {
+                        if let Some(Some(q)) = samples.get(i + 1).map(|row| row[j]) {
+                            self.sheet_edges.push((p, q));
+                        }
+                        if let Some(Some(q)) = samples[i].get(j + 1) {
+                            self.sheet_edges.push((p, *q));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Connect solution cells into polylines through their centers.
+    /// Face neighbors are always connected, while diagonal neighbors are only
+    /// connected when no face neighbor already links the two cells.
+    pub fn generate_curve_set(&mut self) {
+        for key in self.solution_map.keys() {
+            let face_neighbors: HashSet<MortonKey> = key
+                .component_neighbors()
+                .filter(|n_k| self.solution_map.contains_key(n_k))
+                .collect();
+
+            for n_k in key.neighbors() {
+                if n_k <= *key || !self.solution_map.contains_key(&n_k) {
+                    continue;
+                }
+
+                let is_face_neighbor = face_neighbors.contains(&n_k);
+                let is_linked = n_k
+                    .component_neighbors()
+                    .any(|m_k| face_neighbors.contains(&m_k));
+
+                if is_face_neighbor || !is_linked {
+                    self.edge_set.insert((*key, n_k));
+                }
+            }
+        }
     }
 
     pub fn relax_vertices(&mut self) {
@@ -211,7 +773,7 @@ impl<F: Function> MeshTree<MortonKey, F> {
         for (key, vertex) in &self.vertex_map {
             let neighbors: Vec<MortonKey> = key.clone().component_neighbors().collect();
 
-            let mut sum = Vector3::new(0.0, 0.0, 0.0);
+            let mut sum = Vector3::zero();
             let mut count = 0;
             for neighbor_key in neighbors {
                 if let Some(neighbor) = self.vertex_map.get(&neighbor_key) {
@@ -225,9 +787,9 @@ impl<F: Function> MeshTree<MortonKey, F> {
                 continue;
             }
 
-            sum /= count as f32;
+            sum /= S::from_f64(count as f64);
 
-            let mut new_v = vertex + 0.2 * (sum - vertex);
+            let mut new_v = vertex + (sum - vertex) * S::from_f32(0.2);
             let bb = self.solution_map.get(&key).unwrap();
             bb.clamp_vector(&mut new_v);
 
@@ -237,61 +799,121 @@ impl<F: Function> MeshTree<MortonKey, F> {
         self.vertex_map = new_vertex_map;
     }
 
-    pub fn add_vertex_triangles(&mut self, vertex_key: MortonKey) {
-        let maybe_neighbors: Vec<Option<MortonKey>> = Neighbor::component_neighbors()
-            .map(|neighbor| vertex_key.neighbor_key(neighbor))
-            .map(|maybe_neighbor_key| {
-                if maybe_neighbor_key.is_none() {
-                    return None;
-                }
-
-                let neighbor_key = maybe_neighbor_key.unwrap();
+    /// The constraints whose zero sets bound the solution: the equation if there is
+    /// one, otherwise every inequality. Empty for curves, which have no surface.
+    fn surface_constraints(&self) -> Vec<&Box<F>> {
+        if self.is_curve() {
+            return Vec::new();
+        }
 
-                if self.solution_map.contains_key(&neighbor_key) {
-                    Some(neighbor_key)
-                } else {
-                    None
-                }
-            })
+        let equations: Vec<&Box<F>> = self
+            .constraints
+            .iter()
+            .filter(|(_, relation)| *relation == Relation::Equal)
+            .map(|(f, _)| f)
             .collect();
+        if equations.is_empty() {
+            self.constraints.iter().map(|(f, _)| f).collect()
+        } else {
+            equations
+        }
+    }
+
+    /// Dual contouring: every edge of the grid at the current level that the surface
+    /// crosses is shared by four solution cells, which are joined into a quad around it.
+    /// The quads are wound counterclockwise seen from where the constraint is positive.
+    /// Cells along the bounding box or left unrefined by monotone pruning are missing
+    /// some of their neighbors, so the surface is left open there.
+    pub fn generate_quad_set(&mut self) {
+        self.quad_set.clear();
+        let surfaces = self.surface_constraints();
+        let less = |key: &MortonKey, axes: &[usize]| {
+            let mut relations = [NeighborRelation::Same; 3];
+            for axis in axes {
+                relations[*axis] = NeighborRelation::Less;
+            }
+            key.neighbor_key(Neighbor::from_components(
+                relations[0],
+                relations[1],
+                relations[2],
+            ))
+            .filter(|n_k| self.solution_map.contains_key(n_k))
+        };
+
+        let mut quads = Vec::new();
+        for (key, bb) in &self.solution_map {
+            // Each cell looks after the edges leaving its min corner, which it
+            // shares with the cells below it along the other two axes
+            let start = bb.corner(0);
+            for axis in 0..3 {
+                let (u, w) = ((axis + 1) % 3, (axis + 2) % 3);
+                let neighbors = (less(key, &[u, w]), less(key, &[w]), less(key, &[u]));
+                let (k_uw, k_w, k_u) = match neighbors {
+                    (Some(k_uw), Some(k_w), Some(k_u)) => (k_uw, k_w, k_u),
+                    _ => continue,
+                };
 
-        let triangle_iter = [
-            [0, 1], // [Neighbor { x: Same, y: Same, z: Less }, Neighbor { x: Same, y: Same, z: More }]
-            //            [0, 2], // [Neighbor { x: Same, y: Same, z: Less }, Neighbor { x: Same, y: Less, z: Same }]
-            [0, 3], // [Neighbor { x: Same, y: Same, z: Less }, Neighbor { x: Same, y: More, z: Same }]
-            //            [0, 4], // [Neighbor { x: Same, y: Same, z: Less }, Neighbor { x: Less, y: Same, z: Same }]
-            [0, 5], // [Neighbor { x: Same, y: Same, z: Less }, Neighbor { x: More, y: Same, z: Same }]
-            //            [1, 2], // [Neighbor { x: Same, y: Same, z: More }, Neighbor { x: Same, y: Less, z: Same }]
-            [1, 3], // [Neighbor { x: Same, y: Same, z: More }, Neighbor { x: Same, y: More, z: Same }]
-            //            [1, 4], // [Neighbor { x: Same, y: Same, z: More }, Neighbor { x: Less, y: Same, z: Same }]
-            [1, 5], // [Neighbor { x: Same, y: Same, z: More }, Neighbor { x: More, y: Same, z: Same }]
-            //            [2, 3], // [Neighbor { x: Same, y: Less, z: Same }, Neighbor { x: Same, y: More, z: Same }]
-            [2, 4], // [Neighbor { x: Same, y: Less, z: Same }, Neighbor { x: Less, y: Same, z: Same }]
-            //            [2, 5], // [Neighbor { x: Same, y: Less, z: Same }, Neighbor { x: More, y: Same, z: Same }]
-            [3, 4], // [Neighbor { x: Same, y: More, z: Same }, Neighbor { x: Less, y: Same, z: Same }]
-            //           [3, 5], // [Neighbor { x: Same, y: More, z: Same }, Neighbor { x: More, y: Same, z: Same }]
-            [4, 5usize], // [Neighbor { x: Less, y: Same, z: Same }, Neighbor { x: More, y: Same, z: Same }]
-        ]
-        .iter()
-        .filter_map(|[a, b]| {
-            match (maybe_neighbors[a.clone()], maybe_neighbors[b.clone()]) {
-                (Some(key_1), Some(key_2)) => {
-                    let mut triangle = [vertex_key, key_1, key_2];
-                    triangle.sort();
-                    Some(triangle)
+                let mut end = start;
+                end[axis] = bb.corner(7)[axis];
+                for f in &surfaces {
+                    let start_value = f.evaluate(start.x, start.y, start.z);
+                    let end_value = f.evaluate(end.x, end.y, end.z);
+                    if (start_value > S::zero()) != (end_value > S::zero()) {
+                        if end_value > S::zero() {
+                            quads.push([k_uw, k_w, *key, k_u]);
+                        } else {
+                            quads.push([k_u, *key, k_w, k_uw]);
+                        }
+                        break;
+                    }
                 }
-                _ => None,
             }
-        });
+        }
 
-        self.triangle_set.extend(triangle_iter);
+        self.quad_set = quads;
+        self.generate_sheet_edges();
     }
 
-    pub fn generate_triangle_set(&mut self) {
-        let keys: Vec<MortonKey> = self.solution_map.keys().map(|k| k.clone()).collect();
-        for key in keys {
-            self.add_vertex_triangles(key.clone());
+    /// The dual contoured surface, with each quad split into two triangles along
+    /// its shorter diagonal, and the sheets of pruned cells as lines.
+    /// Needs `generate_vertex_map` and `generate_quad_set` to have been called.
+    pub fn get_geometry(&self) -> Geometry {
+        let mut geometry = Geometry {
+            vertices: Vec::new(),
+            triangles: Vec::new(),
+            lines: Vec::new(),
+        };
+
+        let mut indices = HashMap::new();
+        for quad in &self.quad_set {
+            let mut corners = [0u32; 4];
+            for (corner, key) in corners.iter_mut().zip(quad.iter()) {
+                *corner = *indices.entry(*key).or_insert_with(|| {
+                    let vertex = self.vertex_map.get(key).unwrap();
+                    geometry.vertices.push(to_f32_vector(vertex));
+                    geometry.vertices.len() as u32 - 1
+                });
+            }
+
+            let [a, b, c, d] = corners;
+            let vertex = |i: u32| geometry.vertices[i as usize];
+            if (vertex(a) - vertex(c)).magnitude2() <= (vertex(b) - vertex(d)).magnitude2() {
+                geometry.add_triangle([a, b, c]);
+                geometry.add_triangle([a, c, d]);
+            } else {
+                geometry.add_triangle([a, b, d]);
+                geometry.add_triangle([b, c, d]);
+            }
+        }
+
+        for (p, q) in &self.sheet_edges {
+            geometry.vertices.push(to_f32_vector(p));
+            geometry.vertices.push(to_f32_vector(q));
+            let end = geometry.vertices.len() as u32;
+            geometry.add_line(end - 2, end - 1);
         }
+
+        geometry
     }
 
     pub fn add_to_plot(
@@ -300,21 +922,28 @@ impl<F: Function> MeshTree<MortonKey, F> {
         add_vertices: bool,
         add_edges: bool,
         add_mesh: bool,
+        add_interior: bool,
         plot: &mut Plot,
     ) {
         if add_bb {
             for bb in self.solution_map.values() {
                 bb.add_to_plot(plot);
             }
+
+            for (bb, _) in self.sheet_map.values() {
+                bb.add_to_plot(plot);
+            }
+        }
+
+        if add_interior {
+            for bb in self.interior_map.values() {
+                bb.add_to_plot(plot);
+            }
         }
 
         if add_vertices {
             for vertex in self.vertex_map.values() {
-                plot.add_point(Point {
-                    x: vertex.x,
-                    y: vertex.y,
-                    z: vertex.z,
-                });
+                plot.add_point(to_point(vertex));
             }
         }
 
@@ -323,47 +952,30 @@ impl<F: Function> MeshTree<MortonKey, F> {
                 let c1 = &self.vertex_map.get(key1).unwrap();
                 let c2 = &self.vertex_map.get(key2).unwrap();
 
-                let p1 = Point {
-                    x: c1.x,
-                    y: c1.y,
-                    z: c1.z,
-                };
-
-                let p2 = Point {
-                    x: c2.x,
-                    y: c2.y,
-                    z: c2.z,
-                };
-
-                plot.add_line(LineSegment::new(p1, p2));
+                plot.add_line(LineSegment::new(to_point(c1), to_point(c2)));
             }
         }
 
         if add_mesh {
             let mut edges = HashSet::new();
-            for k in &self.triangle_set {
-                edges.insert((k[0].clone(), k[1].clone()));
-                edges.insert((k[0].clone(), k[2].clone()));
-                edges.insert((k[1].clone(), k[2].clone()));
+            for quad in &self.quad_set {
+                for i in 0..4 {
+                    let (k1, k2) = (quad[i], quad[(i + 1) % 4]);
+                    edges.insert((k1.min(k2), k1.max(k2)));
+                }
             }
 
             for e in edges {
                 let c1 = &self.vertex_map.get(&e.0).unwrap();
                 let c2 = &self.vertex_map.get(&e.1).unwrap();
 
-                let p1 = Point {
-                    x: c1.x,
-                    y: c1.y,
-                    z: c1.z,
-                };
-
-                let p2 = Point {
-                    x: c2.x,
-                    y: c2.y,
-                    z: c2.z,
-                };
+                plot.add_line(LineSegment::new(to_point(c1), to_point(c2)));
+            }
+        }
 
-                plot.add_line(LineSegment::new(p1, p2));
+        if add_edges || add_mesh {
+            for (c1, c2) in &self.sheet_edges {
+                plot.add_line(LineSegment::new(to_point(c1), to_point(c2)));
             }
         }
     }
@@ -375,7 +987,13 @@ impl<F: Function> MeshTree<MortonKey, F> {
             let c1 = &self.vertex_map.get(key1).unwrap();
             let c2 = &self.vertex_map.get(key2).unwrap();
 
-            result.extend(&[c1.x, c1.y, c1.z, c2.x, c2.y, c2.z]);
+            extend_floats(&mut result, c1);
+            extend_floats(&mut result, c2);
+        }
+
+        for (c1, c2) in &self.sheet_edges {
+            extend_floats(&mut result, c1);
+            extend_floats(&mut result, c2);
         }
 
         result
@@ -385,7 +1003,7 @@ impl<F: Function> MeshTree<MortonKey, F> {
         let mut result = Vec::with_capacity(self.vertex_map.len() * 3);
 
         for vertex in self.vertex_map.values() {
-            result.extend(&[vertex.x, vertex.y, vertex.z]);
+            extend_floats(&mut result, vertex);
         }
 
         result
@@ -398,6 +1016,419 @@ impl<F: Function> MeshTree<MortonKey, F> {
             bb.add_floats(&mut result);
         }
 
+        for (bb, _) in self.sheet_map.values() {
+            bb.add_floats(&mut result);
+        }
+
         result
     }
+
+    /// Triangles filling in each cell that is fully inside the region
+    pub fn get_interior_floats(&self) -> Vec<f32> {
+        let mut result = Vec::with_capacity(self.interior_map.len() * 108);
+
+        for bb in self.interior_map.values() {
+            bb.add_face_floats(&mut result);
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::CompiledFunction;
+    use crate::parser::{parse_program_with_coordinates, parse_relation, parse_system};
+
+    fn cube(min: f32, max: f32) -> BoundingBox {
+        let interval = Interval::new(min, max);
+        BoundingBox {
+            x: interval,
+            y: interval,
+            z: interval,
+        }
+    }
+
+    #[test]
+    fn test_classify() {
+        let input: Vec<char> = "x^2 + y^2 + z^2 < 25".chars().collect();
+        let (f, relation) = parse_relation(&input, 0).unwrap();
+
+        assert_eq!(cube(-1.0, 1.0).classify(&f, relation), CellClass::Inside);
+        assert_eq!(cube(6.0, 8.0).classify(&f, relation), CellClass::Outside);
+        assert_eq!(cube(2.0, 4.0).classify(&f, relation), CellClass::Boundary);
+
+        let input: Vec<char> = "x^2 + y^2 + z^2 = 25".chars().collect();
+        let (f, relation) = parse_relation(&input, 0).unwrap();
+        assert_eq!(cube(-1.0, 1.0).classify(&f, relation), CellClass::Outside);
+        assert_eq!(cube(2.0, 4.0).classify(&f, relation), CellClass::Boundary);
+    }
+
+    #[test]
+    fn test_degeneracy() {
+        let degeneracy = |equation: &str, simplify: bool| {
+            let input: Vec<char> = equation.chars().collect();
+            let mut constraints = parse_system(&input, 0).unwrap();
+            if simplify {
+                for (f, _) in &mut constraints {
+                    *f = Box::new(f.simplify());
+                }
+            }
+            let mut mtree = MeshTree::new_system(constraints, cube(-20.0, 20.0));
+            let before = mtree.degeneracy();
+            mtree.next_level();
+            assert_eq!(mtree.degeneracy(), before);
+            before
+        };
+
+        assert_eq!(degeneracy("0 = 0", false), Some(Degeneracy::Everywhere));
+        assert_eq!(degeneracy("1 = 0", false), Some(Degeneracy::Nowhere));
+        assert_eq!(
+            degeneracy("x^2 + 1 > 0", false),
+            Some(Degeneracy::Everywhere)
+        );
+        assert_eq!(
+            degeneracy("x^2 + y^2 + z^2 = -1", false),
+            Some(Degeneracy::Nowhere)
+        );
+        assert_eq!(
+            degeneracy("0 = 0, x^2 + 1 < 0", false),
+            Some(Degeneracy::Nowhere)
+        );
+        assert_eq!(degeneracy("x^2 + y^2 + z^2 = 25", false), None);
+        assert_eq!(degeneracy("0 = 0, x = 1", false), None);
+
+        // Interval arithmetic can't see the cancellation on its own
+        assert_eq!(degeneracy("x - x = 0", false), None);
+        assert_eq!(degeneracy("x - x = 0", true), Some(Degeneracy::Everywhere));
+
+        // Undefined for negative x, though the enclosure of the defined part is just 0
+        assert_eq!(degeneracy("sqrt(x) * 0 = 0", true), None);
+        assert_eq!(degeneracy("x / x = 1", true), None);
+
+        // Nothing is kept around to refine
+        let input: Vec<char> = "0 = 0".chars().collect();
+        let mut mtree = MeshTree::new_system(parse_system(&input, 0).unwrap(), cube(-20.0, 20.0));
+        for _ in 0..4 {
+            mtree.next_level();
+        }
+        assert_eq!(mtree.get_solution_cell_count(), 0);
+        assert_eq!(mtree.get_interior_cell_count(), 1);
+    }
+
+    #[test]
+    fn test_system_curve() {
+        let input: Vec<char> = "x^2 + y^2 = 100, z = 0.5".chars().collect();
+        let constraints = parse_system(&input, 0).unwrap();
+        let mut mtree = MeshTree::new_system(constraints, cube(-20.0, 20.0));
+        assert!(mtree.is_curve());
+
+        for _ in 0..4 {
+            mtree.next_level();
+        }
+        mtree.generate_vertex_map();
+        mtree.generate_curve_set();
+
+        // Every cell must straddle both the cylinder and the plane
+        assert!(mtree.get_solution_cell_count() > 0);
+        for bb in mtree.solution_map.values() {
+            assert!(bb.z.min <= 0.5 && bb.z.max >= 0.5);
+        }
+
+        // The circle is closed, so every cell should be on the polyline
+        let mut plot = Plot::new();
+        mtree.add_to_plot(false, false, true, false, false, &mut plot);
+        assert!(plot.lines.len() >= mtree.get_solution_cell_count());
+        for key in mtree.solution_map.keys() {
+            assert!(mtree.edge_set.iter().any(|(k1, k2)| k1 == key || k2 == key));
+        }
+    }
+
+    #[test]
+    fn test_set_parameters() {
+        let input: Vec<char> = "x^2 + y^2 + z^2 = r^2".chars().collect();
+        let constraints = parse_system(&input, 0).unwrap();
+        let mut mtree = MeshTree::new_system(constraints, cube(-20.0, 20.0));
+
+        let mut parameters = Parameters::new();
+        parameters.insert("r".to_string(), 10.0);
+        mtree.set_parameters(parameters.clone());
+        for _ in 0..3 {
+            mtree.next_level();
+        }
+        let cell_count = mtree.get_solution_cell_count();
+
+        // Shrinking the sphere re-meshes at the same level with fewer cells
+        parameters.insert("r".to_string(), 3.0);
+        mtree.set_parameters(parameters);
+        assert_eq!(mtree.get_level(), 3);
+        assert!(mtree.get_solution_cell_count() < cell_count);
+        assert_eq!(mtree.get_parameters()["r"], 3.0);
+        for bb in mtree.solution_map.values() {
+            assert!(bb.x.min <= 3.0 && bb.x.max >= -3.0);
+        }
+    }
+
+    #[test]
+    fn test_inequality_interior() {
+        let input: Vec<char> = "x^2 + y^2 + z^2 <= 100".chars().collect();
+        let (f, relation) = parse_relation(&input, 0).unwrap();
+        let mut mtree = MeshTree::new_relation(f, relation, cube(-20.0, 20.0));
+
+        assert_eq!(mtree.get_interior_cell_count(), 0);
+        for _ in 0..3 {
+            mtree.next_level();
+        }
+
+        // Interior cells are never refined, so all of them stay fully inside
+        assert!(mtree.get_interior_cell_count() > 0);
+        assert!(mtree.get_solution_cell_count() > 0);
+        for bb in mtree.interior_map.values() {
+            let corner = Vector3::new(
+                bb.x.min.abs().max(bb.x.max.abs()),
+                bb.y.min.abs().max(bb.y.max.abs()),
+                bb.z.min.abs().max(bb.z.max.abs()),
+            );
+            assert!(corner.x * corner.x + corner.y * corner.y + corner.z * corner.z <= 100.0);
+        }
+        assert_eq!(
+            mtree.get_interior_floats().len(),
+            mtree.get_interior_cell_count() * 108
+        );
+    }
+
+    #[test]
+    fn test_spherical_coordinates() {
+        let input: Vec<char> = "r = 10 + 3 * sin(5 * theta)".chars().collect();
+        let program = parse_program_with_coordinates(&input, 0).unwrap();
+        let mut mtree = MeshTree::new_system(program.expand(), cube(-20.0, 20.0));
+        for _ in 0..4 {
+            mtree.next_level();
+        }
+
+        // Every solution cell reaches between the smallest and largest radius
+        assert!(mtree.get_solution_cell_count() > 0);
+        for bb in mtree.solution_map.values() {
+            let near = |i: &Interval| i.clamp_value(0.0);
+            let far = |i: &Interval| i.min.abs().max(i.max.abs());
+            let nearest = Vector3::new(near(&bb.x), near(&bb.y), near(&bb.z)).magnitude();
+            let farthest = Vector3::new(far(&bb.x), far(&bb.y), far(&bb.z)).magnitude();
+            assert!(nearest <= 13.0 && farthest >= 7.0);
+        }
+    }
+
+    #[test]
+    fn test_monotone_pruning() {
+        let input: Vec<char> = "x^2 + y^2 + z^2 = 100".chars().collect();
+        let (f, relation) = parse_relation(&input, 0).unwrap();
+        let mut pruned = MeshTree::new_relation(f.clone(), relation, cube(-20.0, 20.0));
+        let mut full = MeshTree::new_relation(f.clone(), relation, cube(-20.0, 20.0));
+        pruned.set_monotone_pruning(true);
+        for _ in 0..5 {
+            pruned.next_level();
+            full.next_level();
+        }
+
+        // Most of the sphere is a graph over one of the planes
+        assert!(pruned.get_sheet_cell_count() > 0);
+        assert!(
+            pruned.get_solution_cell_count() + pruned.get_sheet_cell_count()
+                < full.get_solution_cell_count()
+        );
+
+        // Sheets are still sampled at the current level's resolution
+        pruned.generate_vertex_map();
+        pruned.generate_quad_set();
+        assert!(!pruned.sheet_edges.is_empty());
+        for (p, q) in &pruned.sheet_edges {
+            for v in &[p, q] {
+                assert_similiar!((v.x * v.x + v.y * v.y + v.z * v.z).sqrt(), 10.0);
+            }
+        }
+
+        // Turning it off goes back to refining everything
+        pruned.set_monotone_pruning(false);
+        assert_eq!(pruned.get_sheet_cell_count(), 0);
+        assert_eq!(
+            pruned.get_solution_cell_count(),
+            full.get_solution_cell_count()
+        );
+
+        // Inequalities are never pruned
+        let input: Vec<char> = "x^2 + y^2 + z^2 < 100".chars().collect();
+        let (f, relation) = parse_relation(&input, 0).unwrap();
+        let mut inequality = MeshTree::new_relation(f, relation, cube(-20.0, 20.0));
+        inequality.set_monotone_pruning(true);
+        for _ in 0..3 {
+            inequality.next_level();
+        }
+        assert_eq!(inequality.get_sheet_cell_count(), 0);
+    }
+
+    #[test]
+    fn test_split_division() {
+        let input: Vec<char> = "1/x + 1/y - z = 0".chars().collect();
+        let (f, relation) = parse_relation(&input, 0).unwrap();
+
+        // 1/x = z - 1/y is between -1/2 and -1/4, so there's no solution for x
+        // in [-1, 1], but the hull of 1/x over it is everything
+        let bb = BoundingBox {
+            x: Interval::new(-1.0, 1.0),
+            y: Interval::new(2.0, 3.0),
+            z: Interval::new(0.0, 0.1),
+        };
+        assert_eq!(bb.classify(&f, relation), CellClass::Outside);
+        let compiled = Box::new(CompiledFunction::compile(&f));
+        assert_eq!(bb.classify(&compiled, relation), CellClass::Outside);
+
+        // Both evaluators keep the pieces apart, so they refine the same cells.
+        // The cube is offset so that cells straddle the poles at x = 0 and y = 0
+        let mut tree = MeshTree::new_relation(f, relation, cube(-3.7, 4.3));
+        let mut bytecode = MeshTree::new_relation(compiled, relation, cube(-3.7, 4.3));
+        for _ in 0..4 {
+            tree.next_level();
+            bytecode.next_level();
+            let mut tree_keys: Vec<_> = tree.solution_map.keys().collect();
+            let mut bytecode_keys: Vec<_> = bytecode.solution_map.keys().collect();
+            tree_keys.sort();
+            bytecode_keys.sort();
+            assert_eq!(tree_keys, bytecode_keys);
+        }
+    }
+
+    /// Every edge between triangles is used once in each direction
+    fn assert_watertight(geometry: &Geometry) {
+        let mut edges = HashMap::new();
+        for triangle in geometry.triangles.chunks(3) {
+            for i in 0..3 {
+                *edges
+                    .entry((triangle[i], triangle[(i + 1) % 3]))
+                    .or_insert(0) += 1;
+            }
+        }
+        for ((a, b), count) in &edges {
+            assert_eq!(*count, 1);
+            assert_eq!(edges.get(&(*b, *a)), Some(&1));
+        }
+    }
+
+    #[test]
+    fn test_dual_contouring() {
+        let input: Vec<char> = "x^2 + y^2 + z^2 = 4".chars().collect();
+        let (f, relation) = parse_relation(&input, 0).unwrap();
+        let mut mtree = MeshTree::new_relation(f, relation, cube(-3.7, 4.3));
+        for _ in 0..4 {
+            mtree.next_level();
+        }
+        mtree.generate_vertex_map();
+        mtree.generate_quad_set();
+        let geometry = mtree.get_geometry();
+
+        assert!(!geometry.triangles.is_empty());
+        assert_watertight(&geometry);
+        for v in &geometry.vertices {
+            assert!((v.magnitude() - 2.0).abs() < 0.05);
+        }
+
+        // Triangles face out of the sphere, where the function is positive
+        for triangle in geometry.triangles.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| geometry.vertices[triangle[i] as usize]);
+            assert!((b - a).cross(c - a).dot(a + b + c) > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_dual_contouring_features() {
+        // The corners and edges of a cube are kept, where cell centers would round them off
+        let input: Vec<char> = "max(abs(x), max(abs(y), abs(z))) = 1".chars().collect();
+        let (f, relation) = parse_relation(&input, 0).unwrap();
+        let mut mtree = MeshTree::new_relation(f, relation, cube(-1.3, 1.7));
+        for _ in 0..4 {
+            mtree.next_level();
+        }
+        mtree.generate_vertex_map();
+        mtree.generate_quad_set();
+        let geometry = mtree.get_geometry();
+
+        assert_watertight(&geometry);
+        for v in &geometry.vertices {
+            let distance = v.x.abs().max(v.y.abs()).max(v.z.abs()) - 1.0;
+            assert!(distance.abs() < 1e-3);
+        }
+        for corner in &[Vector3::new(1.0, 1.0, 1.0), Vector3::new(-1.0, 1.0, -1.0)] {
+            assert!(geometry
+                .vertices
+                .iter()
+                .any(|v| (v - corner).magnitude() < 1e-2));
+        }
+
+        // The surface of an inequality is its boundary
+        let input: Vec<char> = "max(abs(x), max(abs(y), abs(z))) < 1".chars().collect();
+        let (f, relation) = parse_relation(&input, 0).unwrap();
+        let mut region = MeshTree::new_relation(f, relation, cube(-1.3, 1.7));
+        for _ in 0..4 {
+            region.next_level();
+        }
+        region.generate_vertex_map();
+        region.generate_quad_set();
+        assert_eq!(region.quad_set.len(), mtree.quad_set.len());
+    }
+
+    #[test]
+    fn test_double_precision() {
+        // Far from the origin f32 runs out of precision before level 20,
+        // cells there would be narrower than the gap between f32 values
+        let input: Vec<char> = "x = 1000.03, y = 1000.03, z = 1000.03".chars().collect();
+        let constraints = parse_system(&input, 0).unwrap();
+        let interval = Interval::new(1000.0f64, 1000.2);
+        let bb = BoundingBox {
+            x: interval,
+            y: interval,
+            z: interval,
+        };
+        let mut mtree = MeshTree::new_system(constraints, bb);
+        for _ in 0..20 {
+            mtree.next_level();
+        }
+
+        let point = f64::from_f32(1000.03);
+        assert!(mtree.get_solution_cell_count() > 0);
+        assert!(mtree.get_solution_cell_count() <= 8);
+        for bb in mtree.solution_map.values() {
+            for i in &[bb.x, bb.y, bb.z] {
+                assert!(i.min < i.max);
+                assert!(i.min <= point && point <= i.max);
+                assert_eq!(i.min.as_f32(), i.max.as_f32());
+            }
+        }
+    }
+
+    #[test]
+    fn test_affine_arithmetic() {
+        let input: Vec<char> = "(x * x + y * y + z * z + 21)^2 - 100 * (x * x + y * y) = 0"
+            .chars()
+            .collect();
+        let (f, relation) = parse_relation(&input, 0).unwrap();
+        let mut affine = MeshTree::new_relation(f.clone(), relation, cube(-9.0, 11.0));
+        let mut intervals = MeshTree::new_relation(f, relation, cube(-9.0, 11.0));
+        affine.set_affine_arithmetic(true);
+        for _ in 0..4 {
+            affine.next_level();
+            intervals.next_level();
+
+            // Affine classification only ever narrows the intervals down
+            assert!(affine
+                .solution_map
+                .keys()
+                .all(|key| intervals.solution_map.contains_key(key)));
+        }
+        assert!(affine.get_solution_cell_count() < intervals.get_solution_cell_count());
+
+        affine.set_affine_arithmetic(false);
+        assert_eq!(
+            affine.get_solution_cell_count(),
+            intervals.get_solution_cell_count()
+        );
+    }
 }
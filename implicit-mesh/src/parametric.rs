@@ -0,0 +1,355 @@
+use crate::bytecode::CompiledFunction;
+use crate::function::{Function, Parameters, Relation};
+use crate::function_ir::Node;
+use crate::interval::Interval;
+use crate::mesh_tree::Geometry;
+use cgmath::Vector3;
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+
+/// The names of the surface coordinates, a curve only uses the first
+pub const PARAMETERS: [&str; 2] = ["u", "v"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParametricError {
+    /// The same coordinate was given twice, i.e. `x = u, x = v`
+    DuplicateCoordinate(char),
+    /// A bound on u or v has to be a number once definitions are expanded
+    NonConstantBound(String),
+    /// The range for u or v is empty or unbounded
+    InvalidRange(String),
+    /// A constraint that is neither `x = ...`, `y = ...`, `z = ...` nor a bound on u or v
+    UnexpectedConstraint(String),
+}
+
+impl Error for ParametricError {}
+
+impl fmt::Display for ParametricError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParametricError::DuplicateCoordinate(c) => write!(f, "{} is given more than once", c),
+            ParametricError::NonConstantBound(ref name) => {
+                write!(f, "The bounds of {} have to be numbers", name)
+            }
+            ParametricError::InvalidRange(ref name) => {
+                write!(f, "The range of {} has to be finite and not empty", name)
+            }
+            ParametricError::UnexpectedConstraint(ref constraint) => write!(
+                f,
+                "Expected x, y or z = ... or a bound on u or v, found {}",
+                constraint
+            ),
+        }
+    }
+}
+
+/// A surface given by `x(u, v)`, `y(u, v)` and `z(u, v)` over ranges of u and v,
+/// or a curve when none of them use v.
+///
+/// These are written as constraints, i.e.
+/// `x = cos(u), y = sin(u), z = v, 0 <= u, u <= 6.2832, -1 <= v, v <= 1`.
+/// Coordinates that aren't given are zero, and ranges that aren't given are `[0, 1]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parametric {
+    pub coordinates: [Box<Node>; 3],
+    pub u: Interval,
+    pub v: Interval,
+}
+
+/// Do the constraints describe a parametric surface or curve rather than an implicit one.
+/// That's the case when each of them gives a coordinate or bounds u or v,
+/// and the coordinates use u.
+pub fn is_parametric(constraints: &[(Box<Node>, Relation)]) -> bool {
+    let mut uses_u = false;
+    for (f, relation) in constraints {
+        if let Some((_, expression)) = coordinate(f, *relation) {
+            uses_u |= expression.parameters().contains(PARAMETERS[0]);
+        } else if bound(f, *relation).is_none() {
+            return false;
+        }
+    }
+    uses_u
+}
+
+/// `x = expression` becomes `x - expression` compared to zero
+fn coordinate(f: &Node, relation: Relation) -> Option<(usize, &Node)> {
+    match (f, relation) {
+        (Node::Sub(ref lhs, ref rhs), Relation::Equal) => {
+            let axis = match **lhs {
+                Node::Variable('x') => 0,
+                Node::Variable('y') => 1,
+                Node::Variable('z') => 2,
+                _ => return None,
+            };
+            if ['x', 'y', 'z'].iter().any(|c| rhs.depends_on(*c)) {
+                None
+            } else {
+                Some((axis, rhs))
+            }
+        }
+        _ => None,
+    }
+}
+
+/// A bound such as `u <= 6.28` or `0 <= v`, giving the parameter, whether it's
+/// the lower bound, and the expression for the bound
+fn bound(f: &Node, relation: Relation) -> Option<(&'static str, bool, &Node)> {
+    let (lhs, rhs) = match *f {
+        Node::Sub(ref lhs, ref rhs) => (lhs, rhs),
+        _ => return None,
+    };
+    let less = match relation {
+        Relation::Less | Relation::LessEqual => true,
+        Relation::Greater | Relation::GreaterEqual => false,
+        Relation::Equal => return None,
+    };
+
+    let parameter = |n: &Node| match *n {
+        Node::Parameter(ref name) => PARAMETERS.iter().find(|p| **p == name.as_str()).cloned(),
+        _ => None,
+    };
+    if let Some(name) = parameter(lhs) {
+        Some((name, !less, &**rhs))
+    } else if let Some(name) = parameter(rhs) {
+        Some((name, less, &**lhs))
+    } else {
+        None
+    }
+}
+
+impl Parametric {
+    pub fn from_constraints(
+        constraints: &[(Box<Node>, Relation)],
+    ) -> Result<Parametric, ParametricError> {
+        let mut coordinates: [Option<Box<Node>>; 3] = [None, None, None];
+        let mut ranges = [Interval::new(0.0, 1.0), Interval::new(0.0, 1.0)];
+
+        for (f, relation) in constraints {
+            if let Some((axis, expression)) = coordinate(f, *relation) {
+                if coordinates[axis].is_some() {
+                    let name = ['x', 'y', 'z'][axis];
+                    return Err(ParametricError::DuplicateCoordinate(name));
+                }
+                coordinates[axis] = Some(Box::new(expression.clone()));
+            } else if let Some((name, lower, expression)) = bound(f, *relation) {
+                let value = match expression.simplify() {
+                    Node::Constant(c) => c,
+                    _ => return Err(ParametricError::NonConstantBound(name.to_string())),
+                };
+                let range = &mut ranges[if name == PARAMETERS[0] { 0 } else { 1 }];
+                if lower {
+                    range.min = value;
+                } else {
+                    range.max = value;
+                }
+            } else {
+                return Err(ParametricError::UnexpectedConstraint(
+                    crate::display::constraint_to_string(f, *relation),
+                ));
+            }
+        }
+
+        for (name, range) in PARAMETERS.iter().zip(&ranges) {
+            if !(range.min.is_finite() && range.max.is_finite() && range.min < range.max) {
+                return Err(ParametricError::InvalidRange(name.to_string()));
+            }
+        }
+
+        let [x, y, z] = coordinates;
+        let zero = || Box::new(Node::Constant(0.0));
+        Ok(Parametric {
+            coordinates: [
+                x.unwrap_or_else(zero),
+                y.unwrap_or_else(zero),
+                z.unwrap_or_else(zero),
+            ],
+            u: ranges[0],
+            v: ranges[1],
+        })
+    }
+
+    /// A curve only depends on u
+    pub fn is_curve(&self) -> bool {
+        !self
+            .coordinates
+            .iter()
+            .any(|c| c.parameters().contains(PARAMETERS[1]))
+    }
+
+    /// The names of parameters other than u and v
+    pub fn parameters(&self) -> BTreeSet<String> {
+        let mut result = BTreeSet::new();
+        for c in &self.coordinates {
+            result.extend(c.parameters());
+        }
+        for p in &PARAMETERS {
+            result.remove(*p);
+        }
+        result
+    }
+
+    /// Sample the surface on a `steps` by `steps` grid over u and v, or the curve at
+    /// `steps + 1` points along u. Triangles and lines leave out any samples that
+    /// aren't finite, such as points outside the domain of a square root.
+    pub fn tessellate(&self, parameters: &Parameters, steps: usize) -> Geometry {
+        let mut parameters = parameters.clone();
+        for p in &PARAMETERS {
+            parameters.remove(*p);
+        }
+
+        // Evaluate with u and v in place of x and y
+        let mut axes = HashMap::new();
+        axes.insert(PARAMETERS[0].to_string(), Node::Variable('x'));
+        axes.insert(PARAMETERS[1].to_string(), Node::Variable('y'));
+        let functions: Vec<CompiledFunction> = self
+            .coordinates
+            .iter()
+            .map(|c| CompiledFunction::compile(&c.bind(&parameters).substitute(&axes).simplify()))
+            .collect();
+        let point = |u: f32, v: f32| {
+            Vector3::new(
+                functions[0].evaluate(u, v, 0.0),
+                functions[1].evaluate(u, v, 0.0),
+                functions[2].evaluate(u, v, 0.0),
+            )
+        };
+        let lerp = |range: &Interval, i: usize| {
+            range.min + (range.max - range.min) * i as f32 / steps as f32
+        };
+
+        let mut geometry = Geometry {
+            vertices: Vec::new(),
+            triangles: Vec::new(),
+            lines: Vec::new(),
+        };
+
+        if self.is_curve() {
+            for i in 0..=steps {
+                geometry.vertices.push(point(lerp(&self.u, i), 0.0));
+            }
+            for i in 0..steps as u32 {
+                geometry.add_line(i, i + 1);
+            }
+            return geometry;
+        }
+
+        let row = steps as u32 + 1;
+        for i in 0..=steps {
+            for j in 0..=steps {
+                geometry
+                    .vertices
+                    .push(point(lerp(&self.u, i), lerp(&self.v, j)));
+            }
+        }
+
+        for i in 0..row {
+            for j in 0..row {
+                let index = i * row + j;
+                if j + 1 < row {
+                    geometry.add_line(index, index + 1);
+                }
+                if i + 1 < row {
+                    geometry.add_line(index, index + row);
+                }
+                if i + 1 < row && j + 1 < row {
+                    geometry.add_triangle([index, index + row, index + row + 1]);
+                    geometry.add_triangle([index, index + row + 1, index + 1]);
+                }
+            }
+        }
+
+        geometry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_program;
+
+    fn constraints(text: &str) -> Vec<(Box<Node>, Relation)> {
+        let input: Vec<char> = text.chars().collect();
+        parse_program(&input, 0).unwrap().expand()
+    }
+
+    #[test]
+    fn test_from_constraints() {
+        let torus = constraints(
+            "R = 2; x = (R + cos(v)) * cos(u), y = (R + cos(v)) * sin(u), z = sin(v), \
+             0 <= u, u <= 2 * 3, v >= -3, 3 >= v",
+        );
+        assert!(is_parametric(&torus));
+        let surface = Parametric::from_constraints(&torus).unwrap();
+        assert!(!surface.is_curve());
+        assert_eq!(surface.u.min, 0.0);
+        assert_eq!(surface.u.max, 6.0);
+        assert_eq!(surface.v.min, -3.0);
+        assert_eq!(surface.v.max, 3.0);
+        assert!(surface.parameters().is_empty());
+
+        // A helix in the plane, with the range left at its default
+        let helix = constraints("x = a * cos(u), y = sin(u)");
+        assert!(is_parametric(&helix));
+        let curve = Parametric::from_constraints(&helix).unwrap();
+        assert!(curve.is_curve());
+        assert_eq!(*curve.coordinates[2], Node::Constant(0.0));
+        assert_eq!(curve.u, Interval::new(0.0, 1.0));
+        assert_eq!(curve.parameters().into_iter().collect::<Vec<_>>(), ["a"]);
+
+        // Implicit equations aren't parametric
+        assert!(!is_parametric(&constraints("x^2 + y^2 = 1")));
+        assert!(!is_parametric(&constraints("x = 1, y = 2")));
+        assert!(!is_parametric(&constraints("x = u, y^2 = 1")));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |text: &str| {
+            format!(
+                "{}",
+                Parametric::from_constraints(&constraints(text)).unwrap_err()
+            )
+        };
+        assert_eq!(error("x = u, x = v"), "x is given more than once");
+        assert_eq!(error("x = u, u <= a"), "The bounds of u have to be numbers");
+        assert_eq!(
+            error("x = u, u <= -1"),
+            "The range of u has to be finite and not empty"
+        );
+        assert_eq!(
+            error("x = u, y^2 = 1"),
+            "Expected x, y or z = ... or a bound on u or v, found y^2 = 1"
+        );
+    }
+
+    #[test]
+    fn test_tessellate() {
+        let sphere = constraints(
+            "x = sin(v) * cos(u), y = sin(v) * sin(u), z = cos(v), \
+             0 <= u, u <= 6.2832, 0 <= v, v <= 3.1416",
+        );
+        let surface = Parametric::from_constraints(&sphere).unwrap();
+        let geometry = surface.tessellate(&Parameters::new(), 8);
+        assert_eq!(geometry.vertices.len(), 81);
+        assert_eq!(geometry.triangles.len(), 8 * 8 * 2 * 3);
+        assert_eq!(geometry.lines.len(), 2 * 8 * 9 * 2);
+        for vertex in &geometry.vertices {
+            assert_similiar!(
+                vertex.x * vertex.x + vertex.y * vertex.y + vertex.z * vertex.z,
+                1.0
+            );
+        }
+
+        // Samples outside the domain are left out
+        let curve =
+            Parametric::from_constraints(&constraints("x = u, y = sqrt(u) * a, -1 <= u, u <= 1"))
+                .unwrap();
+        let mut parameters = Parameters::new();
+        parameters.insert("a".to_string(), 2.0);
+        let geometry = curve.tessellate(&parameters, 4);
+        assert_eq!(geometry.vertices.len(), 5);
+        assert_eq!(geometry.lines, vec![2, 3, 3, 4]);
+        assert_similiar!(geometry.vertices[4].y, 2.0);
+    }
+}
@@ -1,203 +1,771 @@
+use crate::function::Relation;
 use crate::function_ir::Node;
-use crate::parser_error::{Expected, ParseError, ParseResult};
+use crate::lexer::{tokenize, tokenize_latex, Token, TokenKind};
+use crate::parser_error::{
+    operand, Expected, ParseError, ParseErrorKind, ParseErrors, ParseResult, Span,
+};
+use crate::program::{self, Definition, Program};
 
 type BNode = Box<Node>;
 
-fn check_index<'a>(input: &'a [char], current_index: usize) -> ParseResult<()> {
-    if current_index >= input.len() {
-        Err(ParseError::UnexpectedEnd)
-    } else {
-        Ok(())
-    }
+// Binding strength of each operator, higher binds tighter. These match the
+// levels display uses to decide where parentheses are needed.
+const SUM: u8 = 1;
+const PRODUCT: u8 = 2;
+const NEGATION: u8 = 3;
+const POWER: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Associativity {
+    Left,
+    Right,
 }
 
-fn try_incr_index<'a>(input: &'a [char], current_index: usize) -> ParseResult<usize> {
-    let mut index = current_index + 1;
-    check_index(&input, index)?;
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Fixity {
+    Prefix,
+    Infix(Associativity),
+}
 
-    while input[index] == ' ' {
-        index += 1;
-        check_index(&input, index)?;
-    }
+struct Operator {
+    symbol: char,
+    fixity: Fixity,
+    precedence: u8,
+}
 
-    Ok(index)
+static OPERATORS: [Operator; 6] = [
+    Operator {
+        symbol: '+',
+        fixity: Fixity::Infix(Associativity::Left),
+        precedence: SUM,
+    },
+    Operator {
+        symbol: '-',
+        fixity: Fixity::Infix(Associativity::Left),
+        precedence: SUM,
+    },
+    Operator {
+        symbol: '*',
+        fixity: Fixity::Infix(Associativity::Left),
+        precedence: PRODUCT,
+    },
+    Operator {
+        symbol: '/',
+        fixity: Fixity::Infix(Associativity::Left),
+        precedence: PRODUCT,
+    },
+    // Unary minus binds looser than ^, so -x^2 is -(x^2)
+    Operator {
+        symbol: '-',
+        fixity: Fixity::Prefix,
+        precedence: NEGATION,
+    },
+    Operator {
+        symbol: '^',
+        fixity: Fixity::Infix(Associativity::Right),
+        precedence: POWER,
+    },
+];
+
+/// Juxtaposition, i.e. `2x` or `x(y + 1)`, is multiplication
+static IMPLICIT_MULTIPLY: Operator = Operator {
+    symbol: '*',
+    fixity: Fixity::Infix(Associativity::Left),
+    precedence: PRODUCT,
+};
+
+fn find_operator(symbol: char, prefix: bool) -> Option<&'static Operator> {
+    OPERATORS
+        .iter()
+        .find(|op| op.symbol == symbol && (op.fixity == Fixity::Prefix) == prefix)
 }
 
-fn incr_index<'a>(input: &'a [char], current_index: usize) -> usize {
-    let mut index = current_index + 1;
+fn prefix_node(symbol: char, operand: BNode) -> Node {
+    match symbol {
+        '-' => Node::Mul(Box::new(Node::Constant(-1.0)), operand),
+        _ => unreachable!("no prefix operator {}", symbol),
+    }
+}
 
-    while index < input.len() && input[index] == ' ' {
-        index += 1;
+fn infix_node(symbol: char, lhs: BNode, rhs: BNode) -> Node {
+    match symbol {
+        '+' => Node::Add(lhs, rhs),
+        '-' => Node::Sub(lhs, rhs),
+        '*' => Node::Mul(lhs, rhs),
+        '/' => Node::Div(lhs, rhs),
+        '^' => Node::Exp(lhs, rhs),
+        _ => unreachable!("no infix operator {}", symbol),
     }
+}
 
-    index
+fn function_arity(name: &str) -> Option<usize> {
+    match name {
+        "sin" | "cos" | "tan" | "exp" | "ln" | "sqrt" | "abs" => Some(1),
+        "min" | "max" | "atan2" => Some(2),
+        _ => None,
+    }
 }
 
-pub fn parse_expression<'a>(input: &'a [char], current_index: usize) -> ParseResult<BNode> {
-    let (root, index) = parse_add(&input, current_index)?;
+fn function_node(name: &str, args: Vec<BNode>) -> Node {
+    let mut args = args.into_iter();
+    let mut next_arg = || args.next().unwrap();
+    match name {
+        "sin" => Node::Sin(next_arg()),
+        "cos" => Node::Cos(next_arg()),
+        "tan" => Node::Tan(next_arg()),
+        "exp" => Node::Exponential(next_arg()),
+        "ln" => Node::Ln(next_arg()),
+        "sqrt" => Node::Sqrt(next_arg()),
+        "abs" => Node::Abs(next_arg()),
+        "min" => Node::Min(next_arg(), next_arg()),
+        "max" => Node::Max(next_arg(), next_arg()),
+        "atan2" => Node::Atan2(next_arg(), next_arg()),
+        _ => unreachable!("function_arity accepted unknown function {}", name),
+    }
+}
 
-    if index < input.len() {
-        Err(ParseError::UnconsumedInput(index))
-    } else {
-        Ok(root)
+fn is_variable(name: &str) -> bool {
+    name == "x" || name == "y" || name == "z"
+}
+
+/// Spherical (`r`, `theta`, `phi`) and cylindrical (`rho`, `theta`) coordinates
+/// written in terms of x, y and z. `theta` is the angle around the z axis and
+/// `phi` the angle down from it.
+fn coordinate_node(name: &str) -> Option<Node> {
+    let variable = |c| Box::new(Node::Variable(c));
+    let square = |c| Box::new(Node::Exp(variable(c), Box::new(Node::Constant(2.0))));
+    let rho = || Box::new(Node::Sqrt(Box::new(Node::Add(square('x'), square('y')))));
+
+    match name {
+        "r" => Some(Node::Sqrt(Box::new(Node::Add(
+            Box::new(Node::Add(square('x'), square('y'))),
+            square('z'),
+        )))),
+        "rho" => Some(*rho()),
+        "theta" => Some(Node::Atan2(variable('y'), variable('x'))),
+        "phi" => Some(Node::Atan2(rho(), variable('z'))),
+        _ => None,
     }
 }
 
-fn parse_add<'a>(input: &'a [char], current_index: usize) -> ParseResult<(BNode, usize)> {
-    let (mut base, mut index) = parse_mul(&input, current_index)?;
+/// Definition parameters can be named after variables, i.e. `f(x) = x^2`,
+/// in which case the body has to refer to the parameter instead
+fn localize(node: &Node, parameters: &[String]) -> Node {
+    match *node {
+        Node::Variable(c) if parameters.contains(&c.to_string()) => Node::Parameter(c.to_string()),
+        _ => node.map_children(|child| localize(child, parameters)),
+    }
+}
 
-    while index < input.len() && (input[index] == '-' || input[index] == '+') {
-        let op_index = index;
-        index = try_incr_index(&input, index)?;
-        let (term, new_index) = parse_mul(&input, index)?;
+/// A Pratt parser over a run of tokens. The last token is where parsing has to
+/// stop, either the end of the input or the comma after a constraint in a system.
+/// Calls to any functions in definitions are inlined.
+struct Parser<'a> {
+    input: &'a [char],
+    tokens: &'a [Token],
+    definitions: &'a [Definition],
+    /// The parameters of the definition being parsed, which shadow coordinates
+    parameters: Vec<String>,
+    /// Whether `r`, `rho`, `theta` and `phi` are coordinates rather than parameters
+    coordinates: bool,
+    index: usize,
+}
 
-        if input[op_index] == '-' {
-            base = Box::new(Node::Sub(base, term));
-        } else {
-            base = Box::new(Node::Add(base, term));
+impl<'a> Parser<'a> {
+    fn new(input: &'a [char], tokens: &'a [Token], definitions: &'a [Definition]) -> Parser<'a> {
+        Parser {
+            input,
+            tokens,
+            definitions,
+            parameters: Vec::new(),
+            coordinates: false,
+            index: 0,
         }
+    }
 
-        index = new_index;
+    fn peek(&self) -> &'a Token {
+        &self.tokens[self.index]
     }
 
-    Ok((base, index))
-}
+    fn at_end(&self) -> bool {
+        self.index + 1 >= self.tokens.len()
+    }
 
-fn parse_mul<'a>(input: &'a [char], current_index: usize) -> ParseResult<(BNode, usize)> {
-    let (mut base, mut index) = parse_exp(&input, current_index)?;
+    fn advance(&mut self) {
+        if !self.at_end() {
+            self.index += 1;
+        }
+    }
 
-    while index < input.len() && (input[index] == '*' || input[index] == '/') {
-        let op_index = index;
-        index = try_incr_index(&input, index)?;
-        let (term, new_index) = parse_exp(&input, index)?;
+    /// An error for the current token, which was supposed to be one of expected
+    fn unexpected(&self, expected: &[Expected]) -> ParseError {
+        let token = self.peek();
+        match token.kind {
+            TokenKind::End => ParseError::unexpected_end(token.span.start, expected.to_vec()),
+            _ => ParseError::unexpected_char(
+                self.input[token.span.start],
+                token.span.start,
+                expected.to_vec(),
+            ),
+        }
+    }
 
-        if input[op_index] == '*' {
-            base = Box::new(Node::Mul(base, term));
+    fn expect(&mut self, kind: TokenKind, expected: &[Expected]) -> ParseResult<&'a Token> {
+        let token = self.peek();
+        if token.kind == kind {
+            self.advance();
+            Ok(token)
         } else {
-            base = Box::new(Node::Div(base, term));
+            Err(self.unexpected(expected))
         }
-
-        index = new_index;
     }
 
-    Ok((base, index))
-}
+    fn check_consumed(&self) -> ParseResult<()> {
+        if self.at_end() {
+            Ok(())
+        } else {
+            let last = &self.tokens[self.tokens.len() - 2];
+            Err(ParseError::new(
+                ParseErrorKind::UnconsumedInput,
+                Span::new(self.peek().span.start, last.span.end),
+            ))
+        }
+    }
 
-fn parse_exp<'a>(input: &'a [char], current_index: usize) -> ParseResult<(BNode, usize)> {
-    let (base, mut index) = parse_primary(&input, current_index)?;
+    fn relation(&self) -> Option<Relation> {
+        match self.peek().kind {
+            TokenKind::Relation(relation) => Some(relation),
+            _ => None,
+        }
+    }
 
-    if index < input.len() && input[index] == '^' {
-        index = try_incr_index(&input, index)?;
+    /// The name and parameters of a definition, up to and including the `=`
+    fn parse_definition_head(&mut self) -> ParseResult<(String, Vec<String>)> {
+        let name = self.parse_name(false)?;
+        let mut parameters = Vec::new();
+
+        if self.peek().kind == TokenKind::LeftParen {
+            self.advance();
+            loop {
+                parameters.push(self.parse_name(true)?);
+                match self.peek().kind {
+                    TokenKind::Comma => self.advance(),
+                    TokenKind::RightParen => {
+                        self.advance();
+                        break;
+                    }
+                    _ => return Err(self.unexpected(&[Expected::Char(','), Expected::Char(')')])),
+                }
+            }
+        }
 
-        let (exp, index) = parse_primary(&input, index)?;
-        let exp_node = Box::new(Node::Exp(base, exp));
+        match self.relation() {
+            Some(Relation::Equal) => {
+                self.advance();
+                Ok((name, parameters))
+            }
+            _ => Err(self.unexpected(&[Expected::Char('=')])),
+        }
+    }
 
-        Ok((exp_node, index))
-    } else {
-        Ok((base, index))
+    /// A name being defined, which can't shadow a built in function,
+    /// or a variable unless it's a parameter
+    fn parse_name(&mut self, parameter: bool) -> ParseResult<String> {
+        let token = self.peek();
+        match token.kind {
+            TokenKind::Identifier(ref name) => {
+                if function_arity(name).is_some() || (!parameter && is_variable(name)) {
+                    return Err(ParseError::new(
+                        ParseErrorKind::Redefinition(name.clone()),
+                        token.span,
+                    ));
+                }
+                self.advance();
+                Ok(name.clone())
+            }
+            _ => Err(self.unexpected(&[Expected::Identifier])),
+        }
     }
-}
 
-fn parse_primary<'a>(input: &'a [char], current_index: usize) -> ParseResult<(BNode, usize)> {
-    let mut index = current_index;
+    /// The body of a definition, with earlier values that its parameters
+    /// don't shadow written out
+    fn parse_definition_body(&mut self, parameters: &[String]) -> ParseResult<BNode> {
+        self.parameters = parameters.to_vec();
+        let body = self.parse_expression(0);
+        self.parameters.clear();
+        let body = body?;
+        self.check_consumed()?;
+
+        let mut values = program::values(self.definitions);
+        for parameter in parameters {
+            values.remove(parameter);
+        }
+        Ok(Box::new(localize(&body, parameters).substitute(&values)))
+    }
 
-    if input[index] == '(' {
-        index = try_incr_index(&input, index)?;
+    /// Returns the constraint, its relation, and the index of the relation operator.
+    /// With `implicit_zero` set, an expression on its own is taken to be `= 0`,
+    /// and the index is where the relation would have been.
+    fn parse_constraint(&mut self, implicit_zero: bool) -> ParseResult<(BNode, Relation, usize)> {
+        let lhs = self.parse_expression(0)?;
+
+        let op_index = self.peek().span.start;
+        let relation = match self.relation() {
+            Some(relation) => relation,
+            None if implicit_zero && self.at_end() => return Ok((lhs, Relation::Equal, op_index)),
+            None => {
+                return Err(ParseError::new(
+                    ParseErrorKind::MissingRelation,
+                    Span::at(op_index),
+                ))
+            }
+        };
+        self.advance();
+
+        let rhs = self.parse_rhs()?;
+        Ok((Box::new(Node::Sub(lhs, rhs)), relation, op_index))
+    }
 
-        // TODO replace base with expression
-        let (base, mut index) = parse_add(&input, index)?;
-        check_index(&input, index)?;
+    /// The right hand side of a constraint, which has to be all that's left
+    fn parse_rhs(&mut self) -> ParseResult<BNode> {
+        let rhs = self.parse_expression(0)?;
 
-        if input[index] == ')' {
-            index = incr_index(&input, index);
-            Ok((base, index))
+        if self.relation().is_some() {
+            Err(ParseError::new(
+                ParseErrorKind::DuplicateRelation,
+                self.peek().span,
+            ))
         } else {
-            Err(ParseError::UnexpectedChar {
-                pos: index,
-                c: input[index],
-                exp: Expected::Char(')'),
-            })
+            self.check_consumed()?;
+            Ok(rhs)
         }
-    } else {
-        parse_base(&input, index)
     }
-}
 
-fn parse_base<'a>(input: &'a [char], current_index: usize) -> ParseResult<(BNode, usize)> {
-    let mut index = current_index;
-    check_index(&input, index)?;
+    /// Parse operators that bind at least as tightly as min_precedence
+    fn parse_expression(&mut self, min_precedence: u8) -> ParseResult<BNode> {
+        let mut lhs = self.parse_prefix()?;
+
+        loop {
+            let (op, implicit) = match self.peek().kind {
+                TokenKind::Operator(symbol) => match find_operator(symbol, false) {
+                    Some(op) => (op, false),
+                    None => break,
+                },
+                TokenKind::Identifier(_)
+                | TokenKind::Command(_)
+                | TokenKind::LeftParen
+                | TokenKind::LeftBrace => (&IMPLICIT_MULTIPLY, true),
+                _ => break,
+            };
+
+            if op.precedence < min_precedence {
+                break;
+            }
+            if !implicit {
+                self.advance();
+            }
+
+            let rhs_precedence = match op.fixity {
+                Fixity::Infix(Associativity::Right) => op.precedence,
+                _ => op.precedence + 1,
+            };
+            let rhs = self.parse_expression(rhs_precedence)?;
+            lhs = Box::new(infix_node(op.symbol, lhs, rhs));
+        }
 
-    // TODO negative needs to also bind to primary, and bind less strognly than
-    // exponentiation to
-    // avoid uneccassary confustion
-    let mut negative = false;
-    if input[index] == '-' {
-        negative = true;
-        index = try_incr_index(&input, index)?;
+        Ok(lhs)
     }
 
-    let (base, new_index) = match input[index] {
-        'x' | 'y' | 'z' => {
-            let node = Node::Variable(input[index]);
-            index = incr_index(&input, index);
-            (Box::new(node), index)
+    fn parse_prefix(&mut self) -> ParseResult<BNode> {
+        let token = self.peek();
+        match token.kind {
+            TokenKind::Operator(symbol) => match find_operator(symbol, true) {
+                Some(op) => {
+                    self.advance();
+                    let operand = self.parse_expression(op.precedence)?;
+                    Ok(Box::new(prefix_node(op.symbol, operand)))
+                }
+                None => Err(self.unexpected(&operand())),
+            },
+            TokenKind::Number(ref digits) => {
+                self.advance();
+                let constant = digits
+                    .parse()
+                    .map_err(|err| ParseError::new(ParseErrorKind::from(err), token.span))?;
+                Ok(Box::new(Node::Constant(constant)))
+            }
+            TokenKind::Identifier(ref name) => {
+                self.advance();
+                self.parse_identifier(token, name)
+            }
+            TokenKind::LeftParen => {
+                self.advance();
+                let inner = self.parse_expression(0)?;
+                self.expect(
+                    TokenKind::RightParen,
+                    &[Expected::Char(')'), Expected::Operator],
+                )?;
+                Ok(inner)
+            }
+            TokenKind::LeftBrace => self.parse_group(),
+            TokenKind::Bar => {
+                self.advance();
+                let inner = self.parse_expression(0)?;
+                self.expect(TokenKind::Bar, &[Expected::Char('|'), Expected::Operator])?;
+                Ok(Box::new(Node::Abs(inner)))
+            }
+            TokenKind::Command(ref name) => {
+                self.advance();
+                self.parse_command(token, name)
+            }
+            _ => Err(self.unexpected(&operand())),
         }
-        d if d.is_digit(10) => parse_number(input, index)?,
-        c => {
-            return Err(ParseError::UnexpectedChar {
-                c: c,
-                pos: index,
-                exp: Expected::Base,
-            });
+    }
+
+    /// A LaTeX group, `{...}`
+    fn parse_group(&mut self) -> ParseResult<BNode> {
+        self.expect(TokenKind::LeftBrace, &[Expected::Char('{')])?;
+        let inner = self.parse_expression(0)?;
+        self.expect(
+            TokenKind::RightBrace,
+            &[Expected::Char('}'), Expected::Operator],
+        )?;
+        Ok(inner)
+    }
+
+    /// LaTeX functions can be called like `\sin(x)`, or on a group or a single
+    /// operand like `\sin{x}` and `\sin x^2`
+    fn parse_command(&mut self, token: &'a Token, name: &str) -> ParseResult<BNode> {
+        match (name, function_arity(name)) {
+            ("frac", _) => {
+                let numerator = self.parse_group()?;
+                let denominator = self.parse_group()?;
+                Ok(Box::new(Node::Div(numerator, denominator)))
+            }
+            ("sqrt", _) => Ok(Box::new(Node::Sqrt(self.parse_group()?))),
+            (_, Some(arity)) if arity > 1 || self.peek().kind == TokenKind::LeftParen => {
+                let args = self.parse_arguments(token, name, arity)?;
+                Ok(Box::new(function_node(name, args)))
+            }
+            (_, Some(_)) => {
+                let arg = match self.peek().kind {
+                    TokenKind::LeftBrace => self.parse_group()?,
+                    _ => self.parse_expression(POWER)?,
+                };
+                Ok(Box::new(function_node(name, vec![arg])))
+            }
+            (_, None) => Err(ParseError::new(
+                ParseErrorKind::UnknownIdentifier(format!("\\{}", name)),
+                token.span,
+            )),
         }
-    };
+    }
+
+    /// x, y and z are variables, known and defined functions have to be called,
+    /// and any other name is a parameter. A name directly followed by `(` is a call to an unknown
+    /// function rather than a multiplication.
+    fn parse_identifier(&mut self, token: &'a Token, name: &str) -> ParseResult<BNode> {
+        match (name, function_arity(name)) {
+            ("x", _) | ("y", _) | ("z", _) => {
+                Ok(Box::new(Node::Variable(self.input[token.span.start])))
+            }
+            (_, Some(arity)) => {
+                let args = self.parse_arguments(token, name, arity)?;
+                Ok(Box::new(function_node(name, args)))
+            }
+            (_, None) => {
+                let definition = self.definitions.iter().rev().find(|d| d.name == name);
+                if let Some(definition) = definition.filter(|d| d.is_function()) {
+                    let args = self.parse_arguments(token, name, definition.parameters.len())?;
+                    return Ok(Box::new(definition.call(args)));
+                }
+
+                // Anything the user named themselves takes priority over coordinates
+                if self.coordinates
+                    && definition.is_none()
+                    && !self.parameters.iter().any(|p| p == name)
+                {
+                    if let Some(node) = coordinate_node(name) {
+                        return Ok(Box::new(node));
+                    }
+                }
+
+                let next = self.peek();
+                if next.kind == TokenKind::LeftParen && next.span.start == token.span.end {
+                    Err(ParseError::new(
+                        ParseErrorKind::UnknownIdentifier(name.to_string()),
+                        token.span,
+                    ))
+                } else {
+                    Ok(Box::new(Node::Parameter(name.to_string())))
+                }
+            }
+        }
+    }
+
+    /// The parenthesized arguments of a call, there have to be arity of them
+    fn parse_arguments(
+        &mut self,
+        name_token: &'a Token,
+        name: &str,
+        arity: usize,
+    ) -> ParseResult<Vec<BNode>> {
+        self.expect(TokenKind::LeftParen, &[Expected::Char('(')])?;
+
+        let separator = [Expected::Char(','), Expected::Char(')'), Expected::Operator];
+        let mut args = Vec::new();
+        let close = loop {
+            args.push(self.parse_expression(0)?);
+
+            let token = self.peek();
+            match token.kind {
+                TokenKind::Comma => self.advance(),
+                TokenKind::RightParen => {
+                    self.advance();
+                    break token;
+                }
+                _ => return Err(self.unexpected(&separator)),
+            }
+        };
+
+        if args.len() != arity {
+            return Err(ParseError::new(
+                ParseErrorKind::ArgumentCount {
+                    name: name.to_string(),
+                    expected: arity,
+                    found: args.len(),
+                },
+                Span::new(name_token.span.start, close.span.end),
+            ));
+        }
+
+        Ok(args)
+    }
+}
+
+pub fn parse_expression<'a>(input: &'a [char], current_index: usize) -> ParseResult<BNode> {
+    parse_expression_tokens(input, &tokenize(input, current_index))
+}
+
+/// Parse an expression written in LaTeX, such as `\frac{x^{2}}{4} + \sin\left(y\right)`
+pub fn parse_latex_expression<'a>(input: &'a [char], current_index: usize) -> ParseResult<BNode> {
+    parse_expression_tokens(input, &tokenize_latex(input, current_index))
+}
+
+fn parse_expression_tokens<'a>(input: &'a [char], tokens: &'a [Token]) -> ParseResult<BNode> {
+    let mut parser = Parser::new(input, tokens, &[]);
+    let root = parser.parse_expression(0)?;
+    parser.check_consumed()?;
+    Ok(root)
+}
 
-    let result_node;
-    if negative {
-        let constant = Box::new(Node::Constant(-1.0));
-        result_node = Box::new(Node::Mul(constant, base));
+/// True if the input looks like LaTeX rather than plain text,
+/// which is to say it uses commands or braces
+pub fn is_latex(input: &[char]) -> bool {
+    input.iter().any(|&c| c == '\\' || c == '{')
+}
+
+/// Parse an equation of the form `lhs = rhs`, producing `lhs - rhs`
+/// so that the solution set is where the resulting function is zero.
+pub fn parse_equation<'a>(input: &'a [char], current_index: usize) -> ParseResult<BNode> {
+    let tokens = tokenize(input, current_index);
+    let (root, relation, op_index) = Parser::new(input, &tokens, &[]).parse_constraint(false)?;
+
+    if relation == Relation::Equal {
+        Ok(root)
     } else {
-        result_node = base;
+        Err(ParseError::unexpected_char(
+            input[op_index],
+            op_index,
+            vec![Expected::Char('=')],
+        ))
     }
+}
+
+/// Parse an equation or inequality of the form `lhs op rhs`, where op is one of
+/// `=`, `<`, `<=`, `>` or `>=`. The result is `lhs - rhs` and the relation it
+/// should have with zero. An expression without a relation is taken to be `= 0`.
+pub fn parse_relation<'a>(
+    input: &'a [char],
+    current_index: usize,
+) -> ParseResult<(BNode, Relation)> {
+    let tokens = tokenize(input, current_index);
+    let (root, relation, _) = Parser::new(input, &tokens, &[]).parse_constraint(true)?;
+    Ok((root, relation))
+}
+
+/// Parse a comma separated list of equations and inequalities,
+/// i.e. `x^2 + y^2 = 1, z = 0`. Expressions on their own are equations `= 0`.
+///
+/// Parsing recovers from errors, so that every problem can be reported at once.
+/// After an error it picks back up at the right hand side of the constraint,
+/// and then at the next constraint.
+pub fn parse_system<'a>(
+    input: &'a [char],
+    current_index: usize,
+) -> Result<Vec<(BNode, Relation)>, ParseErrors> {
+    parse_system_tokens(input, &tokenize(input, current_index), &[], false)
+}
+
+/// The LaTeX version of `parse_system`, i.e. `x^{2} + y^{2} \le 1, z = 0`
+pub fn parse_latex_system<'a>(
+    input: &'a [char],
+    current_index: usize,
+) -> Result<Vec<(BNode, Relation)>, ParseErrors> {
+    parse_system_tokens(input, &tokenize_latex(input, current_index), &[], false)
+}
+
+/// Parse definitions separated by semicolons, followed by a system that can use them,
+/// i.e. `r = x^2 + y^2; f(t) = t^2 - 1; f(r) + z^2 = 0`.
+/// Definitions can only use the ones before them.
+pub fn parse_program<'a>(input: &'a [char], current_index: usize) -> Result<Program, ParseErrors> {
+    parse_program_tokens(input, &tokenize(input, current_index), false)
+}
+
+/// The LaTeX version of `parse_program`
+pub fn parse_latex_program<'a>(
+    input: &'a [char],
+    current_index: usize,
+) -> Result<Program, ParseErrors> {
+    parse_program_tokens(input, &tokenize_latex(input, current_index), false)
+}
+
+/// Like `parse_program`, but with spherical (`r`, `theta`, `phi`) and cylindrical
+/// (`rho`, `theta`) coordinates in place of parameters of the same names,
+/// i.e. `r = 10 + 3 * sin(5 * theta)`. Definitions and their parameters still
+/// shadow them.
+pub fn parse_program_with_coordinates<'a>(
+    input: &'a [char],
+    current_index: usize,
+) -> Result<Program, ParseErrors> {
+    parse_program_tokens(input, &tokenize(input, current_index), true)
+}
 
-    Ok((result_node, new_index))
+/// The LaTeX version of `parse_program_with_coordinates`
+pub fn parse_latex_program_with_coordinates<'a>(
+    input: &'a [char],
+    current_index: usize,
+) -> Result<Program, ParseErrors> {
+    parse_program_tokens(input, &tokenize_latex(input, current_index), true)
 }
 
-fn parse_number<'a>(input: &'a [char], current_index: usize) -> ParseResult<(BNode, usize)> {
-    let mut index = current_index;
-    if !input[index].is_digit(10) {
-        return Err(ParseError::UnexpectedChar {
-            c: input[index],
-            pos: index,
-            exp: Expected::Constant,
+fn parse_program_tokens<'a>(
+    input: &'a [char],
+    tokens: &'a [Token],
+    coordinates: bool,
+) -> Result<Program, ParseErrors> {
+    let statements = split_tokens(tokens, &TokenKind::Semicolon, false);
+    let (system, statements) = statements.split_last().unwrap();
+    let mut definitions: Vec<Definition> = Vec::new();
+    let mut errors = Vec::new();
+
+    for statement in statements {
+        let mut parser = Parser::new(input, statement, &definitions);
+        parser.coordinates = coordinates;
+        let (name, parameters) = match parser.parse_definition_head() {
+            Ok(head) => head,
+            Err(err) => {
+                errors.push(err);
+                continue;
+            }
+        };
+
+        // Keep a stand in for a broken definition, so its uses aren't errors too
+        let body = parser
+            .parse_definition_body(&parameters)
+            .unwrap_or_else(|err| {
+                errors.push(err);
+                Box::new(Node::Parameter(name.clone()))
+            });
+        definitions.push(Definition {
+            name,
+            parameters,
+            body,
         });
     }
 
-    let mut buffer = String::new();
-    let mut found_decimal = false;
-    while input[index].is_digit(10) {
-        buffer.push(input[index]);
-        index += 1;
+    match parse_system_tokens(input, system, &definitions, coordinates) {
+        Ok(constraints) if errors.is_empty() => Ok(Program {
+            definitions,
+            constraints,
+        }),
+        Ok(_) => Err(ParseErrors(errors)),
+        Err(ParseErrors(mut system_errors)) => {
+            errors.append(&mut system_errors);
+            Err(ParseErrors(errors))
+        }
+    }
+}
 
-        if index >= input.len() {
-            break;
+fn parse_system_tokens<'a>(
+    input: &'a [char],
+    tokens: &'a [Token],
+    definitions: &'a [Definition],
+    coordinates: bool,
+) -> Result<Vec<(BNode, Relation)>, ParseErrors> {
+    let mut constraints = Vec::new();
+    let mut errors = Vec::new();
+
+    for segment in split_tokens(tokens, &TokenKind::Comma, true) {
+        let mut parser = Parser::new(input, segment, definitions);
+        parser.coordinates = coordinates;
+        match parser.parse_constraint(true) {
+            Ok((root, relation, _)) => constraints.push((root, relation)),
+            Err(err) => {
+                // The right hand side may have its own problems
+                let op = segment
+                    .iter()
+                    .position(|t| matches!(t.kind, TokenKind::Relation(_)));
+                let error_start = err.span.start;
+                errors.push(err);
+
+                if let Some(op) = op {
+                    if error_start <= segment[op].span.start {
+                        parser.index = op + 1;
+                        if let Err(err) = parser.parse_rhs() {
+                            errors.push(err);
+                        }
+                    }
+                }
+            }
         }
+    }
+
+    if errors.is_empty() {
+        Ok(constraints)
+    } else {
+        Err(ParseErrors(errors))
+    }
+}
 
-        if (!found_decimal) && input[index] == '.' {
-            buffer.push(input[index]);
-            index += 1;
-            found_decimal = true;
+/// Split tokens at each separator, or only those outside of parentheses and braces
+/// if nested is set. Each piece keeps the separator or end token that finishes it.
+fn split_tokens<'a>(tokens: &'a [Token], separator: &TokenKind, nested: bool) -> Vec<&'a [Token]> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut depth = 0;
+
+    for (index, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenKind::LeftParen | TokenKind::LeftBrace => depth += 1,
+            TokenKind::RightParen | TokenKind::RightBrace if depth > 0 => depth -= 1,
+            TokenKind::End => segments.push(&tokens[start..=index]),
+            ref kind if kind == separator && (depth == 0 || !nested) => {
+                segments.push(&tokens[start..=index]);
+                start = index + 1;
+            }
+            _ => (),
         }
     }
 
-    let constant = buffer.parse()?;
-    let bnode = Box::new(Node::Constant(constant));
-    Ok((bnode, incr_index(&input, index - 1)))
+    segments
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::function::Function;
+    use crate::parser_error::Span;
 
     fn assert_constant(result: &Node, expected: f32) {
         if let &Node::Constant(ref c) = result {
@@ -215,202 +783,518 @@ mod tests {
         }
     }
 
+    fn parse(text: &str) -> ParseResult<BNode> {
+        let input: Vec<char> = text.chars().collect();
+        parse_expression(&input, 0)
+    }
+
+    fn parse_debug(text: &str) -> String {
+        format!("{:?}", parse(text).unwrap())
+    }
+
+    fn parse_err(text: &str) -> String {
+        format!("{}", parse(text).unwrap_err())
+    }
+
     #[test]
     fn test_parse_number() {
-        let mut input: Vec<char>;
-        let mut result;
+        assert_constant(&parse("123").unwrap(), 123f32);
+        assert_constant(&parse("123.1232").unwrap(), 123.1232);
+        assert_constant(&parse("0.1010110").unwrap(), 0.1010110);
+        assert_constant(&parse("1.").unwrap(), 1.0);
 
-        input = "123".chars().collect();
-        result = parse_number(&input, 0).unwrap();
-        assert_constant(&result.0, 123f32);
+        let input: Vec<char> = " 1 + 2 + 34".chars().collect();
+        assert_constant(&parse_expression(&input, 9).unwrap(), 34.0);
 
-        input = "123.1232".chars().collect();
-        result = parse_number(&input, 0).unwrap();
-        assert_constant(&result.0, 123.1232);
+        assert_eq!(parse_err("0.101.0110"), "Unconsumed input starting at 5");
+        assert_eq!(
+            parse_err(".5"),
+            "Expected a number, an identifier, \'(\' or \'-\', found \'.\' at 0"
+        );
+    }
 
-        input = "0.1010110".chars().collect();
-        result = parse_number(&input, 0).unwrap();
-        assert_constant(&result.0, 0.1010110);
+    #[test]
+    fn test_parse_negation() {
+        assert_variable(&parse("x").unwrap(), 'x');
+        assert_eq!(parse_debug("-2.0"), "Mul(Constant(-1.0), Constant(2.0))");
+        assert_eq!(
+            parse_debug("--x"),
+            "Mul(Constant(-1.0), Mul(Constant(-1.0), Variable(\'x\')))"
+        );
+        assert_eq!(
+            parse_debug("-(x + 1)"),
+            "Mul(Constant(-1.0), Add(Variable(\'x\'), Constant(1.0)))"
+        );
 
-        input = "0.101a0110".chars().collect();
-        result = parse_number(&input, 0).unwrap();
-        assert_constant(&result.0, 0.101);
-        assert_eq!(result.1, 5);
+        // Unary minus binds looser than ^ but tighter than *
+        assert_eq!(
+            parse_debug("-x^2"),
+            "Mul(Constant(-1.0), Exp(Variable(\'x\'), Constant(2.0)))"
+        );
+        assert_eq!(
+            parse_debug("-x * y"),
+            "Mul(Mul(Constant(-1.0), Variable(\'x\')), Variable(\'y\'))"
+        );
+        assert_eq!(
+            parse_debug("2^-x"),
+            "Exp(Constant(2.0), Mul(Constant(-1.0), Variable(\'x\')))"
+        );
 
-        input = "0.a101a0110".chars().collect();
-        result = parse_number(&input, 0).unwrap();
-        assert_constant(&result.0, 0.0);
-        assert_eq!(result.1, 2);
+        assert_eq!(
+            parse_err("-"),
+            "Unexpected end of input, expected a number, an identifier, \'(\' or \'-\'"
+        );
+        assert_eq!(
+            parse_err("x * +y"),
+            "Expected a number, an identifier, \'(\' or \'-\', found \'+\' at 4"
+        );
+    }
 
-        input = " 1 + 2 + 34 + 456543.23 + 0.101a0110".chars().collect();
-        result = parse_number(&input, 9).unwrap();
-        assert_constant(&result.0, 34.0);
-        assert_eq!(result.1, 12);
+    #[test]
+    fn test_parse_identifier() {
+        assert_eq!(parse_debug("sin(x)"), "Sin(Variable(\'x\'))");
+        assert_eq!(
+            parse_debug("max( x ,  2 ) + y"),
+            "Add(Max(Variable(\'x\'), Constant(2.0)), Variable(\'y\'))"
+        );
+        assert_eq!(
+            parse_debug("-exp(ln(z * y))"),
+            "Mul(Constant(-1.0), Exponential(Ln(Mul(Variable(\'z\'), Variable(\'y\')))))"
+        );
+        assert_eq!(
+            parse_debug("radius * b"),
+            "Mul(Parameter(\"radius\"), Parameter(\"b\"))"
+        );
+        assert_eq!(parse_debug("sqrt (x)"), "Sqrt(Variable(\'x\'))");
 
-        input = "b0.a101a0110".chars().collect();
-        let err = parse_number(&input, 0).unwrap_err();
-        assert_eq!(format!("{}", err), "Looking for Constant, found b at 0");
+        assert_eq!(parse_err("foo(x)"), "Unknown identifier foo at 0");
+        assert_eq!(
+            parse_err("min(x)"),
+            "Function min takes 2 arguments, found 1 at 0"
+        );
+        assert_eq!(parse_err("sqrt x"), "Expected \'(\', found \'x\' at 5");
+        assert_eq!(
+            parse_err("cos(x"),
+            "Unexpected end of input, expected \',\', \')\' or an operator"
+        );
     }
 
     #[test]
-    fn test_parse_base() {
-        let mut input: Vec<char>;
-        let mut result;
-
-        input = "x".chars().collect();
-        result = parse_base(&input, 0).unwrap();
-        assert_variable(&result.0, 'x');
+    fn test_parse_parentheses() {
+        assert_constant(&parse("(0.131)").unwrap(), 0.131);
+        assert_eq!(parse_debug("(-2.0)"), "Mul(Constant(-1.0), Constant(2.0))");
+        assert_eq!(
+            parse_err("(0.131."),
+            "Expected \')\' or an operator, found \'.\' at 6"
+        );
+        assert_eq!(
+            parse_err("(0.131"),
+            "Unexpected end of input, expected \')\' or an operator"
+        );
+    }
 
-        input = "1 + z / y".chars().collect();
-        result = parse_base(&input, 4).unwrap();
-        assert_variable(&result.0, 'z');
-        assert_eq!(result.1, 6);
+    #[test]
+    fn test_parse_exp() {
+        assert_eq!(
+            parse_debug("0.131^x"),
+            "Exp(Constant(0.131), Variable(\'x\'))"
+        );
+        assert_eq!(
+            parse_debug("(0.131)^(-1.2332)"),
+            "Exp(Constant(0.131), Mul(Constant(-1.0), Constant(1.2332)))"
+        );
+        assert_eq!(
+            parse_debug("((1.0)^(1.0))^x"),
+            "Exp(Exp(Constant(1.0), Constant(1.0)), Variable(\'x\'))"
+        );
 
-        input = "0.0 + x * 9".chars().collect();
-        result = parse_base(&input, 10).unwrap();
-        assert_constant(&result.0, 9.0);
-        assert_eq!(result.1, 11);
+        // ^ is right associative
+        assert_eq!(
+            parse_debug("x^y^z"),
+            "Exp(Variable(\'x\'), Exp(Variable(\'y\'), Variable(\'z\')))"
+        );
 
-        input = "-2.0".chars().collect();
-        result = parse_base(&input, 0).unwrap();
         assert_eq!(
-            format!("{:?}", result.0),
-            "Mul(Constant(-1.0), Constant(2.0))"
+            parse_err("(0.131)^"),
+            "Unexpected end of input, expected a number, an identifier, \'(\' or \'-\'"
         );
+    }
 
-        input = "-".chars().collect();
-        let err = parse_base(&input, 0).unwrap_err();
-        assert_eq!(format!("{}", err), "Unexpected end of input");
+    #[test]
+    fn test_parse_mul() {
+        assert_eq!(
+            parse_debug("1.0 * 1.0 * 1.0"),
+            "Mul(Mul(Constant(1.0), Constant(1.0)), Constant(1.0))"
+        );
+        assert_eq!(
+            parse_debug("1.0 * 1.0 * 1.0^x"),
+            "Mul(Mul(Constant(1.0), Constant(1.0)), Exp(Constant(1.0), Variable(\'x\')))"
+        );
+        assert_eq!(
+            parse_debug("(0.131)^(-1.0) * -2.0 * 4.3"),
+            "Mul(Mul(Exp(Constant(0.131), Mul(Constant(-1.0), Constant(1.0))), Mul(Constant(-1.0), Constant(2.0))), Constant(4.3))"
+        );
+        assert_eq!(
+            parse_debug("x / y * z"),
+            "Mul(Div(Variable(\'x\'), Variable(\'y\')), Variable(\'z\'))"
+        );
     }
 
     #[test]
-    fn test_parse_primary() {
-        let mut input: Vec<char>;
-        let mut result;
+    fn test_implicit_multiplication() {
+        assert_eq!(parse("2x").unwrap(), parse("2 * x").unwrap());
+        assert_eq!(parse("2x^2").unwrap(), parse("2 * x^2").unwrap());
+        assert_eq!(parse("x(y + 1)").unwrap(), parse("x * (y + 1)").unwrap());
+        assert_eq!(parse("3 x y").unwrap(), parse("3 * x * y").unwrap());
+        assert_eq!(parse("1/2x").unwrap(), parse("1 / 2 * x").unwrap());
+        assert_eq!(parse("-2 sin(x)").unwrap(), parse("-2 * sin(x)").unwrap());
+        assert_eq!(
+            parse("(x + 1)(x - 1)").unwrap(),
+            parse("(x + 1) * (x - 1)").unwrap()
+        );
+        assert_eq!(parse("a (x + 1)").unwrap(), parse("a * (x + 1)").unwrap());
 
-        input = "x".chars().collect();
-        result = parse_primary(&input, 0).unwrap();
-        assert_variable(&result.0, 'x');
+        // Numbers only multiply when they come first
+        assert_eq!(parse_err("x 2"), "Unconsumed input starting at 2");
+    }
 
-        input = "0.0 + x * 9".chars().collect();
-        result = parse_primary(&input, 10).unwrap();
-        assert_constant(&result.0, 9.0);
-        assert_eq!(result.1, 11);
+    fn parse_latex(text: &str) -> ParseResult<BNode> {
+        let input: Vec<char> = text.chars().collect();
+        parse_latex_expression(&input, 0)
+    }
+
+    #[test]
+    fn test_parse_latex() {
+        let cases = [
+            ("\\frac{x^{2}}{4}", "x^2 / 4"),
+            ("x^{y^{2}}", "x^y^2"),
+            ("2x \\cdot y", "2 * x * y"),
+            ("xy", "x * y"),
+            ("\\sqrt{x + 1}", "sqrt(x + 1)"),
+            ("\\sin x^2 + \\cos{y}", "sin(x^2) + cos(y)"),
+            ("\\tan\\left(\\theta\\right)", "tan(theta)"),
+            ("\\left( x + 1 \\right) \\times 3", "(x + 1) * 3"),
+            ("\\max(x, \\ln(y))", "max(x, ln(y))"),
+            ("e^{-x} - e^2", "exp(-x) - exp(2)"),
+            ("\\left|x\\right| + |y - 1|", "abs(x) + abs(y - 1)"),
+            ("\\mathrm{radius} \\, r", "radius * r"),
+            ("-\\frac{1}{2}z", "-(1 / 2) * z"),
+        ];
+
+        for (latex, plain) in cases.iter() {
+            assert_eq!(
+                parse_latex(latex).unwrap(),
+                parse(plain).unwrap(),
+                "{}",
+                latex
+            );
+        }
 
-        input = "(-2.0)".chars().collect();
-        result = parse_primary(&input, 0).unwrap();
         assert_eq!(
-            format!("{:?}", result.0),
-            "Mul(Constant(-1.0), Constant(2.0))"
+            format!("{}", parse_latex("\\frac{x}").unwrap_err()),
+            "Unexpected end of input, expected \'{\'"
+        );
+        assert_eq!(
+            format!("{}", parse_latex("\\foo{x}").unwrap_err()),
+            "Unknown identifier \\foo at 0"
+        );
+        assert_eq!(
+            format!("{}", parse_latex("|x").unwrap_err()),
+            "Unexpected end of input, expected \'|\' or an operator"
         );
 
-        input = "1 + 2 * (0.131) + 4".chars().collect();
-        result = parse_primary(&input, 8).unwrap();
-        assert_constant(&result.0, 0.131);
-        assert_eq!(result.1, 16);
+        let input: Vec<char> = "x^{2} + y^{2} \\le 1, z \\geq \\frac{1}{2}"
+            .chars()
+            .collect();
+        assert!(is_latex(&input));
+        let result = parse_latex_system(&input, 0).unwrap();
+        assert_eq!(result[0].1, Relation::LessEqual);
+        assert_eq!(result[1].1, Relation::GreaterEqual);
 
-        input = "(0.131.".chars().collect();
-        let err = parse_primary(&input, 0).unwrap_err();
-        assert_eq!(format!("{}", err), "Looking for character: ), found . at 6");
+        let input: Vec<char> = "x^2 + y^2 <= 1".chars().collect();
+        assert!(!is_latex(&input));
+    }
 
-        input = "(0.131".chars().collect();
-        let err = parse_primary(&input, 0).unwrap_err();
-        assert_eq!(format!("{}", err), "Unexpected end of input");
+    #[test]
+    fn test_parse_add() {
+        assert_eq!(
+            parse_debug("1.0 + 2.0 * 3.0 - 4.0"),
+            "Sub(Add(Constant(1.0), Mul(Constant(2.0), Constant(3.0))), Constant(4.0))"
+        );
+        assert_eq!(
+            parse_debug("x - y - z"),
+            "Sub(Sub(Variable(\'x\'), Variable(\'y\')), Variable(\'z\'))"
+        );
+        assert_eq!(parse_err("x = 1"), "Unconsumed input starting at 2");
     }
 
     #[test]
-    fn test_parse_exp() {
+    fn test_parse_equation() {
         let mut input: Vec<char>;
         let mut result;
 
-        input = "x".chars().collect();
-        result = parse_exp(&input, 0).unwrap();
-        assert_variable(&result.0, 'x');
+        input = "x^2 + y^2 = z".chars().collect();
+        result = parse_equation(&input, 0).unwrap();
+        assert_eq!(
+            format!("{:?}", result),
+            "Sub(Add(Exp(Variable(\'x\'), Constant(2.0)), Exp(Variable(\'y\'), Constant(2.0))), Variable(\'z\'))"
+        );
+
+        input = "0=x".chars().collect();
+        result = parse_equation(&input, 0).unwrap();
+        assert_eq!(
+            format!("{:?}", result),
+            "Sub(Constant(0.0), Variable(\'x\'))"
+        );
+
+        input = "x + y".chars().collect();
+        let err = parse_equation(&input, 0).unwrap_err();
+        assert_eq!(format!("{}", err), "Missing =, <, <=, > or >= at 5");
+
+        input = "x + y ) = 2".chars().collect();
+        let err = parse_equation(&input, 0).unwrap_err();
+        assert_eq!(format!("{}", err), "Missing =, <, <=, > or >= at 6");
 
-        input = "0.0 + x * 9".chars().collect();
-        result = parse_exp(&input, 10).unwrap();
-        assert_constant(&result.0, 9.0);
-        assert_eq!(result.1, 11);
+        input = "x = y = z".chars().collect();
+        let err = parse_equation(&input, 0).unwrap_err();
+        assert_eq!(format!("{}", err), "Duplicate relation at 6");
 
-        input = "(-2.0)".chars().collect();
-        result = parse_exp(&input, 0).unwrap();
+        input = "x <= 1".chars().collect();
+        let err = parse_equation(&input, 0).unwrap_err();
+        assert_eq!(format!("{}", err), "Expected '=', found '<' at 2");
+
+        input = "x = ".chars().collect();
+        let err = parse_equation(&input, 0).unwrap_err();
         assert_eq!(
-            format!("{:?}", result.0),
-            "Mul(Constant(-1.0), Constant(2.0))"
+            format!("{}", err),
+            "Unexpected end of input, expected a number, an identifier, \'(\' or \'-\'"
         );
+    }
+
+    #[test]
+    fn test_parse_relation() {
+        let mut input: Vec<char>;
+        let mut result;
 
-        input = "0.131^x".chars().collect();
-        result = parse_exp(&input, 0).unwrap();
+        input = "x^2 + y^2 < 1".chars().collect();
+        result = parse_relation(&input, 0).unwrap();
+        assert_eq!(result.1, Relation::Less);
         assert_eq!(
             format!("{:?}", result.0),
-            "Exp(Constant(0.131), Variable(\'x\'))"
+            "Sub(Add(Exp(Variable(\'x\'), Constant(2.0)), Exp(Variable(\'y\'), Constant(2.0))), Constant(1.0))"
         );
 
-        input = "(0.131)^(-1.2332)".chars().collect();
-        result = parse_exp(&input, 0).unwrap();
+        input = "x<=y".chars().collect();
+        result = parse_relation(&input, 0).unwrap();
+        assert_eq!(result.1, Relation::LessEqual);
         assert_eq!(
             format!("{:?}", result.0),
-            "Exp(Constant(0.131), Mul(Constant(-1.0), Constant(1.2332)))"
+            "Sub(Variable(\'x\'), Variable(\'y\'))"
         );
 
-        input = "(0.131)^".chars().collect();
-        let err = parse_exp(&input, 0).unwrap_err();
-        assert_eq!(format!("{}", err), "Unexpected end of input");
+        input = "x > y".chars().collect();
+        result = parse_relation(&input, 0).unwrap();
+        assert_eq!(result.1, Relation::Greater);
+
+        input = "x >= y".chars().collect();
+        result = parse_relation(&input, 0).unwrap();
+        assert_eq!(result.1, Relation::GreaterEqual);
+
+        input = "x = y".chars().collect();
+        result = parse_relation(&input, 0).unwrap();
+        assert_eq!(result.1, Relation::Equal);
 
-        input = "((1.0)^(1.0))^x".chars().collect();
-        result = parse_exp(&input, 0).unwrap();
+        input = "x < y <= z".chars().collect();
+        let err = parse_relation(&input, 0).unwrap_err();
+        assert_eq!(format!("{}", err), "Duplicate relation at 6");
+
+        input = "x >".chars().collect();
+        let err = parse_relation(&input, 0).unwrap_err();
         assert_eq!(
-            format!("{:?}", result.0),
-            "Exp(Exp(Constant(1.0), Constant(1.0)), Variable(\'x\'))"
+            format!("{}", err),
+            "Unexpected end of input, expected a number, an identifier, \'(\' or \'-\'"
         );
     }
 
     #[test]
-    fn test_parse_mul() {
+    fn test_parse_system() {
         let mut input: Vec<char>;
         let mut result;
 
-        input = "x".chars().collect();
-        result = parse_mul(&input, 0).unwrap();
-        assert_variable(&result.0, 'x');
+        input = "x^2 + y^2 = 1, z = 0".chars().collect();
+        result = parse_system(&input, 0).unwrap();
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].1, Relation::Equal);
+        assert_eq!(
+            format!("{:?}", result[1].0),
+            "Sub(Variable(\'z\'), Constant(0.0))"
+        );
 
-        input = "1.0 * 1.0 * 1.0".chars().collect();
-        result = parse_mul(&input, 0).unwrap();
+        input = "x = 0,y < 1 , max(x, y) >= z".chars().collect();
+        result = parse_system(&input, 0).unwrap();
+        assert_eq!(result.len(), 3);
+        assert_eq!(result[1].1, Relation::Less);
+        assert_eq!(result[2].1, Relation::GreaterEqual);
         assert_eq!(
-            format!("{:?}", result.0),
-            "Mul(Mul(Constant(1.0), Constant(1.0)), Constant(1.0))"
+            format!("{:?}", result[2].0),
+            "Sub(Max(Variable(\'x\'), Variable(\'y\')), Variable(\'z\'))"
         );
 
-        input = "1.0 * 1.0 * 1.0^x".chars().collect();
-        result = parse_mul(&input, 0).unwrap();
+        // Expressions on their own are `= 0`, as before equations had two sides
+        input = "x = 0, y".chars().collect();
+        result = parse_system(&input, 0).unwrap();
+        assert_eq!(result[1].1, Relation::Equal);
+        assert_eq!(format!("{:?}", result[1].0), "Variable('y')");
+
+        input = "x = 0, y )".chars().collect();
+        let err = parse_system(&input, 0).unwrap_err();
+        assert_eq!(format!("{}", err), "Missing =, <, <=, > or >= at 9");
+
+        input = "x = 0,".chars().collect();
+        let err = parse_system(&input, 0).unwrap_err();
         assert_eq!(
-            format!("{:?}", result.0),
-            "Mul(Mul(Constant(1.0), Constant(1.0)), Exp(Constant(1.0), Variable(\'x\')))"
+            format!("{}", err),
+            "Unexpected end of input, expected a number, an identifier, \'(\' or \'-\'"
         );
 
-        input = "(0.131)^(-1.0) * -2.0 * 4.3".chars().collect();
-        result = parse_mul(&input, 0).unwrap();
+        input = "x = 0 )".chars().collect();
+        let err = parse_system(&input, 0).unwrap_err();
+        assert_eq!(format!("{}", err), "Unconsumed input starting at 6");
+
+        input = "x = (1, y = 2".chars().collect();
+        let err = parse_system(&input, 0).unwrap_err();
         assert_eq!(
-            format!("{:?}", result.0),
-            "Mul(Mul(Exp(Constant(0.131), Mul(Constant(-1.0), Constant(1.0))), Mul(Constant(-1.0), Constant(2.0))), Constant(4.3))"
+            format!("{}", err),
+            "Expected \')\' or an operator, found \',\' at 6"
         );
     }
 
     #[test]
-    fn test_parse_add() {
-        let mut input: Vec<char>;
-        let mut result;
+    fn test_parse_system_recovery() {
+        let input: Vec<char> = "x + = foo(y), y = 1, z < * 2".chars().collect();
+        let err = parse_system(&input, 0).unwrap_err();
+        assert_eq!(err.0.len(), 3);
+        assert_eq!(
+            format!("{}", err),
+            "Expected a number, an identifier, \'(\' or \'-\', found \'=\' at 4\n\
+             Unknown identifier foo at 6\n\
+             Expected a number, an identifier, \'(\' or \'-\', found \'*\' at 25"
+        );
+        assert_eq!(err.0[1].span, Span::new(6, 9));
+
+        let input: Vec<char> = "min(x) = 1, x = 0 0".chars().collect();
+        let err = parse_system(&input, 0).unwrap_err();
+        assert_eq!(
+            err.render(&input),
+            "Function min takes 2 arguments, found 1 at 0\n  min(x) = 1, x = 0 0\n  ^^^^^^\n\
+             Unconsumed input starting at 18\n  min(x) = 1, x = 0 0\n                    ^"
+        );
+    }
 
-        input = "x".chars().collect();
-        result = parse_add(&input, 0).unwrap();
-        assert_variable(&result.0, 'x');
+    #[test]
+    fn test_coordinates() {
+        let coordinates = |text: &str| {
+            let input: Vec<char> = text.chars().collect();
+            let program = if is_latex(&input) {
+                parse_latex_program_with_coordinates(&input, 0)
+            } else {
+                parse_program_with_coordinates(&input, 0)
+            };
+            program.unwrap().constraints.remove(0).0
+        };
+
+        assert_eq!(coordinates("r"), parse("sqrt(x^2 + y^2 + z^2)").unwrap());
+        assert_eq!(coordinates("rho"), parse("sqrt(x^2 + y^2)").unwrap());
+        assert_eq!(coordinates("theta"), parse("atan2(y, x)").unwrap());
+        assert_eq!(
+            coordinates("phi"),
+            parse("atan2(sqrt(x^2 + y^2), z)").unwrap()
+        );
+        assert_eq!(
+            coordinates("\\rho \\cdot \\sin{5\\theta}"),
+            parse("sqrt(x^2 + y^2) * sin(5 * atan2(y, x))").unwrap()
+        );
 
-        input = "1.0 + 2.0 * 3.0 - 4.0".chars().collect();
-        result = parse_add(&input, 0).unwrap();
+        // Otherwise they are parameters like any other name
+        assert_eq!(parse_debug("r"), "Parameter(\"r\")");
         assert_eq!(
-            format!("{:?}", result.0),
-            "Sub(Add(Constant(1.0), Mul(Constant(2.0), Constant(3.0))), Constant(4.0))"
+            format!("{:?}", parse_latex("\\theta").unwrap()),
+            "Parameter(\"theta\")"
         );
+
+        // On the sphere of radius 2, at 60 degrees around and 30 degrees down
+        let (x, y, z) = (0.5, 0.866_025_4, 1.732_050_8);
+        let f = coordinates("r - 2 + theta = phi");
+        let expected = std::f32::consts::FRAC_PI_3 - std::f32::consts::FRAC_PI_6;
+        assert_similiar!(Function::evaluate(&*f, x, y, z), expected);
+
+        // Definitions and their parameters shadow coordinates
+        let input: Vec<char> = "r = 2; f(theta) = theta^2; f(x) - r = 0".chars().collect();
+        let program = parse_program_with_coordinates(&input, 0).unwrap();
+        assert_eq!(
+            format!("{:?}", program.expand()[0].0),
+            format!("{:?}", parse("x^2 - 2 - 0").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_parse_program() {
+        let input: Vec<char> = "f(x) = x^2; f(y) + f (z) = 1".chars().collect();
+        let program = parse_program(&input, 0).unwrap();
+        assert_eq!(
+            format!("{:?}", program.constraints[0].0),
+            format!("{:?}", parse("y^2 + z^2 - 1").unwrap())
+        );
+
+        // Without definitions it's the same as a system
+        let input: Vec<char> = "x = 1, y = 2".chars().collect();
+        let program = parse_program(&input, 0).unwrap();
+        assert!(program.definitions.is_empty());
+        assert_eq!(program.constraints, parse_system(&input, 0).unwrap());
+
+        let input: Vec<char> = "r = \\sqrt{x^{2} + y^{2}}; r \\le 1".chars().collect();
+        let program = parse_latex_program(&input, 0).unwrap();
+        assert_eq!(
+            format!("{:?}", program.expand()[0].0),
+            format!("{:?}", parse("sqrt(x^2 + y^2) - 1").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_parse_program_errors() {
+        let input: Vec<char> = "x = 1; sin(t) = t; f(t, 2) = t; g = ; f(1) + g = 0"
+            .chars()
+            .collect();
+        let err = parse_program(&input, 0).unwrap_err();
+        assert_eq!(
+            format!("{}", err),
+            "Cannot redefine x at 0\n\
+             Cannot redefine sin at 7\n\
+             Expected an identifier, found \'2\' at 24\n\
+             Expected a number, an identifier, \'(\' or \'-\', found \';\' at 36\n\
+             Unknown identifier f at 38"
+        );
+
+        // A broken body still defines the function, so calling it isn't another error
+        let input: Vec<char> = "f(t) = t +; f(x) = 0".chars().collect();
+        let err = parse_program(&input, 0).unwrap_err();
+        assert_eq!(err.0.len(), 1);
+
+        let input: Vec<char> = "f(a, b) = a * b; f(x) = 0".chars().collect();
+        let err = parse_program(&input, 0).unwrap_err();
+        assert_eq!(
+            format!("{}", err),
+            "Function f takes 2 arguments, found 1 at 17"
+        );
+    }
+
+    #[test]
+    fn test_no_panic_at_end_of_input() {
+        for text in ["", " ", "(", "x^", "x*", "min(x,", "x = -", "3 + (", "sin"].iter() {
+            let input: Vec<char> = text.chars().collect();
+            assert!(parse_expression(&input, 0).is_err());
+            assert!(parse_system(&input, 0).is_err());
+        }
     }
 
     // TODO add more parse_expression tests now that the old ones moved to
@@ -2,29 +2,129 @@ use std::error::Error;
 use std::fmt;
 use std::num;
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Expected {
-    Base,
-    Constant,
+    Number,
+    Identifier,
+    Operator,
+    Relation,
     Char(char),
 }
 
 impl fmt::Display for Expected {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Expected::Base => write!(f, "Base"),
-            Expected::Constant => write!(f, "Constant"),
-            Expected::Char(c) => write!(f, "character: {}", c),
+            Expected::Number => write!(f, "a number"),
+            Expected::Identifier => write!(f, "an identifier"),
+            Expected::Operator => write!(f, "an operator"),
+            Expected::Relation => write!(f, "=, <, <=, > or >="),
+            Expected::Char(c) => write!(f, "'{}'", c),
         }
     }
 }
 
-#[derive(Eq, PartialEq, Debug)]
-pub enum ParseError {
-    UnexpectedChar { pos: usize, c: char, exp: Expected },
+/// Anything that can start an operand
+pub fn operand() -> Vec<Expected> {
+    vec![
+        Expected::Number,
+        Expected::Identifier,
+        Expected::Char('('),
+        Expected::Char('-'),
+    ]
+}
+
+/// The range of characters an error applies to, end exclusive
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    pub fn at(pos: usize) -> Span {
+        Span::new(pos, pos + 1)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ParseErrorKind {
+    UnexpectedChar {
+        c: char,
+        expected: Vec<Expected>,
+    },
+    UnknownIdentifier(String),
+    ArgumentCount {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
     Float(String),
-    UnexpectedEnd,
-    UnconsumedInput(usize),
+    UnexpectedEnd {
+        expected: Vec<Expected>,
+    },
+    UnconsumedInput,
+    MissingRelation,
+    DuplicateRelation,
+    /// Definitions can't replace variables or built in functions
+    Redefinition(String),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Span,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, span: Span) -> ParseError {
+        ParseError { kind, span }
+    }
+
+    pub fn unexpected_char(c: char, pos: usize, expected: Vec<Expected>) -> ParseError {
+        ParseError::new(
+            ParseErrorKind::UnexpectedChar { c, expected },
+            Span::at(pos),
+        )
+    }
+
+    pub fn unexpected_end(pos: usize, expected: Vec<Expected>) -> ParseError {
+        ParseError::new(ParseErrorKind::UnexpectedEnd { expected }, Span::at(pos))
+    }
+
+    /// The message followed by the input with a caret under the offending span, i.e.
+    ///
+    /// ```text
+    /// Expected ')' or an operator, found '.' at 6
+    ///   (0.131.
+    ///         ^
+    /// ```
+    pub fn render(&self, input: &[char]) -> String {
+        let line: String = input.iter().collect();
+        let width = self.span.end.saturating_sub(self.span.start).max(1);
+        format!(
+            "{}\n  {}\n  {}{}",
+            self,
+            line,
+            " ".repeat(self.span.start),
+            "^".repeat(width)
+        )
+    }
+}
+
+fn write_expected(f: &mut fmt::Formatter, expected: &[Expected]) -> fmt::Result {
+    for (i, e) in expected.iter().enumerate() {
+        if i > 0 && i == expected.len() - 1 {
+            write!(f, " or ")?;
+        } else if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", e)?;
+    }
+    Ok(())
 }
 
 impl Error for ParseError {
@@ -39,23 +139,115 @@ impl Error for ParseError {
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            &ParseError::UnexpectedChar {
-                ref pos,
+        let pos = self.span.start;
+        match self.kind {
+            ParseErrorKind::UnexpectedChar {
                 ref c,
-                ref exp,
-            } => write!(f, "Looking for {}, found {} at {}", exp, c, pos),
-            &ParseError::Float(ref s) => f.write_fmt(format_args!("{}", s)),
-            &ParseError::UnexpectedEnd => write!(f, "Unexpected end of input"),
-            &ParseError::UnconsumedInput(p) => write!(f, "Unconsumed input starting at {}", p),
+                ref expected,
+            } => {
+                write!(f, "Expected ")?;
+                write_expected(f, expected)?;
+                write!(f, ", found '{}' at {}", c, pos)
+            }
+            ParseErrorKind::UnknownIdentifier(ref name) => {
+                write!(f, "Unknown identifier {} at {}", name, pos)
+            }
+            ParseErrorKind::ArgumentCount {
+                ref name,
+                ref expected,
+                ref found,
+            } => write!(
+                f,
+                "Function {} takes {} arguments, found {} at {}",
+                name, expected, found, pos
+            ),
+            ParseErrorKind::Float(ref s) => write!(f, "{} at {}", s, pos),
+            ParseErrorKind::UnexpectedEnd { ref expected } => {
+                write!(f, "Unexpected end of input, expected ")?;
+                write_expected(f, expected)
+            }
+            ParseErrorKind::UnconsumedInput => write!(f, "Unconsumed input starting at {}", pos),
+            ParseErrorKind::MissingRelation => write!(f, "Missing =, <, <=, > or >= at {}", pos),
+            ParseErrorKind::DuplicateRelation => write!(f, "Duplicate relation at {}", pos),
+            ParseErrorKind::Redefinition(ref name) => {
+                write!(f, "Cannot redefine {} at {}", name, pos)
+            }
         }
     }
 }
 
-impl From<num::ParseFloatError> for ParseError {
-    fn from(err: num::ParseFloatError) -> ParseError {
-        ParseError::Float(format!("{}", err))
+impl From<num::ParseFloatError> for ParseErrorKind {
+    fn from(err: num::ParseFloatError) -> ParseErrorKind {
+        ParseErrorKind::Float(format!("{}", err))
     }
 }
 
 pub type ParseResult<R> = Result<R, ParseError>;
+
+/// Every error found while parsing, in the order they appear in the input
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseErrors(pub Vec<ParseError>);
+
+impl ParseErrors {
+    pub fn render(&self, input: &[char]) -> String {
+        let rendered: Vec<String> = self.0.iter().map(|e| e.render(input)).collect();
+        rendered.join("\n")
+    }
+}
+
+impl From<ParseError> for ParseErrors {
+    fn from(err: ParseError) -> ParseErrors {
+        ParseErrors(vec![err])
+    }
+}
+
+impl Error for ParseErrors {
+    fn description(&self) -> &str {
+        "Errors occured while parsing the expression"
+    }
+}
+
+impl fmt::Display for ParseErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, err) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", err)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let input: Vec<char> = "(0.131.".chars().collect();
+        let err =
+            ParseError::unexpected_char('.', 6, vec![Expected::Char(')'), Expected::Operator]);
+        assert_eq!(
+            err.render(&input),
+            "Expected ')' or an operator, found '.' at 6\n  (0.131.\n        ^"
+        );
+
+        let input: Vec<char> = "foo(x) = 1".chars().collect();
+        let err = ParseError::new(
+            ParseErrorKind::UnknownIdentifier("foo".to_string()),
+            Span::new(0, 3),
+        );
+        assert_eq!(
+            err.render(&input),
+            "Unknown identifier foo at 0\n  foo(x) = 1\n  ^^^"
+        );
+
+        let input: Vec<char> = "x +".chars().collect();
+        let err = ParseError::unexpected_end(3, operand());
+        assert_eq!(
+            err.render(&input),
+            "Unexpected end of input, expected a number, an identifier, '(' or '-'\n  x +\n     ^"
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use crate::function::Relation;
+use crate::function_ir::Node;
+use std::collections::HashMap;
+
+/// A name given to an expression, i.e. `r = x^2 + y^2` or `f(t) = t^2 - 1`.
+/// Bodies have every earlier definition expanded already, so the only
+/// parameters left in them are their own and those of the equation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definition {
+    pub name: String,
+    pub parameters: Vec<String>,
+    pub body: Box<Node>,
+}
+
+impl Definition {
+    pub fn is_function(&self) -> bool {
+        !self.parameters.is_empty()
+    }
+
+    /// The body with the arguments in place of the parameters
+    pub fn call(&self, args: Vec<Box<Node>>) -> Node {
+        let replacements: HashMap<String, Node> = self
+            .parameters
+            .iter()
+            .cloned()
+            .zip(args.into_iter().map(|arg| *arg))
+            .collect();
+        self.body.substitute(&replacements)
+    }
+}
+
+/// The definitions that aren't functions by name, later ones replacing earlier ones
+pub fn values(definitions: &[Definition]) -> HashMap<String, Node> {
+    definitions
+        .iter()
+        .filter(|d| !d.is_function())
+        .map(|d| (d.name.clone(), (*d.body).clone()))
+        .collect()
+}
+
+/// Definitions followed by the constraints that use them, i.e.
+/// `r = x^2 + y^2; f(t) = t^2 - 1; f(r) + z^2 = 0`.
+///
+/// Calls to functions are inlined while parsing, while the constraints still refer to
+/// values by name until `expand` substitutes a full copy into every use.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub definitions: Vec<Definition>,
+    pub constraints: Vec<(Box<Node>, Relation)>,
+}
+
+impl Program {
+    /// The definition for a name, the latest one if it was given more than once
+    pub fn definition(&self, name: &str) -> Option<&Definition> {
+        self.definitions.iter().rev().find(|d| d.name == name)
+    }
+
+    /// The constraints with every definition written out in full
+    pub fn expand(&self) -> Vec<(Box<Node>, Relation)> {
+        let values = values(&self.definitions);
+        self.constraints
+            .iter()
+            .map(|(f, relation)| (Box::new(f.substitute(&values)), *relation))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::{parse_expression, parse_program};
+
+    fn parse(text: &str) -> String {
+        let input: Vec<char> = text.chars().collect();
+        format!("{:?}", parse_expression(&input, 0).unwrap())
+    }
+
+    #[test]
+    fn test_expand() {
+        let input: Vec<char> = "r = x^2 + y^2; f(t) = t^2 - 1; f(r) + z^2 = 0"
+            .chars()
+            .collect();
+        let program = parse_program(&input, 0).unwrap();
+        assert_eq!(program.definitions.len(), 2);
+        assert!(program.definition("f").unwrap().is_function());
+        assert_eq!(
+            format!("{:?}", program.constraints[0].0),
+            parse("(r^2 - 1 + z^2) - 0")
+        );
+
+        let expanded = program.expand();
+        assert_eq!(
+            format!("{:?}", expanded[0].0),
+            parse("((x^2 + y^2)^2 - 1 + z^2) - 0")
+        );
+    }
+
+    #[test]
+    fn test_expand_nested() {
+        // Later definitions see earlier ones, and parameters shadow values
+        let input: Vec<char> = "a = 2; b = a * x; g(a, y) = a + y + b; g(z, 1) = b, a < 3"
+            .chars()
+            .collect();
+        let expanded = parse_program(&input, 0).unwrap().expand();
+        assert_eq!(expanded.len(), 2);
+        assert_eq!(
+            format!("{:?}", expanded[0].0),
+            parse("(z + 1 + 2 * x) - 2 * x")
+        );
+        assert_eq!(format!("{:?}", expanded[1].0), parse("2 - 3"));
+    }
+}
@@ -0,0 +1,503 @@
+use crate::cell_keys::{Key, QuadKey};
+use crate::function::*;
+use crate::geoprim::*;
+use crate::interval::{contains_zero, Interval};
+use crate::mesh_tree::{CellClass, Degeneracy};
+use cgmath::Vector2;
+use std::collections::HashMap;
+
+/// The 2D counterpart to `BoundingBox`, a cell of a `QuadTree`
+#[derive(Debug, Copy, Clone)]
+pub struct BoundingRectangle {
+    pub x: Interval,
+    pub y: Interval,
+}
+
+impl BoundingRectangle {
+    /// The four quadrants, in the same order as the children of a `QuadKey`
+    pub fn split(&self) -> Vec<BoundingRectangle> {
+        let mut result = Vec::with_capacity(4);
+        for x in &self.x.split() {
+            for y in &self.y.split() {
+                result.push(BoundingRectangle { x: *x, y: *y });
+            }
+        }
+        result
+    }
+
+    /// The rectangle lies in the plane z = 0
+    fn bindings(&self) -> HashMap<char, Interval> {
+        let mut bindings = HashMap::new();
+        bindings.insert('x', self.x);
+        bindings.insert('y', self.y);
+        bindings.insert('z', Interval::new(0.0, 0.0));
+        bindings
+    }
+
+    pub fn contains_root<F: Function>(&self, f: &Box<F>) -> bool {
+        let intervals = f.evaluate_interval(&self.bindings());
+        contains_zero(&intervals)
+    }
+
+    /// Cells inside the curve are those where `f` is identically zero
+    pub fn classify<F: Function>(&self, f: &Box<F>) -> CellClass {
+        let intervals = f.evaluate_interval(&self.bindings());
+        CellClass::from_intervals(&intervals, Relation::Equal)
+    }
+
+    /// Counter clockwise, starting from the lower left
+    fn corners(&self) -> [Vector2<f32>; 4] {
+        [
+            Vector2::new(self.x.min, self.y.min),
+            Vector2::new(self.x.max, self.y.min),
+            Vector2::new(self.x.max, self.y.max),
+            Vector2::new(self.x.min, self.y.max),
+        ]
+    }
+
+    pub fn add_to_plot(&self, plot: &mut Plot) {
+        let corners = self.corners();
+        for i in 0..4 {
+            let (a, b) = (corners[i], corners[(i + 1) % 4]);
+            plot.add_line(LineSegment::new(
+                Point::new(a.x, a.y, 0.0),
+                Point::new(b.x, b.y, 0.0),
+            ));
+        }
+    }
+
+    pub fn add_floats(&self, result: &mut Vec<f32>) {
+        let corners = self.corners();
+        for i in 0..4 {
+            let (a, b) = (corners[i], corners[(i + 1) % 4]);
+            result.extend(&[a.x, a.y, 0.0, b.x, b.y, 0.0]);
+        }
+    }
+
+    /// Add two triangles covering the rectangle
+    pub fn add_face_floats(&self, result: &mut Vec<f32>) {
+        let corners = self.corners();
+        for c in &[
+            corners[0], corners[1], corners[2], corners[0], corners[2], corners[3],
+        ] {
+            result.extend(&[c.x, c.y, 0.0]);
+        }
+    }
+}
+
+/// A cell edge on the grid of the current level, given by the axis it runs along
+/// and the grid coordinates of its lower corner
+type EdgeId = (usize, u32, u32);
+
+/// Meshes the curve where `f(x, y, 0) = 0` into polylines. Like `MeshTree`, cells
+/// that might contain the curve are refined each level, then marching squares
+/// connects the points where the curve crosses the edges of each cell.
+pub struct QuadTree<K: Key, F: Function> {
+    source: Box<F>,
+    f: Box<F>,
+    parameters: Parameters,
+    bounding_rectangle: BoundingRectangle,
+    pub level: u32,
+    solution_map: HashMap<K, BoundingRectangle>,
+    interior_map: HashMap<K, BoundingRectangle>,
+    crossings: HashMap<EdgeId, Vector2<f32>>,
+    segments: Vec<(EdgeId, EdgeId)>,
+}
+
+impl<F: Function> QuadTree<QuadKey, F> {
+    pub fn new(f: Box<F>, rectangle: BoundingRectangle) -> QuadTree<QuadKey, F> {
+        let mut result = QuadTree {
+            f: Box::new(f.bind_parameters(&Parameters::new())),
+            source: f,
+            parameters: Parameters::new(),
+            bounding_rectangle: rectangle,
+            level: 0,
+            solution_map: HashMap::new(),
+            interior_map: HashMap::new(),
+            crossings: HashMap::new(),
+            segments: Vec::new(),
+        };
+
+        result.insert_cell(QuadKey::root_key(), rectangle);
+        result
+    }
+
+    pub fn get_parameters(&self) -> &Parameters {
+        &self.parameters
+    }
+
+    /// Bind new parameter values and re-mesh back down to the current level.
+    /// Segments need to be regenerated afterwards.
+    pub fn set_parameters(&mut self, parameters: Parameters) {
+        let level = self.level;
+
+        *self.f = self.source.bind_parameters(&parameters);
+        self.parameters = parameters;
+        self.level = 0;
+        self.solution_map.clear();
+        self.interior_map.clear();
+        self.crossings.clear();
+        self.segments.clear();
+        self.insert_cell(QuadKey::root_key(), self.bounding_rectangle);
+
+        while self.level < level {
+            self.next_level();
+        }
+    }
+
+    fn insert_cell(&mut self, key: QuadKey, rectangle: BoundingRectangle) {
+        match rectangle.classify(&self.f) {
+            CellClass::Boundary => {
+                self.solution_map.insert(key, rectangle);
+            }
+            CellClass::Inside => {
+                self.interior_map.insert(key, rectangle);
+            }
+            CellClass::Outside => (),
+        }
+    }
+
+    pub fn get_level(&self) -> u32 {
+        self.level
+    }
+
+    pub fn get_solution_cell_count(&self) -> usize {
+        self.solution_map.len()
+    }
+
+    pub fn get_interior_cell_count(&self) -> usize {
+        self.interior_map.len()
+    }
+
+    /// Whether the curve turned out to be the whole rectangle or nowhere in it,
+    /// see `MeshTree::degeneracy`
+    pub fn degeneracy(&self) -> Option<Degeneracy> {
+        if !self.solution_map.is_empty() {
+            None
+        } else if self.interior_map.contains_key(&QuadKey::root_key()) {
+            if self.source.is_total() {
+                Some(Degeneracy::Everywhere)
+            } else {
+                None
+            }
+        } else if self.interior_map.is_empty() {
+            Some(Degeneracy::Nowhere)
+        } else {
+            None
+        }
+    }
+
+    pub fn next_level(&mut self) {
+        self.crossings.clear();
+        self.segments.clear();
+        self.level += 1;
+
+        let old_solution_map = std::mem::take(&mut self.solution_map);
+
+        for (key, rectangle) in &old_solution_map {
+            for (i, child) in rectangle.split().into_iter().enumerate() {
+                self.insert_cell(key.child_key(i as u64), child);
+            }
+        }
+    }
+
+    /// Run marching squares over each solution cell. Cells sharing an edge share
+    /// the point where the curve crosses it, so the segments join up into polylines.
+    pub fn generate_segments(&mut self) {
+        self.crossings.clear();
+        self.segments.clear();
+
+        for (key, rectangle) in &self.solution_map {
+            let (i, j) = (key.get_component(0), key.get_component(1));
+            let corners = rectangle.corners();
+            let values: Vec<f32> = corners
+                .iter()
+                .map(|c| self.f.evaluate(c.x, c.y, 0.0))
+                .collect();
+            if values.iter().any(|v| !v.is_finite()) {
+                continue;
+            }
+
+            // Bottom, right, top and left, each running from its lower corner
+            let edges = [
+                ((0, i, j), 0, 1),
+                ((1, i + 1, j), 1, 2),
+                ((0, i, j + 1), 3, 2),
+                ((1, i, j), 0, 3),
+            ];
+
+            let mut crossed = Vec::with_capacity(4);
+            for (id, a, b) in &edges {
+                let (va, vb) = (values[*a], values[*b]);
+                if (va > 0.0) == (vb > 0.0) {
+                    continue;
+                }
+
+                let (ca, cb) = (corners[*a], corners[*b]);
+                self.crossings
+                    .entry(*id)
+                    .or_insert_with(|| ca + (cb - ca) * (va / (va - vb)));
+                crossed.push(*id);
+            }
+
+            match crossed.len() {
+                2 => self.segments.push((crossed[0], crossed[1])),
+                4 => {
+                    // A saddle, the value at the center decides which corners
+                    // are cut off from the others
+                    let center = self
+                        .f
+                        .evaluate(rectangle.x.middle(), rectangle.y.middle(), 0.0);
+                    if (center > 0.0) == (values[0] > 0.0) {
+                        self.segments.push((crossed[0], crossed[1]));
+                        self.segments.push((crossed[2], crossed[3]));
+                    } else {
+                        self.segments.push((crossed[3], crossed[0]));
+                        self.segments.push((crossed[1], crossed[2]));
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// The segments joined end to end. Open curves run between the edges of the
+    /// bounding rectangle, closed curves end with their first point repeated.
+    pub fn polylines(&self) -> Vec<Vec<Vector2<f32>>> {
+        let mut incident: HashMap<EdgeId, Vec<usize>> = HashMap::new();
+        for (s, (a, b)) in self.segments.iter().enumerate() {
+            incident.entry(*a).or_default().push(s);
+            incident.entry(*b).or_default().push(s);
+        }
+
+        let mut visited = vec![false; self.segments.len()];
+        let walk = |start: EdgeId, visited: &mut Vec<bool>| {
+            let mut polyline = vec![self.crossings[&start]];
+            let mut current = start;
+            while let Some(s) = incident[&current].iter().find(|s| !visited[**s]) {
+                visited[*s] = true;
+                let (a, b) = self.segments[*s];
+                current = if a == current { b } else { a };
+                polyline.push(self.crossings[&current]);
+            }
+            polyline
+        };
+
+        let mut result = Vec::new();
+        let ends: Vec<EdgeId> = incident
+            .iter()
+            .filter(|(_, segments)| segments.len() == 1)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in ends {
+            if !visited[incident[&id][0]] {
+                result.push(walk(id, &mut visited));
+            }
+        }
+
+        for s in 0..self.segments.len() {
+            if !visited[s] {
+                result.push(walk(self.segments[s].0, &mut visited));
+            }
+        }
+
+        result
+    }
+
+    pub fn add_to_plot(
+        &self,
+        add_bb: bool,
+        add_vertices: bool,
+        add_interior: bool,
+        plot: &mut Plot,
+    ) {
+        if add_bb {
+            for rectangle in self.solution_map.values() {
+                rectangle.add_to_plot(plot);
+            }
+        }
+
+        if add_interior {
+            for rectangle in self.interior_map.values() {
+                rectangle.add_to_plot(plot);
+            }
+        }
+
+        if add_vertices {
+            for c in self.crossings.values() {
+                plot.add_point(Point::new(c.x, c.y, 0.0));
+            }
+        }
+
+        for (a, b) in &self.segments {
+            let (c1, c2) = (self.crossings[a], self.crossings[b]);
+            plot.add_line(LineSegment::new(
+                Point::new(c1.x, c1.y, 0.0),
+                Point::new(c2.x, c2.y, 0.0),
+            ));
+        }
+    }
+
+    pub fn get_edge_floats(&self) -> Vec<f32> {
+        let mut result = Vec::with_capacity(self.segments.len() * 6);
+
+        for (a, b) in &self.segments {
+            let (c1, c2) = (self.crossings[a], self.crossings[b]);
+            result.extend(&[c1.x, c1.y, 0.0, c2.x, c2.y, 0.0]);
+        }
+
+        result
+    }
+
+    pub fn get_vertex_floats(&self) -> Vec<f32> {
+        let mut result = Vec::with_capacity(self.crossings.len() * 3);
+
+        for c in self.crossings.values() {
+            result.extend(&[c.x, c.y, 0.0]);
+        }
+
+        result
+    }
+
+    pub fn get_bounding_box_floats(&self) -> Vec<f32> {
+        let mut result = Vec::with_capacity(self.solution_map.len() * 24);
+
+        for rectangle in self.solution_map.values() {
+            rectangle.add_floats(&mut result);
+        }
+
+        result
+    }
+
+    /// Triangles filling in each cell where the equation holds throughout
+    pub fn get_interior_floats(&self) -> Vec<f32> {
+        let mut result = Vec::with_capacity(self.interior_map.len() * 18);
+
+        for rectangle in self.interior_map.values() {
+            rectangle.add_face_floats(&mut result);
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_equation;
+    use cgmath::InnerSpace;
+
+    fn square(min: f32, max: f32) -> BoundingRectangle {
+        let interval = Interval::new(min, max);
+        BoundingRectangle {
+            x: interval,
+            y: interval,
+        }
+    }
+
+    fn quad_tree(equation: &str, levels: u32) -> QuadTree<QuadKey, crate::function_ir::Node> {
+        let input: Vec<char> = equation.chars().collect();
+        let f = parse_equation(&input, 0).unwrap();
+        let mut qtree = QuadTree::new(f, square(-2.0, 2.0));
+        for _ in 0..levels {
+            qtree.next_level();
+        }
+        qtree.generate_segments();
+        qtree
+    }
+
+    #[test]
+    fn test_circle() {
+        let qtree = quad_tree("x^2 + y^2 = 1", 6);
+        assert!(qtree.get_solution_cell_count() > 0);
+        for rectangle in qtree.solution_map.values() {
+            let corners = rectangle.corners();
+            assert!(corners.iter().any(|c| c.magnitude() <= 1.0 + 0.0625 * 1.5));
+        }
+
+        let polylines = qtree.polylines();
+        assert_eq!(polylines.len(), 1);
+        let circle = &polylines[0];
+        assert!(circle.len() > 32);
+        assert_eq!(circle.first(), circle.last());
+        for point in circle {
+            assert!((point.magnitude() - 1.0).abs() < 0.01);
+        }
+
+        let mut plot = Plot::new();
+        qtree.add_to_plot(false, false, false, &mut plot);
+        assert_eq!(plot.lines.len(), circle.len() - 1);
+    }
+
+    #[test]
+    fn test_open_curve() {
+        let qtree = quad_tree("y = x^2 - 1", 5);
+        let polylines = qtree.polylines();
+        assert_eq!(polylines.len(), 1);
+
+        // The parabola leaves through the top of the rectangle
+        let parabola = &polylines[0];
+        for end in &[parabola[0], parabola[parabola.len() - 1]] {
+            assert_similiar!(end.y, 2.0);
+            assert!((end.x.abs() - 3f32.sqrt()).abs() < 0.05);
+        }
+        for point in parabola {
+            assert!((point.y - (point.x * point.x - 1.0)).abs() < 0.05);
+        }
+    }
+
+    #[test]
+    fn test_degeneracy() {
+        let qtree = quad_tree("0 = 0", 3);
+        assert_eq!(qtree.degeneracy(), Some(Degeneracy::Everywhere));
+        assert_eq!(qtree.get_solution_cell_count(), 0);
+        assert_eq!(qtree.get_interior_floats().len(), 18);
+
+        // Undefined for negative x, though the enclosure of the defined part is just 0
+        let qtree = quad_tree("0 * sqrt(x) = 0", 3);
+        assert_eq!(qtree.degeneracy(), None);
+
+        let qtree = quad_tree("x^2 + y^2 = -1", 3);
+        assert_eq!(qtree.degeneracy(), Some(Degeneracy::Nowhere));
+        assert!(qtree.polylines().is_empty());
+
+        // The left half of the plane is a solution, with the y axis as its boundary
+        let qtree = quad_tree("max(x, 0) = 0", 3);
+        assert_eq!(qtree.degeneracy(), None);
+        assert!(qtree.get_interior_cell_count() > 0);
+        for rectangle in qtree.interior_map.values() {
+            assert!(rectangle.x.max <= 0.0);
+        }
+        for rectangle in qtree.solution_map.values() {
+            assert!(rectangle.x.min <= 0.0 && rectangle.x.max >= 0.0);
+        }
+    }
+
+    #[test]
+    fn test_set_parameters() {
+        let mut qtree = quad_tree("x^2 - y^2 = a", 5);
+        let mut parameters = Parameters::new();
+
+        // Two branches of a hyperbola, crossing into one another through a
+        // saddle when a is zero
+        parameters.insert("a".to_string(), 0.5);
+        qtree.set_parameters(parameters.clone());
+        assert_eq!(qtree.get_level(), 5);
+        qtree.generate_segments();
+        let polylines = qtree.polylines();
+        assert_eq!(polylines.len(), 2);
+        for polyline in &polylines {
+            let x = polyline[0].x.signum();
+            assert!(polyline.iter().all(|p| p.x.signum() == x));
+        }
+
+        parameters.insert("a".to_string(), 0.0);
+        qtree.set_parameters(parameters);
+        qtree.generate_segments();
+        for (a, b) in &qtree.segments {
+            for c in &[qtree.crossings[a], qtree.crossings[b]] {
+                assert!((c.x.abs() - c.y.abs()).abs() < 0.05);
+            }
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use cgmath::BaseFloat;
+use num_traits::FloatConst;
+use std::fmt::{Debug, Display};
+
+/// The floating point type that cells are split and functions are evaluated in.
+///
+/// f32 is the default everywhere, and is what renderers want in the end. Past
+/// level 16, or for bounding boxes far from the origin, neighboring f32 values
+/// are too far apart to tell cells apart, so meshing in f64 keeps going where
+/// f32 breaks down. Either way the float buffers handed out are f32.
+pub trait Scalar: BaseFloat + FloatConst + Debug + Display + Default + 'static {
+    /// Constants in expressions are f32, which every scalar can hold exactly
+    fn from_f32(value: f32) -> Self;
+
+    /// Rounds to nearest
+    fn from_f64(value: f64) -> Self;
+
+    /// Rounds to nearest
+    fn as_f32(self) -> f32;
+
+    /// The next value towards positive infinity, infinity and NaN are left alone
+    fn next_up(self) -> Self;
+
+    /// The next value towards negative infinity, negative infinity and NaN are left alone
+    fn next_down(self) -> Self {
+        -(-self).next_up()
+    }
+}
+
+macro_rules! impl_scalar {
+    ($float:ident) => {
+        impl Scalar for $float {
+            fn from_f32(value: f32) -> $float {
+                value as $float
+            }
+
+            fn from_f64(value: f64) -> $float {
+                value as $float
+            }
+
+            fn as_f32(self) -> f32 {
+                self as f32
+            }
+
+            fn next_up(self) -> $float {
+                if self.is_nan() || self == $float::INFINITY {
+                    self
+                } else if self == 0.0 {
+                    $float::from_bits(1)
+                } else if self > 0.0 {
+                    $float::from_bits(self.to_bits() + 1)
+                } else {
+                    $float::from_bits(self.to_bits() - 1)
+                }
+            }
+        }
+    };
+}
+
+impl_scalar!(f32);
+impl_scalar!(f64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_up_down() {
+        assert!(Scalar::next_up(1.0f32) > 1.0);
+        assert!(Scalar::next_down(1.0f32) < 1.0);
+        assert_eq!(Scalar::next_down(Scalar::next_up(-2.5f32)), -2.5);
+        assert_eq!(Scalar::next_up(0.0f32), f32::from_bits(1));
+        assert_eq!(Scalar::next_down(0.0f32), -f32::from_bits(1));
+        assert_eq!(Scalar::next_up(f32::MAX), f32::INFINITY);
+        assert_eq!(Scalar::next_up(f32::INFINITY), f32::INFINITY);
+        assert_eq!(Scalar::next_down(-f32::INFINITY), -f32::INFINITY);
+        assert_eq!(Scalar::next_down(f32::INFINITY), f32::MAX);
+        assert!(Scalar::next_up(f32::NAN).is_nan());
+        assert!(Scalar::next_down(f32::NAN).is_nan());
+
+        assert_eq!(Scalar::next_up(1.0f64), 1.0 + f64::EPSILON);
+        assert_eq!(Scalar::next_down(0.0f64), -f64::from_bits(1));
+        assert_eq!(Scalar::next_up(f64::MAX), f64::INFINITY);
+        assert_eq!(Scalar::next_down(f64::INFINITY), f64::MAX);
+        assert!(Scalar::next_up(f64::NAN).is_nan());
+    }
+
+    #[test]
+    fn test_conversions() {
+        // Every f32 is an f64, but not the other way around
+        let tenth = f64::from_f32(0.1);
+        assert_eq!(tenth.as_f32(), 0.1f32);
+        assert_ne!(tenth, 0.1f64);
+        assert_eq!(f32::from_f64(0.1), 0.1f32);
+        assert_eq!(f32::from_f64(1e300), f32::INFINITY);
+    }
+}
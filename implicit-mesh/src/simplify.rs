@@ -0,0 +1,451 @@
+use crate::function_ir::Node;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+impl Node {
+    /// Returns an equivalent expression with constants folded, identities like
+    /// `x * 1` and `x + 0` removed, repeated factors collected into powers,
+    /// like terms collected, and the operands of commutative operations put into
+    /// a canonical order.
+    pub fn simplify(&self) -> Node {
+        simplify_node(self.map_children(|child| child.simplify()))
+    }
+
+    /// Whether the expression is defined for every value of its variables and
+    /// parameters. Rewrites that drop an operand, like `x * 0 = 0` or `x - x = 0`,
+    /// would extend the domain otherwise, so they are only made for these.
+    pub fn is_total(&self) -> bool {
+        let defined = match *self {
+            Node::Div(_, ref n2) => matches!(**n2, Node::Constant(c) if c != 0.0),
+            Node::Exp(ref n1, ref n2) => match (&**n1, &**n2) {
+                (_, Node::Constant(c)) => c.fract() == 0.0 && *c >= 0.0,
+                (Node::Constant(c), _) => *c > 0.0,
+                _ => false,
+            },
+            Node::Tan(_) | Node::Ln(_) | Node::Sqrt(_) => false,
+            _ => true,
+        };
+        defined && self.children().into_iter().all(Node::is_total)
+    }
+}
+
+fn simplify_node(node: Node) -> Node {
+    if let Some(c) = fold_constant(&node) {
+        return Node::Constant(c);
+    }
+
+    match node {
+        Node::Add(..) => simplify_sum(node),
+        Node::Mul(..) => simplify_product(node),
+        Node::Sub(n1, n2) => simplify_difference(*n1, *n2),
+        Node::Div(n1, n2) => simplify_quotient(*n1, *n2),
+        Node::Exp(n1, n2) => simplify_power(*n1, *n2),
+        Node::Min(n1, n2) => {
+            if n1 == n2 {
+                *n1
+            } else if compare(&n1, &n2) == Ordering::Greater {
+                Node::Min(n2, n1)
+            } else {
+                Node::Min(n1, n2)
+            }
+        }
+        Node::Max(n1, n2) => {
+            if n1 == n2 {
+                *n1
+            } else if compare(&n1, &n2) == Ordering::Greater {
+                Node::Max(n2, n1)
+            } else {
+                Node::Max(n1, n2)
+            }
+        }
+        _ => node,
+    }
+}
+
+/// If every child is a constant, evaluate the node. Results outside of
+/// the domain are left alone so that evaluation still sees them.
+fn fold_constant(node: &Node) -> Option<f32> {
+    let children = node.children();
+    if children.is_empty() {
+        return None;
+    }
+
+    for child in children {
+        if let Node::Constant(_) = *child {
+        } else {
+            return None;
+        }
+    }
+
+    let value: f32 = node.evaluate(&HashMap::new());
+    if value.is_finite() {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// Whether `b^m * b^n = b^(m + n)` holds wherever either side is defined,
+/// which isn't the case for i.e. `x^0.5 * x^0.5` at negative x or `x^2 * x^-1` at zero
+fn can_add_exponents(m: f32, n: f32) -> bool {
+    m.fract() == 0.0 && n.fract() == 0.0 && (m > 0.0) == (n > 0.0)
+}
+
+fn is_constant(node: &Node, value: f32) -> bool {
+    *node == Node::Constant(value)
+}
+
+fn flatten_sum(node: Node, terms: &mut Vec<Node>) {
+    match node {
+        Node::Add(n1, n2) => {
+            flatten_sum(*n1, terms);
+            flatten_sum(*n2, terms);
+        }
+        _ => terms.push(node),
+    }
+}
+
+fn flatten_product(node: Node, factors: &mut Vec<Node>) {
+    match node {
+        Node::Mul(n1, n2) => {
+            flatten_product(*n1, factors);
+            flatten_product(*n2, factors);
+        }
+        _ => factors.push(node),
+    }
+}
+
+/// Split a term into its constant coefficient and the rest
+fn split_coefficient(node: Node) -> (f32, Node) {
+    match node {
+        Node::Mul(n1, n2) => match *n1 {
+            Node::Constant(c) => (c, *n2),
+            n1 => (1.0, Node::Mul(Box::new(n1), n2)),
+        },
+        _ => (1.0, node),
+    }
+}
+
+/// Split a factor into its base and constant exponent
+fn split_exponent(node: Node) -> (Node, f32) {
+    match node {
+        Node::Exp(n1, n2) => match *n2 {
+            Node::Constant(c) => (*n1, c),
+            n2 => (Node::Exp(n1, Box::new(n2)), 1.0),
+        },
+        _ => (node, 1.0),
+    }
+}
+
+fn simplify_sum(node: Node) -> Node {
+    let mut terms = Vec::new();
+    flatten_sum(node, &mut terms);
+
+    let mut constant = 0.0;
+    let mut like_terms: Vec<(f32, Node)> = Vec::new();
+    for term in terms {
+        if let Node::Constant(c) = term {
+            constant += c;
+            continue;
+        }
+
+        let (coefficient, base) = split_coefficient(term);
+        match like_terms.iter_mut().find(|(_, b)| *b == base) {
+            Some(like_term) => like_term.0 += coefficient,
+            None => like_terms.push((coefficient, base)),
+        }
+    }
+
+    let mut terms: Vec<Node> = like_terms
+        .into_iter()
+        .filter(|(coefficient, base)| *coefficient != 0.0 || !base.is_total())
+        .map(|(coefficient, base)| {
+            if coefficient == 1.0 {
+                base
+            } else {
+                simplify_product(Node::Mul(
+                    Box::new(Node::Constant(coefficient)),
+                    Box::new(base),
+                ))
+            }
+        })
+        .collect();
+    terms.sort_by(compare_terms);
+
+    if constant != 0.0 || terms.is_empty() {
+        terms.push(Node::Constant(constant));
+    }
+
+    rebuild(terms, Node::Add)
+}
+
+fn simplify_product(node: Node) -> Node {
+    let mut factors = Vec::new();
+    flatten_product(node, &mut factors);
+
+    let mut constant = 1.0;
+    let mut powers: Vec<(Node, f32)> = Vec::new();
+    for factor in factors {
+        if let Node::Constant(c) = factor {
+            constant *= c;
+            continue;
+        }
+
+        let (base, exponent) = split_exponent(factor);
+        match powers
+            .iter_mut()
+            .find(|(b, e)| *b == base && can_add_exponents(*e, exponent))
+        {
+            Some(power) => power.1 += exponent,
+            None => powers.push((base, exponent)),
+        }
+    }
+
+    if constant == 0.0 && powers.iter().all(|(base, _)| base.is_total()) {
+        return Node::Constant(0.0);
+    }
+
+    let mut factors: Vec<Node> = powers
+        .into_iter()
+        .filter(|(_, exponent)| *exponent != 0.0)
+        .map(|(base, exponent)| {
+            if exponent == 1.0 {
+                base
+            } else {
+                Node::Exp(Box::new(base), Box::new(Node::Constant(exponent)))
+            }
+        })
+        .collect();
+    factors.sort_by(compare_terms);
+
+    if constant != 1.0 || factors.is_empty() {
+        factors.insert(0, Node::Constant(constant));
+    }
+
+    rebuild(factors, Node::Mul)
+}
+
+fn simplify_difference(n1: Node, n2: Node) -> Node {
+    if is_constant(&n2, 0.0) {
+        n1
+    } else if n1 == n2 && n1.is_total() {
+        Node::Constant(0.0)
+    } else if is_constant(&n1, 0.0) {
+        simplify_product(Node::Mul(Box::new(Node::Constant(-1.0)), Box::new(n2)))
+    } else {
+        Node::Sub(Box::new(n1), Box::new(n2))
+    }
+}
+
+fn simplify_quotient(n1: Node, n2: Node) -> Node {
+    match n2 {
+        _ if is_constant(&n2, 1.0) => n1,
+        // Multiplying by the reciprocal is cheaper and lets the constant merge
+        Node::Constant(c) => {
+            simplify_product(Node::Mul(Box::new(Node::Constant(1.0 / c)), Box::new(n1)))
+        }
+        _ => Node::Div(Box::new(n1), Box::new(n2)),
+    }
+}
+
+fn simplify_power(n1: Node, n2: Node) -> Node {
+    if is_constant(&n2, 1.0) {
+        return n1;
+    }
+
+    if (is_constant(&n2, 0.0) && n1.is_total()) || is_constant(&n1, 1.0) {
+        return Node::Constant(1.0);
+    }
+
+    // (b^m)^n = b^(m * n) only holds in general for integer n, and for integer m
+    // too if it isn't to extend the domain, as `(x^0.5)^2 = x` would
+    match (n1, n2) {
+        (Node::Exp(base, m), Node::Constant(n)) if n.fract() == 0.0 => match *m {
+            Node::Constant(m) if m.fract() == 0.0 => simplify_power(*base, Node::Constant(m * n)),
+            m => Node::Exp(
+                Box::new(Node::Exp(base, Box::new(m))),
+                Box::new(Node::Constant(n)),
+            ),
+        },
+        (n1, n2) => Node::Exp(Box::new(n1), Box::new(n2)),
+    }
+}
+
+fn rebuild<F>(nodes: Vec<Node>, op: F) -> Node
+where
+    F: Fn(Box<Node>, Box<Node>) -> Node,
+{
+    let mut nodes = nodes.into_iter();
+    let first = nodes.next().unwrap();
+    nodes.fold(first, |acc, node| op(Box::new(acc), Box::new(node)))
+}
+
+fn rank(node: &Node) -> u8 {
+    match *node {
+        Node::Constant(_) => 0,
+        Node::Variable(_) => 1,
+        Node::Parameter(_) => 2,
+        Node::Exp(..) => 3,
+        Node::Mul(..) => 4,
+        Node::Div(..) => 5,
+        Node::Add(..) => 6,
+        Node::Sub(..) => 7,
+        Node::Sin(_) => 8,
+        Node::Cos(_) => 9,
+        Node::Tan(_) => 10,
+        Node::Exponential(_) => 11,
+        Node::Ln(_) => 12,
+        Node::Sqrt(_) => 13,
+        Node::Abs(_) => 14,
+        Node::Min(..) => 15,
+        Node::Max(..) => 16,
+        Node::Atan2(..) => 17,
+    }
+}
+
+/// The part of a term or factor that decides where it sorts,
+/// so that `2 * x` sorts with `x` and `x ^ 2` sorts with `x`
+fn sort_base(node: &Node) -> &Node {
+    match *node {
+        Node::Mul(ref n1, ref n2) => match **n1 {
+            Node::Constant(_) => n2,
+            _ => node,
+        },
+        Node::Exp(ref n1, ref n2) => match **n2 {
+            Node::Constant(_) => n1,
+            _ => node,
+        },
+        _ => node,
+    }
+}
+
+fn compare_terms(n1: &Node, n2: &Node) -> Ordering {
+    match compare(sort_base(n1), sort_base(n2)) {
+        Ordering::Equal => compare(n1, n2),
+        ordering => ordering,
+    }
+}
+
+/// A total order on expressions, used to canonicalise commutative operations
+fn compare(n1: &Node, n2: &Node) -> Ordering {
+    let by_rank = rank(n1).cmp(&rank(n2));
+    if by_rank != Ordering::Equal {
+        return by_rank;
+    }
+
+    match (n1, n2) {
+        (Node::Constant(c1), Node::Constant(c2)) => c1.partial_cmp(c2).unwrap_or(Ordering::Equal),
+        (Node::Variable(v1), Node::Variable(v2)) => v1.cmp(v2),
+        (Node::Parameter(p1), Node::Parameter(p2)) => p1.cmp(p2),
+        // Powers of the same variable sort next to each other by base
+        _ => n1
+            .children()
+            .iter()
+            .zip(n2.children().iter())
+            .map(|(c1, c2)| compare(c1, c2))
+            .find(|ordering| *ordering != Ordering::Equal)
+            .unwrap_or(Ordering::Equal),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::function::Function;
+    use crate::parser::parse_expression;
+
+    fn simplified(expression: &str) -> String {
+        let input: Vec<char> = expression.chars().collect();
+        let root = parse_expression(&input, 0).unwrap();
+        format!("{}", root.simplify())
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        assert_eq!(simplified("3.2 ^ (0.01 / 8) + (4.0 * 3 + 2)"), "15.001455");
+        assert_eq!(simplified("2 * 3 * x"), "6 * x");
+        assert_eq!(simplified("2 * (x * 3)"), "6 * x");
+        assert_eq!(simplified("(x + 2) + 3"), "x + 5");
+        assert_eq!(simplified("x / 2"), "0.5 * x");
+        assert_eq!(simplified("sin(0) + cos(0)"), "1");
+
+        // Don't fold values outside of the domain
+        assert_eq!(simplified("ln(-1)"), "ln(-1)");
+    }
+
+    #[test]
+    fn test_identities() {
+        assert_eq!(simplified("x * 1"), "x");
+        assert_eq!(simplified("1 * x"), "x");
+        assert_eq!(simplified("x + 0"), "x");
+        assert_eq!(simplified("0 + x"), "x");
+        assert_eq!(simplified("x - 0"), "x");
+        assert_eq!(simplified("x ^ 1"), "x");
+        assert_eq!(simplified("x ^ 0"), "1");
+        assert_eq!(simplified("x * 0 + y"), "y");
+        assert_eq!(simplified("x - x"), "0");
+        assert_eq!(simplified("min(x, x)"), "x");
+    }
+
+    #[test]
+    fn test_domain() {
+        // Operands are only dropped where they are defined everywhere
+        assert_eq!(simplified("sqrt(x) * 0"), "0 * sqrt(x)");
+        assert_eq!(simplified("ln(x) - ln(x)"), "ln(x) - ln(x)");
+        assert_eq!(simplified("x / x"), "x / x");
+        assert_eq!(simplified("0 / x"), "0 / x");
+        assert_eq!(simplified("x^0.5 * x^0.5"), "x^0.5 * x^0.5");
+        assert_eq!(simplified("x^2 * x^-1"), "x^-1 * x^2");
+        assert_eq!(simplified("x^-1 * x^-2"), "x^-3");
+        assert_eq!(
+            simplified("2 * sqrt(y) + x + -2 * sqrt(y)"),
+            "x + 0 * sqrt(y)"
+        );
+        assert_eq!(simplified("(x + y^2) * 0"), "0");
+        assert_eq!(simplified("(x^0.5)^2"), "(x^0.5)^2");
+        assert_eq!(simplified("ln(x)^0"), "ln(x)^0");
+        assert_eq!(simplified("sqrt(x)^0"), "sqrt(x)^0");
+        assert_eq!(simplified("(x + y)^0"), "1");
+        assert_eq!(simplified("(x^-1)^2"), "x^-2");
+    }
+
+    #[test]
+    fn test_powers() {
+        assert_eq!(simplified("x * x"), "x^2");
+        assert_eq!(simplified("x * y * x"), "x^2 * y");
+        assert_eq!(simplified("x * x ^ 2 * 3"), "3 * x^3");
+        assert_eq!(simplified("(x ^ 2) ^ 3"), "x^6");
+        assert_eq!(simplified("(x^2)^0.5"), "(x^2)^0.5");
+        assert_eq!(simplified("sin(x) * sin(x)"), "sin(x)^2");
+    }
+
+    #[test]
+    fn test_canonical_order() {
+        assert_eq!(simplified("y + x"), "x + y");
+        assert_eq!(simplified("z * 2 * y"), "2 * y * z");
+        assert_eq!(simplified("x + y + x"), "2 * x + y");
+        assert_eq!(simplified("max(z, x)"), "max(x, z)");
+        assert_eq!(simplified("x^2 + z + y^2"), "x^2 + y^2 + z");
+    }
+
+    #[test]
+    fn test_simplify_preserves_value() {
+        let expressions = [
+            "x + y - (z / x) - y + z",
+            "3.2 ^ (0.01 / 8) + (4.0 * 3 + 2 - 3^7 - (4)) / z ^ 2",
+            "x * x * y + 2 * y * x * x - sin(z) * sin(z) * 4 / 2",
+            "(x + 1) * (x + 1) - x * 0 + max(y, 1 * y)",
+        ];
+
+        for expression in expressions.iter() {
+            let input: Vec<char> = expression.chars().collect();
+            let root = parse_expression(&input, 0).unwrap();
+            let simple = root.simplify();
+
+            for &(x, y, z) in &[(1.13f32, 4.232, 2.0939), (-0.5, 0.25, 3.0)] {
+                let expected = Function::evaluate(&*root, x, y, z);
+                let result = Function::evaluate(&simple, x, y, z);
+                assert_similiar!(result, expected);
+            }
+        }
+    }
+}
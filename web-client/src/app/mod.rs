@@ -1,21 +1,57 @@
 use crate::render::WebRendererWrapper;
 use camera::{ButtonState, Camera, MouseButton};
 use cgmath::InnerSpace;
+use implicit_mesh::bytecode::CompiledFunction;
 use implicit_mesh::cell_keys::morton_keys::MortonKey;
+use implicit_mesh::cell_keys::quad_keys::QuadKey;
+use implicit_mesh::display::{constraint_to_latex, constraint_to_string};
+use implicit_mesh::function::{Parameters, Relation};
 use implicit_mesh::function_ir::Node;
 use implicit_mesh::interval::Interval;
 use implicit_mesh::mesh_tree::*;
+use implicit_mesh::parametric::{self, Parametric};
+use implicit_mesh::parser;
+use implicit_mesh::quad_tree::{BoundingRectangle, QuadTree};
 use std::cell::RefCell;
 use std::rc::Rc;
 use web_sys::console::log_1;
 
 pub type AppWrapper = Rc<RefCell<App>>;
 
+/// Steps to start tessellating a parametric surface at, each level doubles them
+const PARAMETRIC_START_STEPS: usize = 8;
+
+/// Each level quadruples the triangles that are tessellated and uploaded again on
+/// every parameter change, past this that costs more frame time than the detail is
+/// worth. The grid lines of 128 steps would also overflow the u16 edge indices.
+const PARAMETRIC_MAX_STEPS: usize = 64;
+
 pub struct App {
     equation: String,
     pub camera: Camera,
     renderer: Option<WebRendererWrapper>,
-    mtree: Option<MeshTree<MortonKey, Node>>,
+    mtree: Option<MeshTree<MortonKey, CompiledFunction>>,
+    /// Set instead of mtree when the equation is a parametric surface or curve,
+    /// along with the number of steps it's tessellated at
+    parametric: Option<(Parametric, usize)>,
+    /// Set instead of mtree when plotting a single equation in the plane z = 0
+    qtree: Option<QuadTree<QuadKey, CompiledFunction>>,
+    flat: bool,
+    /// Whether r, theta, phi and rho are spherical and cylindrical coordinates
+    coordinates: bool,
+    constraints: Vec<(Box<Node>, Relation)>,
+    parse_errors: Option<String>,
+    parameters: Parameters,
+}
+
+fn generate_mesh(mtree: &mut MeshTree<MortonKey, CompiledFunction>) {
+    if mtree.is_curve() {
+        mtree.generate_vertex_map();
+        mtree.generate_curve_set();
+    } else {
+        mtree.generate_vertex_map();
+        mtree.generate_quad_set();
+    }
 }
 
 impl App {
@@ -25,9 +61,56 @@ impl App {
             camera: Camera::new(),
             renderer: None,
             mtree: None,
+            parametric: None,
+            qtree: None,
+            flat: crate::FLAT_START,
+            coordinates: crate::COORDINATES_START,
+            constraints: Vec::new(),
+            parse_errors: None,
+            parameters: Parameters::new(),
         }))
     }
 
+    /// Parameters of the current equation, sorted by name
+    pub fn get_parameters(&self) -> Vec<(String, f32)> {
+        let mut result: Vec<(String, f32)> = self
+            .parameters
+            .iter()
+            .map(|(name, value)| (name.clone(), *value))
+            .collect();
+        result.sort_by(|a, b| a.0.cmp(&b.0));
+        result
+    }
+
+    /// The current equation as the parser understood it, as plain text and as LaTeX
+    pub fn get_interpreted_equation(&self) -> (String, String) {
+        let text: Vec<String> = self
+            .constraints
+            .iter()
+            .map(|(f, relation)| constraint_to_string(f, *relation))
+            .collect();
+        let latex: Vec<String> = self
+            .constraints
+            .iter()
+            .map(|(f, relation)| constraint_to_latex(f, *relation))
+            .collect();
+        (text.join(", "), latex.join(", \\quad "))
+    }
+
+    /// Whether the current equation holds everywhere or nowhere in the bounding box
+    pub fn get_degeneracy(&self) -> Option<Degeneracy> {
+        match (&self.mtree, &self.qtree) {
+            (Some(mtree), _) => mtree.degeneracy(),
+            (None, Some(qtree)) => qtree.degeneracy(),
+            (None, None) => None,
+        }
+    }
+
+    /// Errors from the last equation entered, with the equation and a caret under each problem
+    pub fn get_parse_errors(&self) -> Option<String> {
+        self.parse_errors.clone()
+    }
+
     pub fn set_renderer(&mut self, renderer: WebRendererWrapper) {
         self.renderer = Some(renderer);
     }
@@ -39,6 +122,86 @@ impl App {
                 .set_plot(&mtree)
                 .expect("Unable to set_plot for renderer");
         }
+        if let (Some(qtree), Some(renderer)) = (&self.qtree, &self.renderer) {
+            renderer
+                .borrow_mut()
+                .set_quad_tree(&qtree)
+                .expect("Unable to set_quad_tree for renderer");
+        }
+        if let (Some((surface, steps)), Some(renderer)) = (&self.parametric, &self.renderer) {
+            let geometry = surface.tessellate(&self.parameters, *steps);
+            renderer
+                .borrow_mut()
+                .set_geometry(&geometry)
+                .expect("Unable to set_geometry for renderer");
+        }
+    }
+
+    /// Drop whatever is plotted, so an equation that can't be plotted doesn't
+    /// leave the previous one on screen
+    fn clear_plot(&mut self) {
+        self.mtree = None;
+        self.parametric = None;
+        self.qtree = None;
+
+        match &mut self.renderer {
+            Some(renderer) => renderer.borrow_mut().clear_plot(),
+            None => (),
+        }
+    }
+
+    fn enter_parametric(&mut self, constraints: Vec<(Box<Node>, Relation)>) {
+        let surface = match Parametric::from_constraints(&constraints) {
+            Ok(surface) => surface,
+            Err(err) => {
+                log_1(&format!("App: unable to plot parametric equation: {}", err).into());
+                self.parse_errors = Some(format!("{}", err));
+                self.clear_plot();
+                return;
+            }
+        };
+
+        // Keep values for parameters that are still around
+        let mut parameters = Parameters::new();
+        for name in surface.parameters() {
+            let value = self
+                .parameters
+                .get(&name)
+                .cloned()
+                .unwrap_or(crate::PARAMETER_START_VALUE);
+            parameters.insert(name, value);
+        }
+        self.parameters = parameters;
+        self.constraints = constraints;
+        self.mtree = None;
+        self.qtree = None;
+        self.parametric = Some((surface, PARAMETRIC_START_STEPS));
+        self.update_plot();
+    }
+
+    fn enter_flat(&mut self, constraints: Vec<(Box<Node>, Relation)>) {
+        let f = match constraints.as_slice() {
+            [(f, Relation::Equal)] => CompiledFunction::compile(&f.simplify()),
+            _ => {
+                self.parse_errors = Some("Flat plots take a single equation".to_string());
+                self.clear_plot();
+                return;
+            }
+        };
+
+        let size_interval = Interval::new(-40.0 / 2.0, 40.0 / 2.0);
+        let rectangle = BoundingRectangle {
+            x: size_interval,
+            y: size_interval,
+        };
+        let mut qtree = QuadTree::new(Box::new(f), rectangle);
+        qtree.set_parameters(self.parameters.clone());
+        qtree.generate_segments();
+
+        self.constraints = constraints;
+        self.mtree = None;
+        self.qtree = Some(qtree);
+        self.update_plot();
     }
 
     pub fn handle_message(&mut self, message: &Message) {
@@ -63,7 +226,48 @@ impl App {
                 self.equation += equation;
                 let input: Vec<char> = equation.chars().collect();
 
-                let a = implicit_mesh::parser::parse_expression(&input, 0).expect("not parseable");
+                let parsed = match (parser::is_latex(&input), self.coordinates) {
+                    (false, false) => parser::parse_program(&input, 0),
+                    (true, false) => parser::parse_latex_program(&input, 0),
+                    (false, true) => parser::parse_program_with_coordinates(&input, 0),
+                    (true, true) => parser::parse_latex_program_with_coordinates(&input, 0),
+                };
+                let constraints = match parsed {
+                    Ok(program) => program.expand(),
+                    Err(errors) => {
+                        log_1(&format!("App: unable to parse equation: {}", errors).into());
+                        self.parse_errors = Some(errors.render(&input));
+                        self.clear_plot();
+                        return;
+                    }
+                };
+                self.parse_errors = None;
+
+                if parametric::is_parametric(&constraints) {
+                    self.enter_parametric(constraints);
+                    return;
+                }
+                self.parametric = None;
+                self.qtree = None;
+
+                // Keep values for parameters that are still around
+                let mut parameters = Parameters::new();
+                for (f, _) in &constraints {
+                    for name in f.parameters() {
+                        let value = self
+                            .parameters
+                            .get(&name)
+                            .cloned()
+                            .unwrap_or(crate::PARAMETER_START_VALUE);
+                        parameters.insert(name, value);
+                    }
+                }
+                self.parameters = parameters;
+                if self.flat {
+                    self.enter_flat(constraints);
+                    return;
+                }
+                self.constraints = constraints.clone();
 
                 let size_interval = Interval::new(-40.0 / 2.0, 40.0 / 2.0);
                 let bounding_box = BoundingBox {
@@ -72,7 +276,15 @@ impl App {
                     z: size_interval.clone(),
                 };
 
-                self.mtree = Some(MeshTree::new(a, bounding_box));
+                let constraints = constraints
+                    .into_iter()
+                    .map(|(f, relation)| {
+                        (Box::new(CompiledFunction::compile(&f.simplify())), relation)
+                    })
+                    .collect();
+                let mut mtree = MeshTree::new_system(constraints, bounding_box);
+                mtree.set_parameters(self.parameters.clone());
+                self.mtree = Some(mtree);
 
                 self.update_plot();
                 if let Some(mtree) = &self.mtree {
@@ -86,14 +298,30 @@ impl App {
                     );
                 }
             }
+            Message::SetParameter(name, value) => {
+                self.parameters.insert(name.clone(), *value);
+                match &mut self.mtree {
+                    Some(mtree) => {
+                        mtree.set_parameters(self.parameters.clone());
+                        generate_mesh(mtree);
+                    }
+                    None => {
+                        if let Some(qtree) = &mut self.qtree {
+                            qtree.set_parameters(self.parameters.clone());
+                            qtree.generate_segments();
+                        } else if self.parametric.is_none() {
+                            return;
+                        }
+                    }
+                };
+                self.update_plot();
+            }
             Message::Clear => {
                 self.equation.clear();
-                self.mtree = None;
-
-                match &mut self.renderer {
-                    Some(renderer) => renderer.borrow_mut().clear_plot(),
-                    None => (),
-                }
+                self.constraints.clear();
+                self.parse_errors = None;
+                self.parameters.clear();
+                self.clear_plot();
             }
             Message::Update(time_delta, window_width, window_height) => {
                 self.camera
@@ -103,9 +331,7 @@ impl App {
                 match &mut self.mtree {
                     Some(mtree) => {
                         mtree.next_level();
-                        mtree.generate_edge_set();
-                        mtree.generate_vertex_map();
-                        mtree.generate_triangle_set();
+                        generate_mesh(mtree);
 
                         log_1(
                             &format!(
@@ -116,10 +342,28 @@ impl App {
                             .into(),
                         );
                     }
-                    None => {
-                        log_1(&"App: no mtree to next level".into());
-                        return;
-                    }
+                    None => match (&mut self.qtree, &mut self.parametric) {
+                        (Some(qtree), _) => {
+                            qtree.next_level();
+                            qtree.generate_segments();
+                            log_1(
+                                &format!(
+                                    "App: level: {}, solution cell count: {}",
+                                    qtree.get_level(),
+                                    qtree.get_solution_cell_count()
+                                )
+                                .into(),
+                            );
+                        }
+                        (None, Some((_, steps))) => {
+                            *steps = (*steps * 2).min(PARAMETRIC_MAX_STEPS);
+                            log_1(&format!("App: parametric steps: {}", steps).into());
+                        }
+                        (None, None) => {
+                            log_1(&"App: no mtree to next level".into());
+                            return;
+                        }
+                    },
                 };
                 self.update_plot();
             }
@@ -152,6 +396,18 @@ impl App {
                 }
                 self.update_plot();
             }
+            Message::DrawInterior(draw_flag) => {
+                if let Some(renderer) = &mut self.renderer {
+                    renderer.borrow_mut().set_draw_interior(*draw_flag);
+                }
+                self.update_plot();
+            }
+            Message::DrawSurface(draw_flag) => {
+                if let Some(renderer) = &mut self.renderer {
+                    renderer.borrow_mut().set_draw_surface(*draw_flag);
+                }
+                self.update_plot();
+            }
             Message::DrawGnomonCenter(draw_flag) => {
                 if let Some(renderer) = &mut self.renderer {
                     renderer.borrow_mut().set_draw_gnomon_center(*draw_flag);
@@ -179,6 +435,17 @@ impl App {
             Message::SetFov(fov) => {
                 self.camera.set_field_of_view(*fov);
             }
+            Message::SetFlat(flat) => {
+                self.flat = *flat;
+                self.camera.set_flat(*flat);
+                let equation = self.equation.clone();
+                self.handle_message(&Message::EnterEquation(equation));
+            }
+            Message::SetCoordinates(coordinates) => {
+                self.coordinates = *coordinates;
+                let equation = self.equation.clone();
+                self.handle_message(&Message::EnterEquation(equation));
+            }
         }
     }
 }
@@ -189,6 +456,7 @@ pub enum Message {
     MouseMove(i32, i32),
     Zoom(f32),
     EnterEquation(String),
+    SetParameter(String, f32),
     Update(f32, f32, f32),
     NextLevel,
     Relax,
@@ -196,9 +464,13 @@ pub enum Message {
     DrawBoundingBoxes(bool),
     DrawVertices(bool),
     DrawEdges(bool),
+    DrawInterior(bool),
+    DrawSurface(bool),
     DrawGnomonCenter(bool),
     DrawGnomonCorner(bool),
     DefaultCam,
     Debug,
     SetFov(f32),
+    SetFlat(bool),
+    SetCoordinates(bool),
 }
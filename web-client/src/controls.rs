@@ -1,5 +1,6 @@
 use crate::app::{AppWrapper, Message};
 use crate::APP_DIV_ID;
+use implicit_mesh::mesh_tree::Degeneracy;
 use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
@@ -10,6 +11,16 @@ use web_sys::Element;
 use web_sys::HtmlElement;
 use web_sys::HtmlInputElement;
 
+const PARAMETER_SLIDERS_ID: &str = "tacit_parameter_sliders";
+const INTERPRETED_EQUATION_ID: &str = "tacit_interpreted_equation";
+
+#[wasm_bindgen]
+extern "C" {
+    /// Provided by KaTeX when the page loads it
+    #[wasm_bindgen(catch, js_namespace = katex, js_name = render)]
+    fn katex_render(latex: &str, element: &Element) -> Result<(), JsValue>;
+}
+
 pub fn append_controls(app: AppWrapper) -> Result<(), JsValue> {
     let window = window().unwrap();
     let document = window.document().unwrap();
@@ -26,9 +37,16 @@ pub fn append_controls(app: AppWrapper) -> Result<(), JsValue> {
     let controls: Element = controls.dyn_into()?;
 
     {
-        let app = Rc::clone(&app);
-        let text_input = create_text_input(app)?;
+        let text_input = create_text_input(Rc::clone(&app))?;
         controls.append_child(&text_input)?;
+        update_interpreted_equation(Rc::clone(&app))?;
+    }
+
+    {
+        let parameter_sliders = document.create_element("div")?;
+        parameter_sliders.set_id(PARAMETER_SLIDERS_ID);
+        controls.append_child(&parameter_sliders)?;
+        update_parameter_sliders(Rc::clone(&app))?;
     }
 
     {
@@ -67,6 +85,18 @@ pub fn append_controls(app: AppWrapper) -> Result<(), JsValue> {
         controls.append_child(&element)?;
     }
 
+    {
+        let app = Rc::clone(&app);
+        let element = create_draw_interior_checkbox(app)?;
+        controls.append_child(&element)?;
+    }
+
+    {
+        let app = Rc::clone(&app);
+        let element = create_draw_surface_checkbox(app)?;
+        controls.append_child(&element)?;
+    }
+
     {
         let app = Rc::clone(&app);
         let element = create_draw_gnomon_center_checkbox(app)?;
@@ -79,6 +109,18 @@ pub fn append_controls(app: AppWrapper) -> Result<(), JsValue> {
         controls.append_child(&element)?;
     }
 
+    {
+        let app = Rc::clone(&app);
+        let element = create_flat_checkbox(app)?;
+        controls.append_child(&element)?;
+    }
+
+    {
+        let app = Rc::clone(&app);
+        let element = create_coordinates_checkbox(app)?;
+        controls.append_child(&element)?;
+    }
+
     {
         let app = Rc::clone(&app);
         let element = create_default_cam_button(app)?;
@@ -116,6 +158,8 @@ fn create_text_input(app: AppWrapper) -> Result<HtmlElement, JsValue> {
         log_1(&format!("Text: {}", text).into());
         app.borrow_mut()
             .handle_message(&Message::EnterEquation(text));
+        update_parameter_sliders(Rc::clone(&app)).expect("update_parameter_sliders");
+        update_interpreted_equation(Rc::clone(&app)).expect("update_interpreted_equation");
     };
     let closure = Closure::wrap(Box::new(handler) as Box<FnMut(_)>);
 
@@ -145,9 +189,98 @@ fn create_text_input(app: AppWrapper) -> Result<HtmlElement, JsValue> {
     closure.forget();
     log_1(&"appended closure".into());
 
+    let interpreted_equation: HtmlElement = document.create_element("span")?.dyn_into()?;
+    interpreted_equation.set_id(INTERPRETED_EQUATION_ID);
+    interpreted_equation
+        .style()
+        .set_property("padding-left", "10px")?;
+    text_input.append_child(&interpreted_equation)?;
+
     Ok(text_input)
 }
 
+/// Show the current equation the way the parser read it, or why it couldn't, next to the
+/// text box. Falls back to plain text if KaTeX isn't available.
+fn update_interpreted_equation(app: AppWrapper) -> Result<(), JsValue> {
+    let window = window().unwrap();
+    let document = window.document().unwrap();
+
+    let element: HtmlElement = match document.get_element_by_id(INTERPRETED_EQUATION_ID) {
+        Some(element) => element.dyn_into()?,
+        None => return Ok(()),
+    };
+
+    // Parse errors replace the last good equation until they're fixed
+    if let Some(errors) = app.borrow().get_parse_errors() {
+        element.style().set_property("white-space", "pre")?;
+        element.style().set_property("font-family", "monospace")?;
+        element.style().set_property("color", "red")?;
+        element.set_text_content(Some(&errors));
+        return Ok(());
+    }
+    element.style().remove_property("white-space")?;
+    element.style().remove_property("font-family")?;
+    element.style().remove_property("color")?;
+
+    let (text, latex) = app.borrow().get_interpreted_equation();
+    if katex_render(&latex, &element).is_err() {
+        element.set_text_content(Some(&text));
+    }
+
+    let note = match app.borrow().get_degeneracy() {
+        Some(Degeneracy::Everywhere) => Some("holds everywhere"),
+        Some(Degeneracy::Nowhere) => Some("no solution in the bounding box"),
+        None => None,
+    };
+    if let Some(note) = note {
+        let span = document.create_element("span")?;
+        span.set_text_content(Some(&format!(" ({})", note)));
+        element.append_child(&span)?;
+    }
+
+    Ok(())
+}
+
+/// Replace the parameter sliders with one for each parameter in the current equation
+fn update_parameter_sliders(app: AppWrapper) -> Result<(), JsValue> {
+    let window = window().unwrap();
+    let document = window.document().unwrap();
+
+    let parameter_sliders = match document.get_element_by_id(PARAMETER_SLIDERS_ID) {
+        Some(element) => element,
+        None => return Ok(()),
+    };
+    parameter_sliders.set_inner_html("");
+
+    let parameters = app.borrow().get_parameters();
+    for (name, value) in parameters {
+        let app = Rc::clone(&app);
+        let parameter_name = name.clone();
+        let handler = move |event: web_sys::Event| {
+            let input_elem: HtmlInputElement = event.target().unwrap().dyn_into().unwrap();
+            let value = input_elem.value().parse().unwrap();
+
+            app.borrow_mut()
+                .handle_message(&Message::SetParameter(parameter_name.clone(), value));
+            update_interpreted_equation(Rc::clone(&app)).expect("update_interpreted_equation");
+        };
+        let closure = Closure::wrap(Box::new(handler) as Box<FnMut(_)>);
+
+        let slider = Slider {
+            min: crate::PARAMETER_MIN,
+            max: crate::PARAMETER_MAX,
+            step: crate::PARAMETER_STEP,
+            start: value,
+            label: &name,
+            closure,
+        }
+        .create_element()?;
+        parameter_sliders.append_child(&slider)?;
+    }
+
+    Ok(())
+}
+
 fn create_default_cam_button(app: AppWrapper) -> Result<HtmlElement, JsValue> {
     let handler = move |_event: web_sys::Event| {
         app.borrow_mut().handle_message(&Message::DefaultCam);
@@ -342,6 +475,87 @@ fn create_draw_edges_checkbox(app: AppWrapper) -> Result<HtmlElement, JsValue> {
     Ok(draw_control)
 }
 
+fn create_draw_interior_checkbox(app: AppWrapper) -> Result<HtmlElement, JsValue> {
+    let handler = move |event: web_sys::Event| {
+        let input_elem: HtmlInputElement = event.target().unwrap().dyn_into().unwrap();
+        let draw_flag = input_elem.checked();
+
+        app.borrow_mut()
+            .handle_message(&Message::DrawInterior(draw_flag));
+    };
+    let closure = Closure::wrap(Box::new(handler) as Box<FnMut(_)>);
+
+    let draw_control = Checkbox {
+        start_checked: crate::DRAW_INTERIOR_START,
+        label: "Draw Interior",
+        closure,
+    }
+    .create_element()?;
+
+    Ok(draw_control)
+}
+
+fn create_draw_surface_checkbox(app: AppWrapper) -> Result<HtmlElement, JsValue> {
+    let handler = move |event: web_sys::Event| {
+        let input_elem: HtmlInputElement = event.target().unwrap().dyn_into().unwrap();
+        let draw_flag = input_elem.checked();
+
+        app.borrow_mut()
+            .handle_message(&Message::DrawSurface(draw_flag));
+    };
+    let closure = Closure::wrap(Box::new(handler) as Box<FnMut(_)>);
+
+    let draw_control = Checkbox {
+        start_checked: crate::DRAW_SURFACE_START,
+        label: "Draw Surface",
+        closure,
+    }
+    .create_element()?;
+
+    Ok(draw_control)
+}
+
+fn create_flat_checkbox(app: AppWrapper) -> Result<HtmlElement, JsValue> {
+    let handler = move |event: web_sys::Event| {
+        let input_elem: HtmlInputElement = event.target().unwrap().dyn_into().unwrap();
+        let flat = input_elem.checked();
+
+        app.borrow_mut().handle_message(&Message::SetFlat(flat));
+    };
+    let closure = Closure::wrap(Box::new(handler) as Box<FnMut(_)>);
+
+    let flat_control = Checkbox {
+        start_checked: crate::FLAT_START,
+        label: "Flat (plot in the xy plane)",
+        closure,
+    }
+    .create_element()?;
+
+    Ok(flat_control)
+}
+
+fn create_coordinates_checkbox(app: AppWrapper) -> Result<HtmlElement, JsValue> {
+    let handler = move |event: web_sys::Event| {
+        let input_elem: HtmlInputElement = event.target().unwrap().dyn_into().unwrap();
+        let coordinates = input_elem.checked();
+
+        app.borrow_mut()
+            .handle_message(&Message::SetCoordinates(coordinates));
+        update_parameter_sliders(Rc::clone(&app)).expect("update_parameter_sliders");
+        update_interpreted_equation(Rc::clone(&app)).expect("update_interpreted_equation");
+    };
+    let closure = Closure::wrap(Box::new(handler) as Box<FnMut(_)>);
+
+    let coordinates_control = Checkbox {
+        start_checked: crate::COORDINATES_START,
+        label: "Coordinates (r, theta, phi and rho)",
+        closure,
+    }
+    .create_element()?;
+
+    Ok(coordinates_control)
+}
+
 fn create_fov_slider(app: AppWrapper) -> Result<HtmlElement, JsValue> {
     let handler = move |event: web_sys::Event| {
         let input_elem: HtmlInputElement = event.target().unwrap().dyn_into().unwrap();
@@ -365,16 +579,16 @@ fn create_fov_slider(app: AppWrapper) -> Result<HtmlElement, JsValue> {
     Ok(fov_control)
 }
 
-struct Slider {
+struct Slider<'a> {
     min: f32,
     max: f32,
     step: f32,
     start: f32,
-    label: &'static str,
+    label: &'a str,
     closure: Closure<FnMut(web_sys::Event)>,
 }
 
-impl Slider {
+impl<'a> Slider<'a> {
     fn create_element(self) -> Result<HtmlElement, JsValue> {
         let window = window().unwrap();
         let document = window.document().unwrap();
@@ -13,13 +13,21 @@ use crate::app::{App, AppWrapper, Message};
 use crate::render::WebRendererWrapper;
 
 pub static APP_DIV_ID: &'static str = "tacit-app";
-pub static EQUATION_START: &'static str = "x^2 + y^2 + z^2 - 100";
+pub static EQUATION_START: &'static str = "x^2 + y^2 + z^2 = 100";
 pub static DRAW_BB_START: bool = true;
 pub static DRAW_VERTICES_START: bool = true;
 pub static DRAW_EDGES_START: bool = true;
+pub static DRAW_INTERIOR_START: bool = true;
+pub static DRAW_SURFACE_START: bool = true;
 pub static DRAW_GNOMON_CENTER_START: bool = false;
 pub static DRAW_GNOMON_CORNER_START: bool = true;
+pub static FLAT_START: bool = false;
+pub static COORDINATES_START: bool = false;
 pub static FOV_START_VALUE: f32 = std::f32::consts::PI / 2.0;
+pub static PARAMETER_START_VALUE: f32 = 1.0;
+pub static PARAMETER_MIN: f32 = -10.0;
+pub static PARAMETER_MAX: f32 = 10.0;
+pub static PARAMETER_STEP: f32 = 0.1;
 
 #[wasm_bindgen]
 pub struct WebClient {
@@ -1,6 +1,8 @@
+use implicit_mesh::bytecode::CompiledFunction;
 use implicit_mesh::cell_keys::morton_keys::MortonKey;
-use implicit_mesh::function_ir::Node;
+use implicit_mesh::cell_keys::quad_keys::QuadKey;
 use implicit_mesh::mesh_tree::*;
+use implicit_mesh::quad_tree::QuadTree;
 use wasm_bindgen::JsValue;
 use web_sys::console::log_1;
 use web_sys::WebGlRenderingContext as GL;
@@ -27,6 +29,8 @@ pub struct WebRenderer {
     draw_vertices: bool,
     draw_edges: bool,
     draw_bb: bool,
+    draw_interior: bool,
+    draw_surface: bool,
     draw_gnomon_center: bool,
     draw_gnomon_corner: bool,
     gnomon: gnomon::Gnomon,
@@ -46,6 +50,8 @@ impl WebRenderer {
             draw_vertices: crate::DRAW_VERTICES_START,
             draw_edges: crate::DRAW_EDGES_START,
             draw_bb: crate::DRAW_BB_START,
+            draw_interior: crate::DRAW_INTERIOR_START,
+            draw_surface: crate::DRAW_SURFACE_START,
             draw_gnomon_center: crate::DRAW_GNOMON_CENTER_START,
             draw_gnomon_corner: crate::DRAW_GNOMON_CORNER_START,
             gnomon,
@@ -65,6 +71,14 @@ impl WebRenderer {
         self.draw_bb = draw_flag;
     }
 
+    pub fn set_draw_interior(&mut self, draw_flag: bool) {
+        self.draw_interior = draw_flag;
+    }
+
+    pub fn set_draw_surface(&mut self, draw_flag: bool) {
+        self.draw_surface = draw_flag;
+    }
+
     pub fn set_draw_gnomon_center(&mut self, draw_flag: bool) {
         self.draw_gnomon_center = draw_flag;
     }
@@ -73,13 +87,33 @@ impl WebRenderer {
         self.draw_gnomon_corner = draw_flag;
     }
 
-    pub fn set_plot(&mut self, mtree: &MeshTree<MortonKey, Node>) -> Result<(), JsValue> {
+    pub fn set_plot(
+        &mut self,
+        mtree: &MeshTree<MortonKey, CompiledFunction>,
+    ) -> Result<(), JsValue> {
         log_1(&"Set_plot in renderer".into());
         let plot_buffers = PlotBuffers::new(&self.gl_context, mtree)?;
         self.plot_buffers = Some(plot_buffers);
         Ok(())
     }
 
+    pub fn set_quad_tree(
+        &mut self,
+        qtree: &QuadTree<QuadKey, CompiledFunction>,
+    ) -> Result<(), JsValue> {
+        log_1(&"Set_quad_tree in renderer".into());
+        let plot_buffers = PlotBuffers::from_quad_tree(&self.gl_context, qtree)?;
+        self.plot_buffers = Some(plot_buffers);
+        Ok(())
+    }
+
+    pub fn set_geometry(&mut self, geometry: &Geometry) -> Result<(), JsValue> {
+        log_1(&"Set_geometry in renderer".into());
+        let plot_buffers = PlotBuffers::from_geometry(&self.gl_context, geometry)?;
+        self.plot_buffers = Some(plot_buffers);
+        Ok(())
+    }
+
     pub fn clear_plot(&mut self) {
         self.plot_buffers = None;
     }
@@ -108,6 +142,8 @@ impl WebRenderer {
                     self.draw_edges,
                     self.draw_bb,
                     self.draw_vertices,
+                    self.draw_interior,
+                    self.draw_surface,
                 );
             }
             None => (),
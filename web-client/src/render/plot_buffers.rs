@@ -2,9 +2,11 @@ use super::buffers::{ArrayBuffer, IndexBuffer};
 use super::color::*;
 use crate::shader::{ShaderKind, ShaderSystem};
 use camera::Camera;
+use implicit_mesh::bytecode::CompiledFunction;
 use implicit_mesh::cell_keys::morton_keys::MortonKey;
-use implicit_mesh::function_ir::Node;
+use implicit_mesh::cell_keys::quad_keys::QuadKey;
 use implicit_mesh::mesh_tree::*;
+use implicit_mesh::quad_tree::QuadTree;
 use wasm_bindgen::JsValue;
 use web_sys::WebGlRenderingContext;
 use web_sys::WebGlRenderingContext as GL;
@@ -21,30 +23,105 @@ pub struct PlotBuffers {
     bb_edge_count: i32,
     bb_vertices_buffer: ArrayBuffer,
     bb_indices_buffer: IndexBuffer,
+
+    interior_vertex_count: i32,
+    interior_vertices_buffer: ArrayBuffer,
+
+    surface_vertex_count: i32,
+    surface_vertices_buffer: ArrayBuffer,
 }
 
 impl PlotBuffers {
+    /// Curves are drawn as edges between the vertices of their cells. Surfaces are
+    /// drawn like `from_geometry`, with the triangles of `MeshTree::get_geometry`
+    /// as the surface, and cells still drawn as bounding boxes and the interior
     pub fn new(
         gl_context: &WebGlRenderingContext,
-        mtree: &MeshTree<MortonKey, Node>,
+        mtree: &MeshTree<MortonKey, CompiledFunction>,
+    ) -> Result<PlotBuffers, JsValue> {
+        if mtree.is_curve() {
+            return PlotBuffers::from_floats(
+                gl_context,
+                mtree.get_vertex_floats(),
+                mtree.get_edge_floats(),
+                mtree.get_bounding_box_floats(),
+                mtree.get_interior_floats(),
+                Vec::new(),
+            );
+        }
+
+        let geometry = mtree.get_geometry();
+        PlotBuffers::from_floats(
+            gl_context,
+            geometry.get_vertex_floats(),
+            geometry.get_edge_floats(),
+            mtree.get_bounding_box_floats(),
+            mtree.get_interior_floats(),
+            geometry.get_triangle_floats(),
+        )
+    }
+
+    /// Segments of the curve are drawn as edges, cells as bounding boxes,
+    /// and cells where the equation holds throughout as the interior
+    pub fn from_quad_tree(
+        gl_context: &WebGlRenderingContext,
+        qtree: &QuadTree<QuadKey, CompiledFunction>,
+    ) -> Result<PlotBuffers, JsValue> {
+        PlotBuffers::from_floats(
+            gl_context,
+            qtree.get_vertex_floats(),
+            qtree.get_edge_floats(),
+            qtree.get_bounding_box_floats(),
+            qtree.get_interior_floats(),
+            Vec::new(),
+        )
+    }
+
+    /// Triangles are drawn as the surface, there are no bounding boxes or interior
+    pub fn from_geometry(
+        gl_context: &WebGlRenderingContext,
+        geometry: &Geometry,
+    ) -> Result<PlotBuffers, JsValue> {
+        PlotBuffers::from_floats(
+            gl_context,
+            geometry.get_vertex_floats(),
+            geometry.get_edge_floats(),
+            Vec::new(),
+            Vec::new(),
+            geometry.get_triangle_floats(),
+        )
+    }
+
+    fn from_floats(
+        gl_context: &WebGlRenderingContext,
+        point_float_vec: Vec<f32>,
+        edge_float_vec: Vec<f32>,
+        bb_float_vec: Vec<f32>,
+        interior_float_vec: Vec<f32>,
+        surface_float_vec: Vec<f32>,
     ) -> Result<PlotBuffers, JsValue> {
-        let point_float_vec = mtree.get_vertex_floats();
         let point_count = point_float_vec.len() / 3;
         let point_vertices_buffer = ArrayBuffer::new(gl_context, point_float_vec)?;
         let point_indices_buffer = IndexBuffer::new(gl_context, (0..point_count as u16).collect())?;
 
-        let edge_float_vec = mtree.get_edge_floats();
         let edge_vertex_count = edge_float_vec.len() / 3;
         let edge_vertices_buffer = ArrayBuffer::new(gl_context, edge_float_vec)?;
         let edge_indices_buffer =
             IndexBuffer::new(gl_context, (0..edge_vertex_count as u16).collect())?;
 
-        let bb_float_vec = mtree.get_bounding_box_floats();
         let bb_vertex_count = bb_float_vec.len() / 3;
         let bb_vertices_buffer = ArrayBuffer::new(gl_context, bb_float_vec)?;
         let bb_indices_buffer =
             IndexBuffer::new(gl_context, (0..bb_vertex_count as u16).collect())?;
 
+        // Interior cells and surface triangles can easily exceed the u16 index
+        // limit, so they are drawn directly from the array buffer
+        let interior_vertex_count = interior_float_vec.len() / 3;
+        let interior_vertices_buffer = ArrayBuffer::new(gl_context, interior_float_vec)?;
+
+        let surface_vertex_count = surface_float_vec.len() / 3;
+        let surface_vertices_buffer = ArrayBuffer::new(gl_context, surface_float_vec)?;
+
         Ok(PlotBuffers {
             point_count: point_count as i32,
             point_vertices_buffer,
@@ -55,6 +132,10 @@ impl PlotBuffers {
             bb_edge_count: (bb_vertex_count / 2) as i32,
             bb_vertices_buffer,
             bb_indices_buffer,
+            interior_vertex_count: interior_vertex_count as i32,
+            interior_vertices_buffer,
+            surface_vertex_count: surface_vertex_count as i32,
+            surface_vertices_buffer,
         })
     }
 
@@ -66,6 +147,8 @@ impl PlotBuffers {
         draw_edges: bool,
         draw_bb: bool,
         draw_points: bool,
+        draw_interior: bool,
+        draw_surface: bool,
     ) {
         shader_sys.use_program(gl_context, ShaderKind::Simple);
 
@@ -88,6 +171,46 @@ impl PlotBuffers {
         let color_uniform = &shader_sys.simple_shader.color_uniform;
         let position_attribute = shader_sys.simple_shader.position_attribute;
 
+        if draw_interior && self.interior_vertex_count > 0 {
+            let mut interior_color = Color::from_floats(0.45, 0.36, 0.62, 1.0);
+            gl_context.uniform4fv_with_f32_array(Some(color_uniform), &mut interior_color);
+
+            gl_context.bind_buffer(
+                GL::ARRAY_BUFFER,
+                Some(&self.interior_vertices_buffer.gl_buffer),
+            );
+            gl_context.vertex_attrib_pointer_with_i32(
+                position_attribute,
+                3,
+                GL::FLOAT,
+                false,
+                0,
+                0,
+            );
+            gl_context.enable_vertex_attrib_array(position_attribute);
+            gl_context.draw_arrays(GL::TRIANGLES, 0, self.interior_vertex_count);
+        }
+
+        if draw_surface && self.surface_vertex_count > 0 {
+            let mut surface_color = Color::from_floats(0.93, 0.71, 0.35, 1.0);
+            gl_context.uniform4fv_with_f32_array(Some(color_uniform), &mut surface_color);
+
+            gl_context.bind_buffer(
+                GL::ARRAY_BUFFER,
+                Some(&self.surface_vertices_buffer.gl_buffer),
+            );
+            gl_context.vertex_attrib_pointer_with_i32(
+                position_attribute,
+                3,
+                GL::FLOAT,
+                false,
+                0,
+                0,
+            );
+            gl_context.enable_vertex_attrib_array(position_attribute);
+            gl_context.draw_arrays(GL::TRIANGLES, 0, self.surface_vertex_count);
+        }
+
         if draw_edges {
             let mut edge_color = Color::from_floats(0.2, 0.33, 0.84, 1.0);
             gl_context.uniform4fv_with_f32_array(Some(color_uniform), &mut edge_color);